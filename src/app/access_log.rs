@@ -0,0 +1,131 @@
+//! This module writes access logs, one JSON line per request, to a sink separate from the
+//! application logs.
+use std::io;
+use std::time::{Instant, SystemTime};
+use anyhow::Result;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Serialize;
+use tracing::log::warn;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::Rotation;
+use crate::app::json_lines::JsonLines;
+use crate::config::{AccessLogConfig, LogRotation};
+
+
+/// A line of the access log.
+#[derive(Debug, Serialize)]
+struct AccessLogLine<'a> {
+    /// The time the request was received, in milliseconds since the Unix epoch.
+    timestamp: u128,
+    method: &'a str,
+    uri: &'a str,
+    status: u16,
+    duration_ms: u128,
+}
+
+
+/// A shared sink receiving the access log lines.
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    lines: JsonLines,
+}
+
+
+impl AccessLog {
+    /// Opens the sink described by `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The access log configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `None` if access logs are disabled, or the access log together with
+    /// the guard that flushes it when dropped.
+    pub fn from_config(config: &AccessLogConfig) -> Result<Option<(Self, WorkerGuard)>> {
+        let (lines, guard) = match config {
+            AccessLogConfig::Disabled => return Ok(None),
+            AccessLogConfig::Stdout => JsonLines::non_blocking(io::stdout()),
+            AccessLogConfig::File { path, rotation } => {
+                let rotation = match rotation {
+                    LogRotation::Hourly => Rotation::HOURLY,
+                    LogRotation::Daily => Rotation::DAILY,
+                    LogRotation::Never => Rotation::NEVER,
+                };
+                JsonLines::file(path, rotation)?
+            },
+        };
+        Ok(Some((Self { lines }, guard)))
+    }
+
+    fn write(&self, line: &AccessLogLine) {
+        if let Err(err) = self.lines.write(line) {
+            warn!("Error writing access log: {}", err);
+        }
+    }
+}
+
+
+/// This middleware writes a line to the access log for every request, once its response is ready.
+pub async fn access_log(State(log): State<AccessLog>, req: Request, next: Next) -> Response {
+    let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis();
+    let start = Instant::now();
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+
+    let response = next.run(req).await;
+
+    log.write(&AccessLogLine {
+        timestamp,
+        method: method.as_str(),
+        uri: &uri.to_string(),
+        status: response.status().as_u16(),
+        duration_ms: start.elapsed().as_millis(),
+    });
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_access_log_file() {
+        let directory = std::env::temp_dir().join(format!("access-log-test-{}", std::process::id()));
+        let path = directory.join("access.log");
+        let config = AccessLogConfig::File { path: path.clone(), rotation: LogRotation::Never };
+        let (log, guard) = AccessLog::from_config(&config).unwrap().unwrap();
+
+        let app = Router::new()
+            .route("/{url_key}", get(|| async { StatusCode::NOT_FOUND }))
+            .layer(from_fn_with_state(log, access_log));
+
+        let req = Request::builder().uri("/12345678?q=1").body(Body::empty()).unwrap();
+        app.oneshot(req).await.unwrap();
+        // Dropping the guard flushes the pending lines.
+        drop(guard);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let lines: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["method"], "GET");
+        assert_eq!(lines[0]["uri"], "/12345678?q=1");
+        assert_eq!(lines[0]["status"], 404);
+    }
+
+    #[test]
+    fn test_access_log_disabled() {
+        assert!(AccessLog::from_config(&AccessLogConfig::Disabled).unwrap().is_none());
+    }
+}
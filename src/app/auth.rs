@@ -0,0 +1,111 @@
+//! This module authenticates create requests with API keys.
+use std::sync::Arc;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use subtle::ConstantTimeEq;
+use tracing::log::debug;
+use crate::app::errors::{ApiError, ErrorCode};
+use crate::config::AuthConfig;
+
+
+/// This middleware only serves requests carrying one of the configured API keys in the
+/// configured header, and rejects the others with `401 Unauthorized`.
+/// If no API key is configured, every request is served.
+pub async fn require_api_key(State(config): State<Arc<AuthConfig>>, req: Request, next: Next) -> Response {
+    if config.api_keys.is_empty() {
+        return next.run(req).await;
+    }
+
+    let authorized = req.headers()
+        .get(config.header.as_str())
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|key| {
+            // Every configured key is compared, so the time taken does not reveal which one matched.
+            config.api_keys.iter().fold(false, |found, api_key| found | secret_eq(key, api_key))
+        });
+
+    if !authorized {
+        debug!("Rejecting request to {} without a valid API key", req.uri().path());
+        return ApiError::new(StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized, "Invalid or missing API key").into_response();
+    }
+    next.run(req).await
+}
+
+
+/// Compares a provided secret with an expected one in constant time, so the time taken does not
+/// reveal how long a prefix of the secret was guessed.
+pub(crate) fn secret_eq(provided: &str, expected: &str) -> bool {
+    provided.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::{get, post};
+    use tower::ServiceExt;
+
+    fn app(api_keys: &[&str]) -> Router {
+        let config = AuthConfig {
+            header: "x-api-key".to_string(),
+            api_keys: api_keys.iter().map(|key| key.to_string()).collect::<BTreeSet<_>>(),
+        };
+        Router::new()
+            .route("/api/v1/create", post(|| async { StatusCode::CREATED }))
+            .route_layer(from_fn_with_state(Arc::new(config), require_api_key))
+            .route("/{url_key}", get(|| async { StatusCode::OK }))
+    }
+
+    fn create_request(api_key: Option<&str>) -> Request {
+        let req = Request::builder().method("POST").uri("/api/v1/create");
+        match api_key {
+            Some(key) => req.header("X-API-Key", key),
+            None => req,
+        }.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_secret_eq() {
+        assert!(secret_eq("key-1", "key-1"));
+        assert!(!secret_eq("key-1", "key-2"));
+        assert!(!secret_eq("key-1", "key-10"));
+        assert!(!secret_eq("", "key-1"));
+    }
+
+    #[tokio::test]
+    async fn test_valid_api_key() {
+        let resp = app(&["key-1", "key-2"]).oneshot(create_request(Some("key-2"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_missing_api_key() {
+        let resp = app(&["key-1"]).oneshot(create_request(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_api_key() {
+        let resp = app(&["key-1"]).oneshot(create_request(Some("key-3"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_not_authenticated() {
+        let req = Request::builder().uri("/12345678").body(Body::empty()).unwrap();
+        let resp = app(&["key-1"]).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_no_api_keys_configured() {
+        let resp = app(&[]).oneshot(create_request(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+}
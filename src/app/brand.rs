@@ -0,0 +1,125 @@
+//! This module verifies the signed brand tokens that select the base of created short URLs.
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use crate::config::normalize_base_url;
+
+/// The header carrying the brand token of a create request.
+pub const BRAND_TOKEN_HEADER: &str = "x-brand-token";
+
+type HmacSha256 = Hmac<Sha256>;
+
+
+/// The claims of a brand token.
+#[derive(Debug, Deserialize)]
+struct BrandClaims {
+    /// The base the short URLs are built on, e.g. `https://brand.example/`.
+    base_url: String,
+    /// When the token expires, in seconds since the Unix epoch.
+    exp: u64,
+}
+
+
+/// Verifies a brand token and returns the base of the short URLs it selects, ending with a `/`.
+///
+/// A token is `<claims>.<signature>`: the JSON claims and their HMAC-SHA256 signature with the
+/// shared secret, both base64url-encoded without padding.
+///
+/// # Arguments
+///
+/// * `token` - The brand token of the request.
+/// * `secret` - The secret the token must be signed with.
+/// * `now` - The current time, to check the token has not expired.
+///
+/// # Returns
+///
+/// A `Result` containing the base of the short URLs, or the reason the token is invalid.
+pub fn verify_brand_token(token: &str, secret: &[u8], now: SystemTime) -> Result<String, String> {
+    let (claims, signature) = token
+        .split_once('.')
+        .ok_or_else(|| "Malformed brand token, expected <claims>.<signature>".to_string())?;
+    let signature = URL_SAFE_NO_PAD.decode(signature)
+        .map_err(|err| format!("Malformed brand token signature: {err}"))?;
+    let mut mac = HmacSha256::new_from_slice(secret)
+        .map_err(|err| format!("Invalid brand token secret: {err}"))?;
+    mac.update(claims.as_bytes());
+    mac.verify_slice(&signature).map_err(|_| "Invalid brand token signature".to_string())?;
+
+    let claims = URL_SAFE_NO_PAD.decode(claims)
+        .map_err(|err| format!("Malformed brand token claims: {err}"))?;
+    let claims: BrandClaims = serde_json::from_slice(&claims)
+        .map_err(|err| format!("Malformed brand token claims: {err}"))?;
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if claims.exp <= now {
+        return Err("Expired brand token".to_string());
+    }
+
+    normalize_base_url(&claims.base_url).map_err(|err| format!("Invalid brand token base URL {err}"))
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Signs a brand token for the given base URL and expiry.
+    pub(crate) fn sign(base_url: &str, exp: u64, secret: &[u8]) -> String {
+        let claims = URL_SAFE_NO_PAD.encode(serde_json::json!({ "base_url": base_url, "exp": exp }).to_string());
+        let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+        mac.update(claims.as_bytes());
+        format!("{claims}.{}", URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_valid_token() {
+        let token = sign("https://brand.example", 2000, b"secret");
+        assert_eq!(verify_brand_token(&token, b"secret", at(1000)), Ok("https://brand.example/".to_string()));
+
+        let token = sign("https://brand.example/go", 2000, b"secret");
+        assert_eq!(verify_brand_token(&token, b"secret", at(1000)), Ok("https://brand.example/go/".to_string()));
+
+        // Base URLs end with exactly one `/`, like the public base URL.
+        let token = sign("https://brand.example/go//", 2000, b"secret");
+        assert_eq!(verify_brand_token(&token, b"secret", at(1000)), Ok("https://brand.example/go/".to_string()));
+    }
+
+    #[test]
+    fn test_wrong_secret() {
+        let token = sign("https://brand.example", 2000, b"other");
+        assert_eq!(verify_brand_token(&token, b"secret", at(1000)), Err("Invalid brand token signature".to_string()));
+    }
+
+    #[test]
+    fn test_tampered_claims() {
+        let token = sign("https://brand.example", 2000, b"secret");
+        let (_, signature) = token.split_once('.').unwrap();
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"base_url": "https://evil.example", "exp": 2000}"#);
+        assert_eq!(verify_brand_token(&format!("{claims}.{signature}"), b"secret", at(1000)), Err("Invalid brand token signature".to_string()));
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let token = sign("https://brand.example", 2000, b"secret");
+        assert_eq!(verify_brand_token(&token, b"secret", at(2000)), Err("Expired brand token".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        for token in [
+            "no-signature".to_string(),
+            "claims.!!!".to_string(),
+            sign("javascript:alert(1)", 2000, b"secret"),
+            sign("https://brand.example/?q=1", 2000, b"secret"),
+        ] {
+            assert!(verify_brand_token(&token, b"secret", at(1000)).is_err(), "{token}");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! This module marks deprecated routes with `Deprecation` and `Sunset` response headers.
+use std::time::{Duration, SystemTime};
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use crate::app::AppState;
+
+
+/// This middleware adds the `Deprecation` header (RFC 9745) and, if a sunset is configured, the
+/// `Sunset` header (RFC 8594) to the responses of routes under a deprecated prefix.
+pub async fn deprecation_headers(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let route = state.config.deprecated_routes
+        .iter()
+        .find(|route| req.uri().path().starts_with(&route.prefix))
+        .cloned();
+
+    let mut response = next.run(req).await;
+
+    if let Some(route) = route {
+        let headers = response.headers_mut();
+        if let Ok(deprecation) = HeaderValue::from_str(&format!("@{}", route.deprecated_at)) {
+            headers.insert("deprecation", deprecation);
+        }
+        if let Some(sunset) = route.sunset {
+            let date = httpdate::fmt_http_date(SystemTime::UNIX_EPOCH + Duration::from_secs(sunset));
+            if let Ok(sunset) = HeaderValue::from_str(&date) {
+                headers.insert("sunset", sunset);
+            }
+        }
+    }
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tower::ServiceExt;
+    use crate::config::{DeprecatedRoute, HandlerConfig};
+    use crate::database::MockDatabase;
+    use crate::key_generator::MockKeyGenerationService;
+    use crate::task_sender::MockTaskSender;
+
+    async fn app() -> Router {
+        let config = HandlerConfig {
+            deprecated_routes: DeprecatedRoute::parse_list("/api/v1/=1767225600:1798761600").unwrap(),
+            ..HandlerConfig::default()
+        };
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        Router::new()
+            .route("/api/v1/healthy", get(|| async { StatusCode::OK }))
+            .route("/api/v2/healthy", get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(state.clone(), deprecation_headers))
+            .with_state(state)
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_route_headers() {
+        let req = Request::builder().uri("/api/v1/healthy").body(Body::empty()).unwrap();
+        let resp = app().await.oneshot(req).await.unwrap();
+        assert_eq!(resp.headers()["deprecation"], "@1767225600");
+        assert_eq!(resp.headers()["sunset"], "Fri, 01 Jan 2027 00:00:00 GMT");
+    }
+
+    #[tokio::test]
+    async fn test_other_route_no_headers() {
+        let req = Request::builder().uri("/api/v2/healthy").body(Body::empty()).unwrap();
+        let resp = app().await.oneshot(req).await.unwrap();
+        assert!(!resp.headers().contains_key("deprecation"));
+        assert!(!resp.headers().contains_key("sunset"));
+    }
+}
@@ -0,0 +1,169 @@
+//! This module serves custom error pages to browsers, and JSON errors to API clients.
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::Result;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::log::{debug, warn};
+use crate::app::errors::{ErrorBody, ErrorCode, ERROR_CODE_HEADER};
+use crate::app::html;
+use crate::app::responses::{HtmlBody, JsonBody};
+
+
+/// The maximum size of an error body that is rewritten.
+const MAX_ERROR_BODY_SIZE: usize = 64 * 1024; // 64KB
+
+
+/// The error page templates, keyed by status code.
+///
+/// A template may contain `{{status}}` and `{{message}}` placeholders, replaced by the status
+/// code and the HTML-escaped error message.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    pages: Arc<HashMap<u16, String>>,
+}
+
+
+impl ErrorPages {
+    /// Creates a new `ErrorPages` from templates keyed by status code.
+    pub fn new(pages: HashMap<u16, String>) -> Self {
+        Self { pages: Arc::new(pages) }
+    }
+
+    /// Loads the templates of a directory, named after their status code, e.g. `404.html`.
+    /// Other files are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory containing the templates.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the loaded templates or an error reading the directory.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut pages = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "html") {
+                continue;
+            }
+            let Some(status) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u16>().ok()) else {
+                continue;
+            };
+            debug!("Loading error page for status {}", status);
+            pages.insert(status, std::fs::read_to_string(&path)?);
+        }
+        Ok(Self::new(pages))
+    }
+
+    fn render(&self, status: StatusCode, message: &str) -> Option<String> {
+        let page = self.pages.get(&status.as_u16())?;
+        Some(page
+            .replace("{{status}}", status.as_str())
+            .replace("{{message}}", &html::escape(message)))
+    }
+}
+
+
+/// Returns `true` if the request accepts an HTML response.
+fn accepts_html(req: &Request) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+
+/// This middleware rewrites error responses: clients accepting HTML get the error page of the
+/// status code if one is configured, and any other client gets the JSON body of handler errors,
+/// with the error code and the error message.
+pub async fn error_pages(State(pages): State<ErrorPages>, req: Request, next: Next) -> Response {
+    let html = accepts_html(&req);
+    let response = next.run(req).await;
+
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // Responses without a code, e.g. those of the router, get the generic code of their status.
+    let code = parts.headers.get(ERROR_CODE_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| ErrorCode::from_status(status).as_str().to_string());
+    // Handler errors carry their message in a JSON body, other errors in a plain text one.
+    let message = match axum::body::to_bytes(body, MAX_ERROR_BODY_SIZE).await {
+        Ok(bytes) => match serde_json::from_slice::<ErrorBody>(&bytes) {
+            Ok(body) => body.error,
+            Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+        },
+        Err(err) => {
+            warn!("Error reading error response body: {}", err);
+            String::new()
+        },
+    };
+    // The rewritten body has a different type and length.
+    parts.headers.remove(header::CONTENT_TYPE);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let body = match pages.render(status, &message) {
+        Some(page) if html => HtmlBody(page).into_response(),
+        _ => JsonBody(ErrorBody { code, error: message }).into_response(),
+    };
+    (parts, body).into_response()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::errors::ApiError;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let pages = ErrorPages::new(HashMap::from([(404, "<h1>{{status}}: {{message}}</h1>".to_string())]));
+        Router::new()
+            .route("/{url_key}", get(|| async { ApiError::new(StatusCode::NOT_FOUND, ErrorCode::KeyNotFound, "<key>") }))
+            .layer(from_fn_with_state(pages, error_pages))
+    }
+
+    async fn get_not_found(accept: &str) -> (Option<String>, String) {
+        let req = Request::builder().uri("/12345678").header(header::ACCEPT, accept).body(Body::empty()).unwrap();
+        let resp = app().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let content_type = resp.headers().get(header::CONTENT_TYPE).map(|h| h.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_not_found_html() {
+        let (content_type, body) = get_not_found("text/html,application/xhtml+xml,*/*;q=0.8").await;
+        assert_eq!(content_type.unwrap(), "text/html; charset=utf-8");
+        assert_eq!(body, "<h1>404: &lt;key&gt;</h1>");
+    }
+
+    #[tokio::test]
+    async fn test_not_found_json() {
+        let (content_type, body) = get_not_found("application/json").await;
+        assert_eq!(content_type.unwrap(), "application/json; charset=utf-8");
+        assert_eq!(body, r#"{"code":"KEY_NOT_FOUND","error":"<key>"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_route_json() {
+        let req = Request::builder().uri("/a/b").header(header::ACCEPT, "application/json").body(Body::empty()).unwrap();
+        let resp = app().oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        assert_eq!(body, r#"{"code":"NOT_FOUND","error":""}"#);
+    }
+}
@@ -0,0 +1,266 @@
+//! This module contains the errors returned by the handlers, with stable machine-readable codes
+//! clients can switch on instead of parsing the messages.
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, IntoResponseParts, Response, ResponseParts};
+use serde::{Deserialize, Serialize};
+use crate::app::responses::JsonBody;
+use crate::database::DatabaseError;
+use crate::key_generator::error::GeneratorError;
+
+
+/// The header carrying the code of an error response.
+pub const ERROR_CODE_HEADER: HeaderName = HeaderName::from_static("x-error-code");
+
+
+/// The stable code of an error response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The request is malformed, e.g. its body is not a valid create request.
+    InvalidRequest,
+    /// The target URL of a create request is invalid or not allowed.
+    InvalidUrl,
+    /// The alias of a create request does not follow the key format.
+    InvalidAlias,
+    /// The brand token of a create request is invalid or expired.
+    InvalidBrandToken,
+    /// The short URL would be longer than the configured maximum.
+    ShortUrlTooLong,
+    /// The request lacks valid credentials.
+    Unauthorized,
+    /// The user agent of a create request is denied.
+    ForbiddenUserAgent,
+    /// The key does not exist.
+    KeyNotFound,
+    /// The requested route or feature is not available.
+    NotFound,
+    /// The alias of a create request is already taken.
+    KeyTaken,
+    /// The key has used up its allowed visits.
+    KeyExhausted,
+    /// The request was sent to a host the service does not answer on.
+    UnknownHost,
+    /// The key or its destination is blocked for legal reasons.
+    Blocked,
+    /// Too many requests are in flight.
+    RateLimited,
+    /// The service is starting up or shutting down, or a dependency is unreachable.
+    NotReady,
+    /// The database is unreachable.
+    DbUnavailable,
+    /// The key generator is unreachable.
+    KeygenUnavailable,
+    /// No free key could be generated.
+    KeyspaceExhausted,
+    /// The key generator failed.
+    KeygenError,
+    /// The operation is not supported by the configured database.
+    NotImplemented,
+    /// An unexpected error occurred.
+    InternalError,
+}
+
+
+impl ErrorCode {
+    /// Returns the string clients receive, e.g. `KEY_NOT_FOUND`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::InvalidUrl => "INVALID_URL",
+            ErrorCode::InvalidAlias => "INVALID_ALIAS",
+            ErrorCode::InvalidBrandToken => "INVALID_BRAND_TOKEN",
+            ErrorCode::ShortUrlTooLong => "SHORT_URL_TOO_LONG",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::ForbiddenUserAgent => "FORBIDDEN_USER_AGENT",
+            ErrorCode::KeyNotFound => "KEY_NOT_FOUND",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::KeyTaken => "KEY_TAKEN",
+            ErrorCode::KeyExhausted => "KEY_EXHAUSTED",
+            ErrorCode::UnknownHost => "UNKNOWN_HOST",
+            ErrorCode::Blocked => "BLOCKED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::NotReady => "NOT_READY",
+            ErrorCode::DbUnavailable => "DB_UNAVAILABLE",
+            ErrorCode::KeygenUnavailable => "KEYGEN_UNAVAILABLE",
+            ErrorCode::KeyspaceExhausted => "KEYSPACE_EXHAUSTED",
+            ErrorCode::KeygenError => "KEYGEN_ERROR",
+            ErrorCode::NotImplemented => "NOT_IMPLEMENTED",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    /// Returns the code of an error response that carries none, e.g. one returned by the router.
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            StatusCode::NOT_IMPLEMENTED => ErrorCode::NotImplemented,
+            status if status.is_client_error() => ErrorCode::InvalidRequest,
+            _ => ErrorCode::InternalError,
+        }
+    }
+}
+
+
+/// Sets the `X-Error-Code` header of a response.
+impl IntoResponseParts for ErrorCode {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.headers_mut().insert(ERROR_CODE_HEADER, HeaderValue::from_static(self.as_str()));
+        Ok(res)
+    }
+}
+
+
+impl From<&DatabaseError> for ErrorCode {
+    fn from(err: &DatabaseError) -> Self {
+        match err {
+            DatabaseError::NotExist(_) => ErrorCode::KeyNotFound,
+            DatabaseError::AlreadyExists(_) => ErrorCode::KeyTaken,
+            DatabaseError::Exhausted(_) => ErrorCode::KeyExhausted,
+            DatabaseError::CorruptData(_) => ErrorCode::InternalError,
+            DatabaseError::Unimplemented => ErrorCode::NotImplemented,
+            DatabaseError::UnavailableError(_) => ErrorCode::DbUnavailable,
+            DatabaseError::UnknownError(_) => ErrorCode::InternalError,
+        }
+    }
+}
+
+
+impl From<&GeneratorError> for ErrorCode {
+    fn from(err: &GeneratorError) -> Self {
+        match err {
+            GeneratorError::ConnectionError => ErrorCode::KeygenUnavailable,
+            GeneratorError::KeyspaceExhausted => ErrorCode::KeyspaceExhausted,
+            GeneratorError::GeneratorNotFound
+            | GeneratorError::NotPermission
+            | GeneratorError::BadRequest
+            | GeneratorError::UnknownError(_) => ErrorCode::KeygenError,
+        }
+    }
+}
+
+
+/// The JSON body of an error response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorBody {
+    /// The code of the error, e.g. `KEY_NOT_FOUND`.
+    pub code: String,
+    /// The message of the error.
+    pub error: String,
+}
+
+
+/// An error returned by a handler: a status, the code of the error and a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    /// The status of the response.
+    pub status: StatusCode,
+    /// The code of the error, sent in the `X-Error-Code` header.
+    pub code: ErrorCode,
+    /// The message of the error, sent in the JSON body with the code.
+    pub message: String,
+}
+
+
+impl ApiError {
+    /// Creates a new `ApiError`.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status of the response.
+    /// * `code` - The code of the error.
+    /// * `message` - The message of the error.
+    ///
+    /// # Returns
+    ///
+    /// A new `ApiError` instance.
+    pub fn new(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into() }
+    }
+}
+
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody { code: self.code.as_str().to_string(), error: self.message };
+        (self.status, self.code, JsonBody(body)).into_response()
+    }
+}
+
+
+/// Keeps the status and message of the conversion to `(StatusCode, String)`.
+impl From<DatabaseError> for ApiError {
+    fn from(err: DatabaseError) -> Self {
+        let code = ErrorCode::from(&err);
+        let (status, message) = err.into();
+        Self { status, code, message }
+    }
+}
+
+
+/// Keeps the status and message of the conversion to `(StatusCode, String)`.
+impl From<GeneratorError> for ApiError {
+    fn from(err: GeneratorError) -> Self {
+        let code = ErrorCode::from(&err);
+        let (status, message) = err.into();
+        Self { status, code, message }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_error_codes() {
+        for (err, status, code) in [
+            (DatabaseError::NotExist("k".to_string()), StatusCode::NOT_FOUND, "KEY_NOT_FOUND"),
+            (DatabaseError::AlreadyExists("k".to_string()), StatusCode::CONFLICT, "KEY_TAKEN"),
+            (DatabaseError::Exhausted("k".to_string()), StatusCode::GONE, "KEY_EXHAUSTED"),
+            (DatabaseError::CorruptData("k".to_string()), StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+            (DatabaseError::Unimplemented, StatusCode::NOT_IMPLEMENTED, "NOT_IMPLEMENTED"),
+            (DatabaseError::UnavailableError("down".to_string()), StatusCode::SERVICE_UNAVAILABLE, "DB_UNAVAILABLE"),
+            (DatabaseError::UnknownError("oops".to_string()), StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR"),
+        ] {
+            let err = ApiError::from(err);
+            assert_eq!((err.status, err.code.as_str()), (status, code));
+        }
+    }
+
+    #[test]
+    fn test_generator_error_codes() {
+        for (err, status, code) in [
+            (GeneratorError::ConnectionError, StatusCode::SERVICE_UNAVAILABLE, "KEYGEN_UNAVAILABLE"),
+            (GeneratorError::KeyspaceExhausted, StatusCode::SERVICE_UNAVAILABLE, "KEYSPACE_EXHAUSTED"),
+            (GeneratorError::GeneratorNotFound, StatusCode::NOT_FOUND, "KEYGEN_ERROR"),
+            (GeneratorError::NotPermission, StatusCode::FORBIDDEN, "KEYGEN_ERROR"),
+            (GeneratorError::BadRequest, StatusCode::BAD_REQUEST, "KEYGEN_ERROR"),
+            (GeneratorError::UnknownError("oops".to_string()), StatusCode::INTERNAL_SERVER_ERROR, "KEYGEN_ERROR"),
+        ] {
+            let err = ApiError::from(err);
+            assert_eq!((err.status, err.code.as_str()), (status, code));
+        }
+    }
+
+    #[test]
+    fn test_codes_from_status() {
+        assert_eq!(ErrorCode::from_status(StatusCode::METHOD_NOT_ALLOWED).as_str(), "INVALID_REQUEST");
+        assert_eq!(ErrorCode::from_status(StatusCode::TOO_MANY_REQUESTS).as_str(), "RATE_LIMITED");
+        assert_eq!(ErrorCode::from_status(StatusCode::BAD_GATEWAY).as_str(), "INTERNAL_ERROR");
+    }
+
+    #[tokio::test]
+    async fn test_api_error_response() {
+        let resp = ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidUrl, "Invalid URL").into_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "INVALID_URL");
+        assert_eq!(resp.headers()[axum::http::header::CONTENT_TYPE], crate::app::responses::JSON_CONTENT_TYPE);
+
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, ErrorBody { code: "INVALID_URL".to_string(), error: "Invalid URL".to_string() });
+    }
+}
@@ -0,0 +1,64 @@
+//! This module contains the custom extractors used by the application handlers.
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::http::{Method, StatusCode};
+use tracing::log::debug;
+use url::form_urlencoded;
+
+use crate::app::AppState;
+use crate::app::errors::{ApiError, ErrorCode};
+
+
+/// A shortened URL key extracted from the request path.
+///
+/// Keys that do not follow the key spec can never have been created, so they are
+/// rejected with a `404` before reaching the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedKey(pub String);
+
+
+impl FromRequestParts<AppState> for ValidatedKey {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Path(key) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, err.body_text()))?;
+
+        if let Err(err) = state.key_spec.validate(&key) {
+            debug!("Rejecting key: {}", err);
+            return Err(ApiError::new(StatusCode::NOT_FOUND, ErrorCode::KeyNotFound, "Key not found"));
+        }
+
+        Ok(ValidatedKey(key))
+    }
+}
+
+
+/// The header a client sets to visit a key without the visit being recorded.
+pub const NO_TRACK_HEADER: &str = "x-no-track";
+
+
+/// Whether a visit should be recorded.
+///
+/// Visits are not recorded for `HEAD` requests, requests with an `X-No-Track` header and
+/// requests with a `track=false` query parameter, e.g. health probes and monitoring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackVisit(pub bool);
+
+
+impl<S> FromRequestParts<S> for TrackVisit
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let head = parts.method == Method::HEAD;
+        let header = parts.headers.contains_key(NO_TRACK_HEADER);
+        let query = parts.uri
+            .query()
+            .is_some_and(|q| form_urlencoded::parse(q.as_bytes()).any(|(k, v)| k == "track" && v == "false"));
+        Ok(TrackVisit(!(head || header || query)))
+    }
+}
@@ -1,73 +1,538 @@
 //! This module contains the handlers for the application routes.
 use axum::body::Bytes;
-use axum::extract::{Path, State, Request};
-use axum::http::{header, StatusCode};
-use axum::response::{IntoResponse, Redirect};
-use serde::Deserialize;
+use axum::extract::{State, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::response::sse::{KeepAlive, Sse};
+use serde::{Deserialize, Serialize};
+use serde::de::{DeserializeOwned, DeserializeSeed, Deserializer, Error as _, IgnoredAny, SeqAccess, Visitor};
+use url::{form_urlencoded, Url};
 
-use tracing::instrument;
+use metrics::counter;
+use futures::StreamExt;
+use tracing::{instrument, Instrument};
 
-use std::time::SystemTime;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
 
 use crate::app::AppState;
+use crate::app::auth::secret_eq;
+use crate::app::brand::{verify_brand_token, BRAND_TOKEN_HEADER};
+use crate::app::errors::{ApiError, ErrorCode};
+use crate::app::extractors::{TrackVisit, ValidatedKey};
+use crate::app::html;
+use crate::app::responses::{HtmlBody, JsonBody};
+use crate::app::prometheus::{record_database_error, CREATE_URL_REQUESTS, GET_URL_REDIRECTS};
+use crate::app::spans::handler_span;
+use crate::app::target::{normalize_target, validate_target};
+use crate::app::visits::{visit_stream, VisitEvent};
+use crate::config::AnalyticsMode;
+use crate::database::{DatabaseError, RedirectTarget};
+use crate::key_generator::error::GeneratorError;
+use crate::task_sender::visit_task;
 
-use rust_proto_pkg;
+use tracing::log::{debug, error, warn};
 
-use tracing::log::{error, warn};
+/// The header a client sets to `key` to receive only the bare key from the create endpoint.
+const RESPONSE_FORMAT_HEADER: &str = "x-response";
 
-/// The maximum size of the payload for the create_url endpoint.
-const MAX_PAYLOAD_SIZE: usize = 5 * 1024; // 5KB
+/// How many items of a bulk create request are stored at once.
+const BULK_CREATE_CONCURRENCY: usize = 16;
 
 /// The route for health check.
 pub const HEALTHY_URL: &str = "/api/v1/healthy";
 
+/// The route for readiness check.
+pub const READY_URL: &str = "/readyz";
+
+/// The route for the liveness probe.
+pub const ROUTE_HEALTH: &str = "/health";
+
+/// The route for the readiness probe that checks the dependencies.
+pub const ROUTE_READY: &str = "/ready";
+
 /// The route for creating a new URL.
 pub const ROUTE_CREATE_URL: &str = "/api/v1/create";
 
+/// The route for creating many URLs at once.
+pub const ROUTE_CREATE_URL_BULK: &str = "/api/v1/create/bulk";
+
 /// The route for getting a URL.
 pub const ROUTE_GET_URL: &str = "/{url_key}";
 
+/// The route for the live visit stream.
+pub const ROUTE_VISIT_STREAM: &str = "/api/v1/stream/visits";
+
+/// The route for resolving a URL without redirecting.
+pub const ROUTE_DEBUG_RESOLVE: &str = "/api/v1/debug/resolve/{url_key}";
+
+/// The route for previewing a URL without redirecting.
+pub const ROUTE_PREVIEW: &str = "/api/v1/preview/{url_key}";
+
+/// The route for the visit statistics of a URL.
+pub const ROUTE_STATS: &str = "/api/v1/stats/{url_key}";
+
 
 /// This handler creates a new shortened URL.
-/// It takes a JSON payload with a "url" field and returns a shortened URL.
-#[instrument(level = "info", target = "create_url", skip(state))]
+/// It takes a JSON payload with a "url" field and returns a `CreateURLResponse` as JSON, or the
+/// bare shortened URL as plain text if the `Accept` header asks for `text/plain`.
+/// With `?format=key` or an `X-Response: key` header, it returns only the bare key.
+/// A taken alias returns `409 Conflict`, or `412 Precondition Failed` with `If-None-Match: *`.
+/// An optional `ttl_seconds` sets how long the key is kept, up to the configured maximum.
+/// Requests from denied user agents return `403 Forbidden`.
+/// A short URL over the configured maximum length returns `400 Bad Request` for an alias, or
+/// `500 Internal Server Error` for a generated key, and no key is stored.
+/// Every key created is recorded in the key audit log, if enabled.
+/// A signed `X-Brand-Token` selects the base of the short URL instead of the request host, and
+/// an invalid or expired one returns `400 Bad Request`.
+/// Its span is created at the level configured for `create_url`.
 pub async fn create_url(
     State(state): State<AppState>,
     req: Request<axum::body::Body>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
+    counter!(CREATE_URL_REQUESTS).increment(1);
+    let span = handler_span!(state.config.trace_levels.create_url, "create_url", uri = %req.uri());
+    create_short_url(state, req).instrument(span).await
+}
+
+
+/// This handler creates a shortened URL for each item of a JSON array of create requests.
+/// It returns `200 OK` with a JSON array of the result of each item, in order: the created
+/// `CreateURLResponse`, or the `status`, `code` and `error` the item failed with, so a failed item
+/// does not abort the others.
+/// Arrays longer than the configured maximum return `400 Bad Request`, and requests from
+/// denied user agents return `403 Forbidden`.
+/// Each item is counted as a create request.
+/// Its span is created at the level configured for `create_url`.
+pub async fn create_url_bulk(
+    State(state): State<AppState>,
+    req: Request<axum::body::Body>,
+) -> Result<impl IntoResponse, ApiError> {
+    let span = handler_span!(state.config.trace_levels.create_url, "create_url_bulk", uri = %req.uri());
+    create_short_urls(state, req).instrument(span).await
+}
+
+
+/// Returns `true` if the client asked for the bare key, through `?format=key` or an
+/// `X-Response: key` header.
+fn wants_key_only(parts: &Parts) -> bool {
+    let header = parts.headers
+        .get(RESPONSE_FORMAT_HEADER)
+        .is_some_and(|h| h.as_bytes().eq_ignore_ascii_case(b"key"));
+    let query = parts.uri
+        .query()
+        .is_some_and(|q| form_urlencoded::parse(q.as_bytes()).any(|(k, v)| k == "format" && v == "key"));
+    header || query
+}
+
+
+/// Checks that the short URL of `key` is within the configured maximum length, before the key
+/// is stored, so no key is created whose short URL is unusable.
+fn check_short_url_length(state: &AppState, short_url_prefix: &str, key: &str) -> Result<(), String> {
+    let length = short_url_prefix.len() + key.len();
+    let max_length = state.limits.max_short_url_length;
+    if length > max_length {
+        return Err(format!("The short URL {short_url_prefix}{key} would be {length} characters long, over the maximum of {max_length}"));
+    }
+    Ok(())
+}
+
+
+/// Generates a key for a create request, and checks it is valid and fits in a short URL.
+async fn generate_valid_key(state: &AppState, short_url_prefix: &str) -> Result<String, ApiError> {
+    let key = state.key_generator.generate_key().await?;
+    state.key_spec.validate(&key).map_err(|err| {
+        let msg = format!("Generated key {key} is invalid: {err}");
+        error!("{}", msg);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError, msg)
+    })?;
+    // The host and the generated key are set by the deployment, so this is a misconfiguration.
+    check_short_url_length(state, short_url_prefix, &key).map_err(|msg| {
+        error!("{}", msg);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::ShortUrlTooLong, msg)
+    })?;
+    Ok(key)
+}
+
+
+/// Deserializes a create request body.
+/// In lenient mode, a body that fails to deserialize falls back to the first valid object it
+/// contains, e.g. a `{"url": ...}` object followed by noise. The error of the whole body is
+/// returned if there is none.
+fn parse_body<T: DeserializeOwned>(bytes: &[u8], lenient: bool) -> serde_json::Result<T> {
+    let err = match serde_json::from_slice(bytes) {
+        Ok(payload) => return Ok(payload),
+        Err(err) if lenient => err,
+        Err(err) => return Err(err),
+    };
+
+    let payload = bytes.iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'{')
+        .find_map(|(start, _)| serde_json::Deserializer::from_slice(&bytes[start..]).into_iter::<T>().next()?.ok());
+    match payload {
+        Some(payload) => {
+            debug!("Extracted a create request from a malformed body: {}", err);
+            Ok(payload)
+        },
+        None => Err(err),
+    }
+}
+
+
+/// Returns `true` if the `Accept` header asks for `text/plain` but not for JSON, for clients
+/// expecting the bare shortened URL.
+fn wants_plain_text(headers: &HeaderMap) -> bool {
+    let accepted: Vec<&str> = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|range| range.split(';').next().unwrap_or_default().trim())
+        .collect();
+    let accepts = |media_type: &str| accepted.iter().any(|range| range.eq_ignore_ascii_case(media_type));
+    accepts("text/plain") && !accepts("application/json")
+}
+
+
+/// Returns `true` if the user agent of a create request is denied, e.g. a known bot.
+/// Requests without a user agent are only denied if configured.
+fn is_denied_user_agent(state: &AppState, headers: &HeaderMap) -> bool {
+    match headers.get(header::USER_AGENT).and_then(|h| h.to_str().ok()).filter(|ua| !ua.is_empty()) {
+        Some(ua) => {
+            let ua = ua.to_lowercase();
+            state.config.create_ua_denylist.iter().any(|denied| ua.contains(denied.as_str()))
+        },
+        None => state.config.create_deny_empty_ua,
+    }
+}
+
+
+/// Creates a new shortened URL from a create request.
+async fn create_short_url(
+    state: AppState,
+    req: Request<axum::body::Body>,
+) -> Result<Response, ApiError> {
+    // Denied user agents are rejected before the body is read.
+    if is_denied_user_agent(&state, req.headers()) {
+        debug!("Rejecting create request from denied user agent {:?}", req.headers().get(header::USER_AGENT));
+        return Err(ApiError::new(StatusCode::FORBIDDEN, ErrorCode::ForbiddenUserAgent, "User agent is not allowed"));
+    }
+
+    let (parts, body) = req.into_parts();
+
+    let bytes = read_body(body, state.limits.max_payload_bytes).await?;
+    let payload = parse_create_request(&state, &bytes)?;
+    let short_url_prefix = short_url_prefix(&state, &parts)?;
+    let created = store_short_url(&state, payload, &short_url_prefix, &parts.headers).await?;
+
+    if wants_key_only(&parts) {
+        return Ok((StatusCode::CREATED, created.key).into_response());
+    }
+
+    if wants_plain_text(&parts.headers) {
+        return Ok((StatusCode::CREATED, created.short_url).into_response());
+    }
+
+    Ok((StatusCode::CREATED, JsonBody(created)).into_response())
+}
+
+
+/// Creates a shortened URL for each item of a bulk create request.
+async fn create_short_urls(
+    state: AppState,
+    req: Request<axum::body::Body>,
+) -> Result<Response, ApiError> {
+    if is_denied_user_agent(&state, req.headers()) {
+        debug!("Rejecting bulk create request from denied user agent {:?}", req.headers().get(header::USER_AGENT));
+        return Err(ApiError::new(StatusCode::FORBIDDEN, ErrorCode::ForbiddenUserAgent, "User agent is not allowed"));
+    }
+
     let (parts, body) = req.into_parts();
 
-    let bytes: Bytes = axum::body::to_bytes(body, MAX_PAYLOAD_SIZE).await.map_err(|err| {
+    // Each item may be as large as the body of a single create request.
+    let limit = state.limits.max_payload_bytes.saturating_mul(state.limits.max_bulk_items);
+    let bytes = read_body(body, limit).await?;
+    // The items are deserialized one by one, so a malformed item fails alone, with its index.
+    let items = parse_bulk_items(&bytes, state.limits.max_bulk_items)?;
+    counter!(CREATE_URL_REQUESTS).increment(items.len() as u64);
+    let short_url_prefix = short_url_prefix(&state, &parts)?;
+
+    let (state, headers, short_url_prefix) = (&state, &parts.headers, &short_url_prefix);
+    let results: Vec<(usize, Result<CreateURLResponse, ApiError>)> = futures::stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| async move {
+            let result = match parse_bulk_item(state, item) {
+                Ok(item) => store_short_url(state, item, short_url_prefix, headers).await,
+                Err(err) => Err(err),
+            };
+            (index, result)
+        })
+        .buffered(BULK_CREATE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut response = BulkCreateURLResponse::default();
+    for (index, result) in results {
+        match result {
+            Ok(created) => response.created.push(BulkCreatedItem { index, created }),
+            Err(err) => response.errors.push(BulkItemError {
+                index,
+                status: err.status.as_u16(),
+                error_code: err.code.as_str().to_string(),
+                message: err.message,
+            }),
+        }
+    }
+
+    Ok((StatusCode::OK, JsonBody(response)).into_response())
+}
+
+
+/// Splits the body of a bulk create request into its items, without deserializing them.
+/// The array is read item by item, and rejected as soon as it has more than `max_items`.
+fn parse_bulk_items(bytes: &[u8], max_items: usize) -> Result<Vec<serde_json::Value>, ApiError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let items = BulkItems { max_items }
+        .deserialize(&mut deserializer)
+        .and_then(|items| deserializer.end().map(|_| items));
+    items.map_err(|err| {
+        let msg = format!("Error deserializing request body: {}", err);
+        warn!("{}", msg);
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, msg)
+    })
+}
+
+
+/// Deserializes a JSON array of at most `max_items` items.
+struct BulkItems {
+    max_items: usize,
+}
+
+
+impl<'de> DeserializeSeed<'de> for BulkItems {
+    type Value = Vec<serde_json::Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+
+impl<'de> Visitor<'de> for BulkItems {
+    type Value = Vec<serde_json::Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an array of at most {} create requests", self.max_items)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            if items.len() == self.max_items {
+                return Err(A::Error::custom(format!("A bulk create request has at most {} items", self.max_items)));
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+
+/// Deserializes an item of a bulk create request, in the configured validation mode.
+fn parse_bulk_item(state: &AppState, item: serde_json::Value) -> Result<CreateURLRequest, ApiError> {
+    let payload = serde_json::from_value::<CreateURLRequest>(item).map_err(|err| err.to_string());
+    let payload = match state.config.strict_request_validation {
+        true => payload.and_then(CreateURLRequest::deny_unknown_fields),
+        false => payload,
+    };
+    payload.map_err(|err| {
+        let msg = format!("Error deserializing item: {}", err);
+        warn!("{}", msg);
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, msg)
+    })
+}
+
+
+/// Reads a request body of at most `limit` bytes.
+async fn read_body(body: axum::body::Body, limit: usize) -> Result<Bytes, ApiError> {
+    axum::body::to_bytes(body, limit).await.map_err(|err| {
         let msg = format!("Error reading request body: {}", err);
         warn!("{}", msg);
-        (StatusCode::BAD_REQUEST, msg)
-    })?;
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, msg)
+    })
+}
 
-    let payload: CreateURLRequest = serde_json::from_slice(&bytes).map_err(|err| {
+
+/// Deserializes a create request body, in the configured validation mode. In strict mode, a
+/// body with unknown fields is rejected.
+fn parse_create_request(state: &AppState, bytes: &[u8]) -> Result<CreateURLRequest, ApiError> {
+    let payload = parse_body::<CreateURLRequest>(bytes, state.config.lenient_json_parsing).map_err(|err| err.to_string());
+    let payload = match state.config.strict_request_validation {
+        true => payload.and_then(CreateURLRequest::deny_unknown_fields),
+        false => payload,
+    };
+    payload.map_err(|err| {
         let msg = format!("Error deserializing request body: {}", err);
         warn!("{}", msg);
-        (StatusCode::BAD_REQUEST, msg)
-    })?;
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, msg)
+    })
+}
 
-    let key = state.key_generator.generate_key().await?;
 
-    let headers = &parts.headers;
-    let host = headers
-        .get(header::HOST)
-        .and_then(|h| h.to_str().ok())
+/// Returns the base the short URLs of a create request are built on: the base selected by a
+/// signed brand token, the configured public base URL, or the scheme and host of the request.
+fn short_url_prefix(state: &AppState, parts: &Parts) -> Result<String, ApiError> {
+    let brand_token = parts.headers
+        .get(BRAND_TOKEN_HEADER)
+        .zip(state.config.brand_token_secret.as_deref());
+    if let Some((token, secret)) = brand_token {
+        let token = token.to_str().map_err(|_| "Malformed brand token".to_string());
+        return token.and_then(|token| verify_brand_token(token, secret.as_bytes(), SystemTime::now())).map_err(|msg| {
+            warn!("{}", msg);
+            ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidBrandToken, msg)
+        });
+    }
+
+    // Behind a reverse proxy, the host and scheme of the request are the internal ones.
+    if let Some(base) = &state.config.public_base_url {
+        return Ok(base.clone());
+    }
+
+    let (scheme, host) = request_origin(parts, state.config.trust_forwarded_headers);
+    Ok(format!("{scheme}://{host}/"))
+}
+
+
+/// The header a reverse proxy sets to the scheme of the original request.
+const FORWARDED_PROTO_HEADER: &str = "x-forwarded-proto";
+
+/// The header a reverse proxy sets to the host of the original request.
+const FORWARDED_HOST_HEADER: &str = "x-forwarded-host";
+
+
+/// Returns the scheme and host a request was sent to.
+///
+/// If `trust_forwarded` is set, the `X-Forwarded-Proto` and `X-Forwarded-Host` headers take
+/// precedence over the scheme and `Host` header of the request, which are those of the proxy.
+/// With several proxies, the first value of each header, set by the outermost one, is used.
+fn request_origin(parts: &Parts, trust_forwarded: bool) -> (String, String) {
+    let header_value = |name| parts.headers.get(name).and_then(|h| h.to_str().ok());
+    let forwarded = |name| header_value(name)
+        .filter(|_| trust_forwarded)
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+
+    let scheme = forwarded(FORWARDED_PROTO_HEADER)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+        .map(str::to_ascii_lowercase)
+        .or_else(|| parts.uri.scheme_str().map(str::to_string))
+        .unwrap_or_else(|| "http".to_string());
+    // HTTP/2 requests carry their host in the URI rather than in a `Host` header.
+    let host = forwarded(FORWARDED_HOST_HEADER)
+        .or_else(|| header_value(header::HOST.as_str()))
+        .or_else(|| parts.uri.authority().map(|authority| authority.as_str()))
         .unwrap_or("localhost");
 
-    let schema = if let Some(ch) = parts.uri.scheme() {
-        ch.to_string()
-    } else {
-        "http".to_string()
-    };
+    (scheme, host.to_string())
+}
+
+
+/// Stores the target of a create request under its alias or a generated key, and records the
+/// key in the key audit log.
+async fn store_short_url(
+    state: &AppState,
+    payload: CreateURLRequest,
+    short_url_prefix: &str,
+    headers: &HeaderMap,
+) -> Result<CreateURLResponse, ApiError> {
+    let target = normalize_target(
+        &payload.url,
+        state.config.default_target_scheme.as_deref(),
+        state.config.block_homograph_hosts,
+    ).and_then(|target| {
+        validate_target(&target, &state.config.allowed_target_schemes)?;
+        Ok(target)
+    }).map_err(|msg| {
+        warn!("{}", msg);
+        ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidUrl, msg)
+    })?;
+    let original_url = target.clone();
+    let target = RedirectTarget { url: target, permanent: payload.permanent, limited: false, expires_in: None };
+
+    if payload.max_uses == Some(0) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, "max_uses must be at least 1"));
+    }
 
-    state.db_layer.insert_key(key.clone(), payload.url).await?;
+    let ttl = payload.ttl_seconds.map(Duration::from_secs);
+    if ttl.is_some_and(|ttl| ttl.is_zero() || ttl > state.limits.max_ttl) {
+        let msg = format!("ttl_seconds must be between 1 and {}", state.limits.max_ttl.as_secs());
+        warn!("{}", msg);
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, msg));
+    }
+    if ttl.is_some() && payload.max_uses.is_some() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, "ttl_seconds is not supported with max_uses"));
+    }
+
+    let key = match payload.alias {
+        Some(alias) => {
+            if payload.max_uses.is_some() {
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidRequest, "max_uses is not supported with an alias"));
+            }
+            state.key_spec.validate(&alias).map_err(|err| {
+                let msg = format!("Invalid alias {alias}: {err}");
+                warn!("{}", msg);
+                ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidAlias, msg)
+            })?;
+            // The client chose the alias, so it can pick a shorter one.
+            check_short_url_length(state, short_url_prefix, &alias).map_err(|msg| {
+                warn!("{}", msg);
+                ApiError::new(StatusCode::BAD_REQUEST, ErrorCode::ShortUrlTooLong, msg)
+            })?;
+            // With `If-None-Match: *`, the client made the create conditional on the alias being free.
+            let conditional = headers
+                .get(header::IF_NONE_MATCH)
+                .is_some_and(|h| h.as_bytes() == b"*");
+            state.db_layer.insert_key_with_ttl_if_absent(alias.clone(), target, ttl).await.map_err(|err| match err {
+                DatabaseError::AlreadyExists(_) if conditional => ApiError::new(StatusCode::PRECONDITION_FAILED, ErrorCode::KeyTaken, err.to_string()),
+                err => err.into(),
+            })?;
+            alias
+        },
+        None => {
+            let max_attempts = state.config.key_generation_attempts;
+            let mut attempt = 1;
+            loop {
+                let key = generate_valid_key(state, short_url_prefix).await?;
+                // A colliding key is never overwritten, a new one is generated instead.
+                let inserted = match (payload.max_uses, ttl) {
+                    (Some(max_uses), _) => state.db_layer.insert_limited_key_if_absent(key.clone(), target.clone(), max_uses).await,
+                    (None, Some(ttl)) => state.db_layer.insert_key_with_ttl_if_absent(key.clone(), target.clone(), Some(ttl)).await,
+                    (None, None) => state.db_layer.insert_key_if_absent(key.clone(), target.clone()).await,
+                };
+                match inserted {
+                    Ok(()) => break key,
+                    Err(DatabaseError::AlreadyExists(_)) if attempt < max_attempts => {
+                        warn!("Generated key {} is already taken, generating another one", key);
+                        attempt += 1;
+                    },
+                    Err(DatabaseError::AlreadyExists(_)) => {
+                        error!("No free key found after {} attempts", max_attempts);
+                        return Err(GeneratorError::KeyspaceExhausted.into());
+                    },
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        },
+    };
 
-    let url = format!("{schema}://{host}/{key}");
+    if let Some(key_audit) = &state.key_audit {
+        key_audit.record(&key, headers);
+    }
 
-    Ok((StatusCode::CREATED, url))
+    Ok(CreateURLResponse { short_url: format!("{short_url_prefix}{key}"), key, original_url })
 }
 
 
@@ -76,62 +541,472 @@ pub async fn create_url(
 #[instrument(level = "debug", target = "healthy", skip(_state))]
 pub async fn get_healthy(
     State(_state): State<AppState>
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(StatusCode::OK)
+}
+
+
+/// This handler checks whether the service is ready to receive traffic.
+/// It returns a 503 Service Unavailable status during the startup warmup, so load balancers hold
+/// traffic until connections are warm, and once shutdown begins, so they stop sending requests
+/// while in-flight ones drain.
+#[instrument(level = "debug", target = "ready", skip(state))]
+pub async fn get_ready(
+    State(state): State<AppState>
+) -> Result<impl IntoResponse, ApiError> {
+    if state.is_shutting_down() {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, ErrorCode::NotReady, "Shutting down"));
+    }
+    if state.is_warming_up() {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, ErrorCode::NotReady, "Warming up"));
+    }
+    Ok(StatusCode::OK)
+}
+
+
+/// Runs the health check of a dependency, bounded by `timeout`.
+/// It returns the name of the dependency if it is unavailable.
+async fn check_dependency<E: std::fmt::Display>(
+    name: &'static str,
+    timeout: Duration,
+    health_check: impl Future<Output = Result<(), E>>,
+) -> Option<&'static str> {
+    match tokio::time::timeout(timeout, health_check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => {
+            warn!("Health check of the {} failed: {}", name, err);
+            Some(name)
+        },
+        Err(_) => {
+            warn!("Health check of the {} timed out after {:?}", name, timeout);
+            Some(name)
+        },
+    }
+}
+
+
+/// This handler checks whether the service and its dependencies are ready to receive traffic.
+/// Besides the checks of `get_ready`, it returns a 503 Service Unavailable status listing the
+/// unreachable dependencies if the database, the task sender or the key generator is unreachable.
+/// The dependencies are checked concurrently, each bounded by the configured health check
+/// timeout, so a slow dependency does not delay the checks of the others.
+#[instrument(level = "debug", target = "ready_dependencies", skip(state))]
+pub async fn get_ready_dependencies(
+    State(state): State<AppState>
+) -> Result<impl IntoResponse, ApiError> {
+    get_ready(State(state.clone())).await?;
+
+    let timeout = state.config.health_check_timeout;
+    let (db, task_sender, key_generator) = tokio::join!(
+        check_dependency("database", timeout, state.db_layer.health_check()),
+        check_dependency("task sender", timeout, state.task_sender.health_check()),
+        check_dependency("key generator", timeout, state.key_generator.health_check()),
+    );
+
+    let unavailable: Vec<&str> = [db, task_sender, key_generator].into_iter().flatten().collect();
+    if !unavailable.is_empty() {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, ErrorCode::NotReady, format!("Unavailable: {}", unavailable.join(", "))));
+    }
     Ok(StatusCode::OK)
 }
 
 
 /// This handler retrieves a URL from a shortened key and redirects the user to it.
-/// It also sends a task to a task sender to record the URL visit.
-#[instrument(level = "info", target = "get_url", skip(state))]
+/// It also sends a task to a task sender in the background to record the URL visit, so the
+/// redirect does not wait for it.
+/// Keys created with `max_uses` return `410 Gone` once they have used up their visits.
+/// Legally-blocked keys or destinations return `451 Unavailable For Legal Reasons` instead.
+/// Redirects of throttled keys or destinations are delayed by the configured throttle delay.
+/// In beacon analytics mode, no task is sent: an HTML page records the visit client-side
+/// and then navigates to the URL.
+/// `HEAD` requests and requests opting out with `X-No-Track` or `?track=false` are redirected
+/// without recording the visit or using up a key created with `max_uses`.
+/// Its span is created at the level configured for `get_url`.
 pub async fn get_url(
     State(state): State<AppState>,
-    Path(url_key): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let url = state.db_layer.get_key_url(&url_key).await?;
-    
-    let now_dur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    ValidatedKey(url_key): ValidatedKey,
+    TrackVisit(track): TrackVisit,
+) -> Result<Response, ApiError> {
+    let span = handler_span!(state.config.trace_levels.get_url, "get_url", url_key = %url_key);
+    redirect_to_url(state, url_key, track).instrument(span).await
+}
+
+
+/// This handler returns an HTML page showing where a shortened key leads, with a link to
+/// continue to it, instead of redirecting.
+/// The visit is neither recorded nor counted against `max_uses`.
+/// Its span is created at the level configured for `get_url`.
+pub async fn preview_url(
+    State(state): State<AppState>,
+    ValidatedKey(url_key): ValidatedKey,
+) -> Result<Response, ApiError> {
+    let span = handler_span!(state.config.trace_levels.get_url, "preview_url", url_key = %url_key);
+    let Resolution { location, .. } = resolve_key(&state, &url_key).instrument(span).await?;
+    Ok(HtmlBody(html::preview_page(&url_key, &location)).into_response())
+}
+
+
+/// Resolves a key to the location `get_url` sends the visitor to.
+/// Legally-blocked keys or destinations return `451 Unavailable For Legal Reasons`.
+async fn resolve_key(state: &AppState, url_key: &str) -> Result<Resolution, ApiError> {
+    if state.config.blocked_keys.contains(url_key) {
+        return Err(ApiError::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, ErrorCode::Blocked, state.config.blocked_notice.clone()));
+    }
+
+    let target = state.db_layer.get_key_url(url_key).await.inspect_err(record_database_error)?;
+
+    if state.config.blocked_urls.contains(&target.url) {
+        return Err(ApiError::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, ErrorCode::Blocked, state.config.blocked_notice.clone()));
+    }
+
+    let mut location = target.url.clone();
+    let mut transformations = Vec::new();
+    if let Some(upgraded) = upgrade_http(&location).filter(|_| state.config.upgrade_http_targets) {
+        location = upgraded;
+        transformations.push("upgrade_http".to_string());
+    }
+
+    Ok(Resolution {
+        key: url_key.to_string(),
+        location,
+        target: target.url,
+        transformations,
+        permanent: target.permanent,
+        limited: target.limited,
+    })
+}
+
+
+/// Returns the `https` counterpart of an `http` URL, or `None` for any other URL.
+/// URLs with an explicit non-default port are not upgraded, as that port is unlikely to serve
+/// `https`.
+fn upgrade_http(url: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    // The default port is dropped while parsing, so any port left is a non-default one.
+    if url.scheme() != "http" || url.port().is_some() {
+        return None;
+    }
+    url.set_scheme("https").ok()?;
+    Some(url.into())
+}
+
+
+/// Redirects to `url` with `308 Permanent Redirect`, or `307 Temporary Redirect` so that
+/// browsers and CDNs do not cache it.
+fn redirect(url: &str, permanent: bool) -> Response {
+    if permanent {
+        Redirect::permanent(url).into_response()
+    } else {
+        Redirect::temporary(url).into_response()
+    }
+}
+
+
+/// Looks up the URL of a key and redirects to it, recording the visit.
+async fn redirect_to_url(state: AppState, url_key: String, track: bool) -> Result<Response, ApiError> {
+    let Resolution { location: url, target, permanent, limited, .. } = resolve_key(&state, &url_key).await?;
+    // Only tracked visits use up a limited key, so link unfurlers and probes cannot exhaust it.
+    // Unlimited keys are not looked up again.
+    if track && limited {
+        state.db_layer.consume_visit(&url_key).await.inspect_err(record_database_error)?;
+    }
+    counter!(GET_URL_REDIRECTS).increment(1);
+
+    // The database calls are done, so the delay does not hold any database resources.
+    if state.config.throttled_keys.contains(&url_key) || state.config.throttled_urls.contains(&target) {
+        debug!("Delaying the redirect of throttled key {}", url_key);
+        tokio::time::sleep(state.config.throttle_delay).await;
+    }
+
+    if !track {
+        return Ok(redirect(&url, permanent));
+    }
+
+    // Counting the visit is best-effort too, and happens whatever the analytics mode.
+    let db_layer = state.db_layer.clone();
+    let visited_key = url_key.clone();
+    state.spawn_background(async move {
+        db_layer.record_visit(&visited_key).await.unwrap_or_else(|err| {
+            error!("Error recording visit of key {}: {}", visited_key, err);
+        });
+    });
+
+    if let AnalyticsMode::Beacon { url: beacon_url } = &state.config.analytics {
+        let page = html::beacon_page(beacon_url, &url_key, &url);
+        return Ok(([(header::CACHE_CONTROL, "no-store")], HtmlBody(page)).into_response());
+    }
     
-    state.task_sender.send_task(
-        rust_proto_pkg::generated::Task {
-            task: Some(
-                rust_proto_pkg::generated::task::Task::T1(rust_proto_pkg::generated::InsertRecord {
-                    tag: url_key,
-                    time: Some(
-                        prost_types::Timestamp {
-                            seconds: now_dur.as_secs() as i64,
-                            nanos: now_dur.subsec_nanos() as i32,
-                        }
-                    ),
-                })
-            )
-        }
-    ).await.unwrap_or_else(|err| {
-        error!("Error sending task: {}", err);
+    // Recording the visit is best-effort, so the redirect does not wait for the task queue.
+    let now = SystemTime::now();
+    send_task_in_background(&state, visit_task(&url_key, now));
+
+    // Sending only fails when nobody is subscribed to the live visit stream.
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let _ = state.visits.send(VisitEvent { key: url_key, url: url.clone(), timestamp });
+
+    Ok(redirect(&url, permanent))
+}
+
+
+/// Sends a task in the background, so the response does not wait for the task queue.
+///
+/// Sending is best-effort: a task that cannot be sent is logged and dropped.
+fn send_task_in_background(state: &AppState, task: rust_proto_pkg::generated::Task) {
+    let task_sender = state.task_sender.clone();
+    state.spawn_background(async move {
+        if let Err(err) = task_sender.send_task(task).await {
+            error!("Error sending task: {}", err);
+        }
     });
+}
+
+
+/// This handler streams URL visits as Server-Sent Events as they happen.
+/// It requires the configured visit stream token as a bearer token, and returns a 404 if
+/// the stream is disabled.
+#[instrument(level = "info", target = "stream_visits", skip(state, headers))]
+pub async fn stream_visits(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(token) = &state.config.visit_stream.token else {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, ErrorCode::NotFound, "Visit stream is disabled"));
+    };
+
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|provided| secret_eq(provided, token));
 
-    Ok(Redirect::permanent(url.as_str()))
+    if !authorized {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized, "Invalid or missing token"));
+    }
+
+    let stream = visit_stream(state.visits.subscribe());
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+
+/// This handler resolves a shortened key without redirecting or recording a visit.
+/// It returns the stored target and the `Location` that `get_url` would emit, and
+/// returns a 404 unless debug endpoints are enabled.
+#[instrument(level = "info", target = "resolve_url", skip(state))]
+pub async fn resolve_url(
+    State(state): State<AppState>,
+    ValidatedKey(url_key): ValidatedKey,
+) -> Result<JsonBody<Resolution>, ApiError> {
+    if !state.config.debug_endpoints {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, ErrorCode::NotFound, "Debug endpoints are disabled"));
+    }
+
+    Ok(JsonBody(resolve_key(&state, &url_key).await?))
+}
+
+
+/// This handler returns the original URL of a shortened key and how many times it was visited.
+/// It returns a 404 if the key does not exist.
+#[instrument(level = "info", target = "get_stats", skip(state))]
+pub async fn get_stats(
+    State(state): State<AppState>,
+    ValidatedKey(url_key): ValidatedKey,
+) -> Result<JsonBody<KeyStats>, ApiError> {
+    let target = state.db_layer.get_key_url(&url_key).await.inspect_err(record_database_error)?;
+    let visits = state.db_layer.get_visit_count(&url_key).await.inspect_err(record_database_error)?;
+    Ok(JsonBody(KeyStats { key: url_key, original_url: target.url, visits }))
 }
 
 
 #[derive(Deserialize)]
 struct CreateURLRequest {
     url: String,
+    /// The number of visits allowed, e.g. `1` for a one-time link. If `None`, visits are unlimited.
+    max_uses: Option<u32>,
+    /// The key requested instead of a generated one.
+    alias: Option<String>,
+    /// How long the key is kept, in seconds. If `None`, the default expiration of the database applies.
+    ttl_seconds: Option<u64>,
+    /// Whether visits are redirected permanently (`308`), or temporarily (`307`) so the key can
+    /// be repurposed without clients caching the old target.
+    #[serde(default = "default_permanent")]
+    permanent: bool,
+    /// The fields of the request that are not known, only rejected in strict validation mode.
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+
+fn default_permanent() -> bool {
+    true
+}
+
+
+impl CreateURLRequest {
+    /// Rejects the request if it has unknown fields, as `#[serde(deny_unknown_fields)]` would.
+    fn deny_unknown_fields(self) -> Result<Self, String> {
+        match self.unknown_fields.keys().next() {
+            Some(field) => Err(format!("unknown field `{field}`")),
+            None => Ok(self),
+        }
+    }
+}
+
+
+/// The body returned by the create endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateURLResponse {
+    /// The shortened URL redirecting to the original URL.
+    pub short_url: String,
+    /// The key of the shortened URL.
+    pub key: String,
+    /// The URL the key redirects to, after normalization.
+    pub original_url: String,
+}
+
+
+/// The body returned by the bulk create endpoint. Each item is reported with its index in the
+/// request, so clients can correlate the results with their inputs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkCreateURLResponse {
+    /// The items that were created, in order.
+    pub created: Vec<BulkCreatedItem>,
+    /// The items that failed, in order.
+    pub errors: Vec<BulkItemError>,
+}
+
+
+/// An item of a bulk create request that was created.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkCreatedItem {
+    /// The index of the item in the request.
+    pub index: usize,
+    /// The body a single create request would have returned.
+    #[serde(flatten)]
+    pub created: CreateURLResponse,
+}
+
+
+/// An item of a bulk create request that failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkItemError {
+    /// The index of the item in the request.
+    pub index: usize,
+    /// The HTTP status code a single create request would have returned.
+    pub status: u16,
+    /// The machine-readable code of the failure, e.g. `INVALID_URL`.
+    pub error_code: String,
+    /// Why the item failed.
+    pub message: String,
+}
+
+
+/// How a key resolves to the location a visitor is redirected to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolution {
+    /// The resolved key.
+    pub key: String,
+    /// The target stored for the key.
+    pub target: String,
+    /// The transformations applied to the target to compute the location, in order.
+    pub transformations: Vec<String>,
+    /// The location the visitor is redirected to.
+    pub location: String,
+    /// Whether the redirect is permanent, or temporary.
+    pub permanent: bool,
+    /// Whether the key may have a limited number of visits.
+    pub limited: bool,
+}
+
+
+/// The visit statistics of a key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyStats {
+    /// The key of the shortened URL.
+    pub key: String,
+    /// The URL the key redirects to.
+    pub original_url: String,
+    /// The number of recorded visits of the key.
+    pub visits: u64,
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use anyhow::anyhow;
+    use async_trait::async_trait;
+    use tokio::sync::Notify;
     use super::*;
-    use axum::http::Request;
+    use axum::http::{HeaderName, HeaderValue, Request};
     use axum::response::{IntoResponse, Response};
     use axum::body::Body;
+    use axum::Router;
+    use axum::routing::get;
+    use tower::ServiceExt;
     use crate::app::AppState;
+    use crate::app::brand::tests::sign;
+    use crate::app::errors::{ErrorBody, ERROR_CODE_HEADER};
+    use crate::app::key_audit::KeyAudit;
+    use crate::app::json_lines::JsonLines;
+    use crate::app::json_lines::tests::SharedBuffer;
+    use crate::app::spans::tests::RecordingSubscriber;
+    use crate::config::{HandlerConfig, KeySpecConfig, LimitsConfig, TraceLevelConfig, VisitStreamConfig};
     use crate::database::MockDatabase;
-    use crate::key_generator::MockKeyGenerationService;
-    use crate::task_sender::MockTaskSender;
+    use futures::StreamExt;
+    use crate::key_generator::{KeyGenerationService, MockKeyGenerationService, MAX_KEY_LENGTH};
+    use crate::task_sender::{MockTaskSender, TaskSender};
+
+    /// Returns the message of an error response.
+    async fn error_message(resp: Response) -> String {
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        serde_json::from_slice::<ErrorBody>(&body_bytes).unwrap().error
+    }
+
+    /// The state a create request is sent to by `create_with`.
+    struct CreateState {
+        db_layer: MockDatabase,
+        key_generator: MockKeyGenerationService,
+        config: HandlerConfig,
+        limits: LimitsConfig,
+    }
+
+    impl Default for CreateState {
+        /// A state whose database accepts any insert, and whose key generator returns `12345678`.
+        fn default() -> Self {
+            let mut db_layer = MockDatabase::new();
+            let mut key_generator = MockKeyGenerationService::new();
+            db_layer.expect_insert_key_if_absent().returning(|_, _| Ok(()));
+            key_generator.expect_generate_key().returning(|| Ok("12345678".to_string()));
+            Self { db_layer, key_generator, config: HandlerConfig::default(), limits: LimitsConfig::default() }
+        }
+    }
+
+    impl CreateState {
+        /// A state whose database and key generator panic on any call.
+        fn unused() -> Self {
+            Self { db_layer: MockDatabase::new(), key_generator: MockKeyGenerationService::new(), ..Self::default() }
+        }
+    }
+
+    /// Sends a create request with `headers` and `body` to `state`. The `Host` header is
+    /// `some-host` unless set in `headers`.
+    async fn create_with(state: CreateState, headers: &[(HeaderName, &str)], body: impl Into<Body>) -> Response {
+        let app_state = AppState::new (
+            Arc::new(state.db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(state.key_generator),
+        ).await.unwrap().with_config(state.config).with_limits(state.limits);
+
+        let mut req = Request::builder().method("POST").uri("/api/v1/create").body(body.into()).unwrap();
+        req.headers_mut().insert(header::HOST, HeaderValue::from_static("some-host"));
+        for (name, value) in headers {
+            req.headers_mut().insert(name, HeaderValue::from_str(value).unwrap());
+        }
+
+        create_url(State(app_state), req).await.into_response()
+    }
 
     #[tokio::test]
     async fn test_create_url() {
@@ -140,7 +1015,7 @@ mod tests {
         let mut key_generator = MockKeyGenerationService::new();
         let task_sender = MockTaskSender::new();
 
-        db_layer.expect_insert_key().returning(|_, _| Ok(()));
+        db_layer.expect_insert_key_if_absent().returning(|_, _| Ok(()));
         key_generator.expect_generate_key().returning(|| Ok("12345678".to_string()));
 
         let state = AppState::new (
@@ -164,17 +1039,574 @@ mod tests {
         let resp: Response = response.unwrap().into_response();
         assert_eq!(resp.status(), StatusCode::CREATED);
 
-        let body_bytes = axum::body::to_bytes(resp.into_body(), 50_usize).await.unwrap();
-        assert_eq!(body_bytes, "http://some-host/12345678"); // Assuming the key is generated as "12345678");
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        let body: CreateURLResponse = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(body.short_url, "http://some-host/12345678"); // Assuming the key is generated as "12345678");
+    }
+
+    async fn create_with_accept(accept: &str) -> Response {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_if_absent().times(1).returning(|_, _| Ok(()));
+        let state = CreateState { db_layer, ..CreateState::default() };
+        create_with(state, &[(header::ACCEPT, accept)], r#"{"url": "http://example.com/path"}"#).await
     }
 
     #[tokio::test]
-    async fn test_create_url_bad_req() {
-        let db_layer = MockDatabase::new();
-        let key_generator = MockKeyGenerationService::new();
-        let task_sender = MockTaskSender::new();
+    async fn test_create_url_json_body() {
+        let resp = create_with_accept("application/json").await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
 
-        let state = AppState::new (
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "short_url": "http://some-host/12345678",
+            "key": "12345678",
+            "original_url": "http://example.com/path",
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_create_url_accept_plain_text() {
+        let resp = create_with_accept("text/plain").await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 50_usize).await.unwrap();
+        assert_eq!(body_bytes, "http://some-host/12345678");
+
+        // Clients accepting both keep getting JSON.
+        let resp = create_with_accept("text/plain, application/json;q=0.9").await;
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+    }
+
+    /// Creates a URL with a database where the first `taken` generated keys already exist.
+    async fn create_with_collisions(taken: usize) -> (Response, Arc<AtomicUsize>) {
+        let mut db_layer = MockDatabase::new();
+        let mut key_generator = MockKeyGenerationService::new();
+
+        db_layer.expect_insert_key_if_absent()
+            .returning(|key, _| if key.as_str() < "3" { Err(DatabaseError::AlreadyExists(key)) } else { Ok(()) });
+        let generated = Arc::new(AtomicUsize::new(0));
+        let counter = generated.clone();
+        key_generator.expect_generate_key()
+            .returning(move || Ok(format!("{}abcdefg", (3 - taken) + counter.fetch_add(1, Ordering::SeqCst))));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(key_generator),
+        ).await.unwrap();
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/v1/create")
+            .header("x-response", "key")
+            .body(Body::from(r#"{"url": "http://example.com"}"#))
+            .unwrap();
+        (create_url(State(state), req).await.into_response(), generated)
+    }
+
+    #[tokio::test]
+    async fn test_create_url_retries_key_collisions() {
+        let (resp, generated) = create_with_collisions(2).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(generated.load(Ordering::SeqCst), 3);
+
+        let body = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        assert_eq!(body, "3abcdefg");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_retries_key_collisions_with_ttl_or_limit() {
+        for body in [r#"{"url": "http://example.com", "ttl_seconds": 60}"#, r#"{"url": "http://example.com", "max_uses": 1}"#] {
+            let mut db_layer = MockDatabase::new();
+            let mut key_generator = MockKeyGenerationService::new();
+
+            let taken = |key: String| if key == "1abcdefg" { Err(DatabaseError::AlreadyExists(key)) } else { Ok(()) };
+            db_layer.expect_insert_key_with_ttl_if_absent().returning(move |key, _, _| taken(key));
+            db_layer.expect_insert_limited_key_if_absent().returning(move |key, _, _| taken(key));
+            let mut seq = mockall::Sequence::new();
+            key_generator.expect_generate_key().times(1).in_sequence(&mut seq).returning(|| Ok("1abcdefg".to_string()));
+            key_generator.expect_generate_key().times(1).in_sequence(&mut seq).returning(|| Ok("2abcdefg".to_string()));
+
+            let state = AppState::new (
+                Arc::new(db_layer),
+                Arc::new(MockTaskSender::new()),
+                Arc::new(key_generator),
+            ).await.unwrap();
+
+            let req = Request::builder()
+                .method("POST")
+                .uri("/api/v1/create")
+                .header("x-response", "key")
+                .body(Body::from(body))
+                .unwrap();
+            let resp = create_url(State(state), req).await.into_response();
+            assert_eq!(resp.status(), StatusCode::CREATED, "{body}");
+            let key = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+            assert_eq!(key, "2abcdefg", "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_url_key_collisions_exhausted() {
+        let (resp, generated) = create_with_collisions(3).await;
+        // The same as a key generator running out of keys.
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "KEYSPACE_EXHAUSTED");
+        // The default of 3 attempts is used up.
+        assert_eq!(generated.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_key_audit() {
+        let mut db_layer = MockDatabase::new();
+        let mut key_generator = MockKeyGenerationService::new();
+
+        db_layer.expect_insert_key_if_absent().returning(|_, _| Ok(()));
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .returning(|key, _, _| match key.as_str() {
+                "taken" => Err(DatabaseError::AlreadyExists(key)),
+                _ => Ok(()),
+            });
+        key_generator.expect_generate_key().returning(|| Ok("12345678".to_string()));
+
+        let buffer = SharedBuffer::default();
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(key_generator),
+        ).await.unwrap().with_key_audit(KeyAudit::new(JsonLines::new(buffer.clone()), "x-owner"));
+
+        for (body, status) in [
+            (r#"{"url": "http://example.com"}"#, StatusCode::CREATED),
+            (r#"{"url": "http://example.com", "alias": "my-link"}"#, StatusCode::CREATED),
+            (r#"{"url": "http://example.com", "alias": "taken"}"#, StatusCode::CONFLICT),
+        ] {
+            let req = Request::builder()
+                .method("POST")
+                .uri("/api/v1/create")
+                .header("x-owner", "team-a")
+                .body(Body::from(body))
+                .unwrap();
+            let resp = create_url(State(state.clone()), req).await.into_response();
+            assert_eq!(resp.status(), status);
+        }
+
+        // Only the successful creates are audited.
+        let entries = buffer.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["key"], "12345678");
+        assert_eq!(entries[1]["key"], "my-link");
+        assert!(entries.iter().all(|entry| entry["owner"] == "team-a"));
+    }
+
+    async fn create_with_max_short_url_length(max_short_url_length: usize, host: &str, body: &'static str) -> Response {
+        // No database expectations are set, so storing a key panics.
+        let state = CreateState {
+            db_layer: MockDatabase::new(),
+            limits: LimitsConfig { max_short_url_length, ..LimitsConfig::default() },
+            ..CreateState::default()
+        };
+        create_with(state, &[(header::HOST, host)], body).await
+    }
+
+    #[tokio::test]
+    async fn test_create_url_short_url_too_long() {
+        let host = format!("{}.example.com", "a".repeat(60));
+        let resp = create_with_max_short_url_length(64, &host, r#"{"url": "http://example.com"}"#).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert!(error_message(resp).await.ends_with("would be 88 characters long, over the maximum of 64"));
+    }
+
+    async fn create_bulk(max_bulk_items: usize, body: &'static str) -> Response {
+        let mut db_layer = MockDatabase::new();
+        let mut key_generator = MockKeyGenerationService::new();
+        db_layer.expect_insert_key_if_absent().returning(|_, _| Ok(()));
+        db_layer.expect_insert_key_with_ttl_if_absent().returning(|key, _, _| match key.as_str() {
+            "taken" => Err(DatabaseError::AlreadyExists(key)),
+            _ => Ok(()),
+        });
+        let generated = AtomicUsize::new(0);
+        key_generator.expect_generate_key()
+            .returning(move || Ok(format!("{}abcdefg", generated.fetch_add(1, Ordering::SeqCst) + 1)));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(key_generator),
+        ).await.unwrap().with_limits(LimitsConfig {
+            max_bulk_items,
+            ..LimitsConfig::default()
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/v1/create/bulk")
+            .header(header::HOST, "some-host")
+            .body(Body::from(body))
+            .unwrap();
+
+        create_url_bulk(State(state), req).await.into_response()
+    }
+
+    #[tokio::test]
+    async fn test_create_url_bulk_partial_failure() {
+        let resp = create_bulk(10, r#"[
+            {"url": "http://example.com/1"},
+            {"url": "javascript:alert(1)"},
+            {"url": "http://example.com/2", "alias": "my-link"},
+            {"url": "http://example.com/3", "alias": "taken"},
+            {"url": "http://example.com/4"}
+        ]"#).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+        let response: BulkCreateURLResponse = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(response.created.len(), 3);
+        assert_eq!(response.created[0], BulkCreatedItem {
+            index: 0,
+            created: CreateURLResponse {
+                short_url: "http://some-host/1abcdefg".to_string(),
+                key: "1abcdefg".to_string(),
+                original_url: "http://example.com/1".to_string(),
+            },
+        });
+        assert!(matches!(&response.created[1], BulkCreatedItem { index: 2, created } if created.short_url == "http://some-host/my-link"));
+        assert!(matches!(&response.created[2], BulkCreatedItem { index: 4, created } if created.key == "2abcdefg"));
+        assert_eq!(response.errors.len(), 2);
+        assert!(matches!(&response.errors[0], BulkItemError { index: 1, status: 400, error_code, .. } if error_code == "INVALID_URL"));
+        assert!(matches!(&response.errors[1], BulkItemError { index: 3, status: 409, error_code, .. } if error_code == "KEY_TAKEN"));
+    }
+
+    #[tokio::test]
+    async fn test_create_url_bulk_error_index() {
+        let resp = create_bulk(50, r#"[
+            {"url": "http://example.com/1"},
+            {"url": "http://example.com/2"},
+            {"url": 37},
+            {"url": "http://example.com/3"},
+            {"url": "ftp://example.com/4"}
+        ]"#).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["index"], 2);
+        assert_eq!(errors[0]["status"], 400);
+        assert_eq!(errors[0]["error_code"], "INVALID_REQUEST");
+        assert!(errors[0]["message"].as_str().unwrap().starts_with("Error deserializing item: invalid type"));
+        assert_eq!(errors[1]["index"], 4);
+        assert_eq!(errors[1]["error_code"], "INVALID_URL");
+        let indices: Vec<_> = body["created"].as_array().unwrap().iter().map(|item| item["index"].as_u64().unwrap()).collect();
+        assert_eq!(indices, [0, 1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_bulk_too_many_items() {
+        let resp = create_bulk(2, r#"[{"url": "http://example.com/1"}, {"url": "http://example.com/2"}, {"url": "http://example.com/3"}]"#).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_parse_bulk_items() {
+        let items = parse_bulk_items(br#"[{"url": "http://example.com/1"}, 37]"#, 2).unwrap();
+        assert_eq!(items, [serde_json::json!({"url": "http://example.com/1"}), serde_json::json!(37)]);
+        assert!(parse_bulk_items(b"[]", 2).unwrap().is_empty());
+
+        // The array is rejected at its first extra item, before the rest of the body is read.
+        let err = parse_bulk_items(br#"[1, 2, 3, not json"#, 2).unwrap_err();
+        assert!(err.message.contains("at most 2 items"), "{}", err.message);
+        assert!(parse_bulk_items(br#"{"url": "http://example.com"}"#, 2).is_err());
+        assert!(parse_bulk_items(br#"[1] 2"#, 2).is_err());
+    }
+
+    async fn create_with_body_size(size: usize) -> StatusCode {
+        let state = CreateState { limits: LimitsConfig { max_payload_bytes: 64, ..LimitsConfig::default() }, ..CreateState::default() };
+        // The path of the target pads the body to the requested size.
+        let prefix = r#"{"url": "http://example.com/"#;
+        let body = format!("{prefix}{}\"}}", "a".repeat(size - prefix.len() - 2));
+        assert_eq!(body.len(), size);
+        create_with(state, &[], body).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_create_url_max_payload_bytes() {
+        assert_eq!(create_with_body_size(63).await, StatusCode::CREATED);
+        assert_eq!(create_with_body_size(64).await, StatusCode::CREATED);
+        assert_eq!(create_with_body_size(65).await, StatusCode::BAD_REQUEST);
+    }
+
+    async fn create_with_brand_token(token: &str) -> Response {
+        let state = CreateState {
+            config: HandlerConfig { brand_token_secret: Some("secret".to_string()), ..HandlerConfig::default() },
+            ..CreateState::default()
+        };
+        let headers = [(header::ACCEPT, "text/plain"), (HeaderName::from_static("x-brand-token"), token)];
+        create_with(state, &headers, r#"{"url": "http://example.com"}"#).await
+    }
+
+    #[tokio::test]
+    async fn test_create_url_brand_token() {
+        let exp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() + 60;
+        let resp = create_with_brand_token(&sign("https://brand.example", exp, b"secret")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        assert_eq!(&body_bytes[..], b"https://brand.example/12345678");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_invalid_brand_token() {
+        let exp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() + 60;
+        for token in [sign("https://brand.example", exp, b"other"), sign("https://brand.example", 1, b"secret")] {
+            let resp = create_with_brand_token(&token).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    async fn create_with_public_base_url(public_base_url: Option<&str>) -> String {
+        let state = CreateState {
+            config: HandlerConfig { public_base_url: public_base_url.map(str::to_string), ..HandlerConfig::default() },
+            ..CreateState::default()
+        };
+        let headers = [(header::HOST, "internal-pod:8081"), (header::ACCEPT, "text/plain")];
+        let resp = create_with(state, &headers, r#"{"url": "http://example.com"}"#).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        String::from_utf8(body_bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_url_public_base_url() {
+        assert_eq!(create_with_public_base_url(Some("https://sho.rt/go/")).await, "https://sho.rt/go/12345678");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_host_header_fallback() {
+        assert_eq!(create_with_public_base_url(None).await, "http://internal-pod:8081/12345678");
+    }
+
+    fn origin(headers: &[(&str, &str)], trust_forwarded: bool) -> (String, String) {
+        let mut req = Request::builder().uri("/api/v1/create");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let (parts, _) = req.body(()).unwrap().into_parts();
+        request_origin(&parts, trust_forwarded)
+    }
+
+    #[test]
+    fn test_request_origin_forwarded_headers() {
+        let headers = [("Host", "internal-pod:8081"), ("X-Forwarded-Proto", "https"), ("X-Forwarded-Host", "sho.rt")];
+        assert_eq!(origin(&headers, true), ("https".to_string(), "sho.rt".to_string()));
+
+        // Behind several proxies, the outermost one sets the first value.
+        let headers = [("X-Forwarded-Proto", "HTTPS, http"), ("X-Forwarded-Host", "sho.rt, edge.internal")];
+        assert_eq!(origin(&headers, true), ("https".to_string(), "sho.rt".to_string()));
+
+        // Unexpected schemes are ignored.
+        let headers = [("Host", "internal-pod:8081"), ("X-Forwarded-Proto", "javascript")];
+        assert_eq!(origin(&headers, true), ("http".to_string(), "internal-pod:8081".to_string()));
+    }
+
+    #[test]
+    fn test_request_origin_without_forwarded_headers() {
+        assert_eq!(origin(&[("Host", "internal-pod:8081")], true), ("http".to_string(), "internal-pod:8081".to_string()));
+        assert_eq!(origin(&[], true), ("http".to_string(), "localhost".to_string()));
+    }
+
+    #[test]
+    fn test_request_origin_uri_authority() {
+        let (parts, _) = Request::builder().uri("https://some-host:8443/api/v1/create").body(()).unwrap().into_parts();
+        assert_eq!(request_origin(&parts, false), ("https".to_string(), "some-host:8443".to_string()));
+
+        // The `Host` header takes precedence.
+        let (parts, _) = Request::builder().uri("https://some-host/api/v1/create").header("Host", "other-host").body(()).unwrap().into_parts();
+        assert_eq!(request_origin(&parts, false), ("https".to_string(), "other-host".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_create_url_host_header() {
+        let resp = create_with(CreateState::default(), &[(header::HOST, "other-host:8081"), (header::ACCEPT, "text/plain")], r#"{"url": "http://example.com"}"#).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        assert_eq!(body_bytes, "http://other-host:8081/12345678");
+    }
+
+    #[test]
+    fn test_request_origin_forwarded_headers_untrusted() {
+        let headers = [("Host", "internal-pod:8081"), ("X-Forwarded-Proto", "https"), ("X-Forwarded-Host", "sho.rt")];
+        assert_eq!(origin(&headers, false), ("http".to_string(), "internal-pod:8081".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_create_url_alias_short_url_too_long() {
+        let resp = create_with_max_short_url_length(32, "sho.rt", r#"{"url": "http://example.com", "alias": "a-much-too-long-alias"}"#).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn create_key_only(req: Request<Body>) -> Response {
+        let mut db_layer = MockDatabase::new();
+        let mut key_generator = MockKeyGenerationService::new();
+
+        db_layer.expect_insert_key_if_absent().times(1).returning(|_, _| Ok(()));
+        key_generator.expect_generate_key().returning(|| Ok("12345678".to_string()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(key_generator),
+        ).await.unwrap();
+
+        create_url(State(state), req).await.into_response()
+    }
+
+    #[tokio::test]
+    async fn test_create_url_key_only_query() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("http://some-host/api/v1/create?format=key")
+            .header(header::HOST, "some-host")
+            .body(Body::from(r#"{"url": "http://example.com"}"#))
+            .unwrap();
+
+        let resp = create_key_only(req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 50_usize).await.unwrap();
+        assert_eq!(body_bytes, "12345678");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_key_only_header() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/v1/create")
+            .header("X-Response", "key")
+            .body(Body::from(r#"{"url": "http://example.com"}"#))
+            .unwrap();
+
+        let resp = create_key_only(req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 50_usize).await.unwrap();
+        assert_eq!(body_bytes, "12345678");
+    }
+
+    fn alias_request(body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("http://some-host/api/v1/create")
+            .header(header::HOST, "some-host")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_url_alias() {
+        // No key generator expectations are set, so generating a key panics.
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .withf(|key, target, ttl| key == "my-link_1" && target.url == "http://example.com" && ttl.is_none())
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let resp = create_url(State(state), alias_request(r#"{"url": "http://example.com", "alias": "my-link_1"}"#)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 200_usize).await.unwrap();
+        let body: CreateURLResponse = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(body.short_url, "http://some-host/my-link_1");
+        assert_eq!(body.key, "my-link_1");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_alias_conflict() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .times(1)
+            .returning(|key, _, _| Err(DatabaseError::AlreadyExists(key)));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let resp = create_url(State(state), alias_request(r#"{"url": "http://example.com", "alias": "taken"}"#)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "KEY_TAKEN");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_alias_if_none_match() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .returning(|key, _, _| if key == "taken" { Err(DatabaseError::AlreadyExists(key)) } else { Ok(()) });
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let conditional = |body: &str| {
+            let mut req = alias_request(body);
+            req.headers_mut().insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+            req
+        };
+
+        let resp = create_url(State(state.clone()), conditional(r#"{"url": "http://example.com", "alias": "taken"}"#)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        let resp = create_url(State(state), conditional(r#"{"url": "http://example.com", "alias": "free"}"#)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_alias_invalid() {
+        // No expectations are set, so generating or inserting a key panics.
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let too_long = format!(r#"{{"url": "http://example.com", "alias": "{}"}}"#, "a".repeat(MAX_KEY_LENGTH + 1));
+        for (body, code) in [
+            (r#"{"url": "http://example.com", "alias": "not/valid"}"#, "INVALID_ALIAS"),
+            (r#"{"url": "http://example.com", "alias": "with space"}"#, "INVALID_ALIAS"),
+            (r#"{"url": "http://example.com", "alias": ""}"#, "INVALID_ALIAS"),
+            (too_long.as_str(), "INVALID_ALIAS"),
+            (r#"{"url": "http://example.com", "alias": "once", "max_uses": 1}"#, "INVALID_REQUEST"),
+        ] {
+            let resp = create_url(State(state.clone()), alias_request(body)).await.into_response();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{body}");
+            assert_eq!(resp.headers()[ERROR_CODE_HEADER], code, "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_url_bad_req() {
+        let db_layer = MockDatabase::new();
+        let key_generator = MockKeyGenerationService::new();
+        let task_sender = MockTaskSender::new();
+
+        let state = AppState::new (
             Arc::new(db_layer),
             Arc::new(task_sender),
             Arc::new(key_generator),
@@ -192,13 +1624,87 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[tokio::test]
+    async fn test_create_url_schemeless_rejected() {
+        // No expectations are set, so generating or inserting a key panics.
+        let response = create_with(CreateState::unused(), &[], r#"{"url": "example.com/path"}"#).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn create_invalid_target(url: &str) -> (StatusCode, String) {
+        // No expectations are set, so generating or inserting a key panics.
+        let state = CreateState {
+            config: HandlerConfig { default_target_scheme: Some("https".to_string()), ..HandlerConfig::default() },
+            ..CreateState::unused()
+        };
+        let resp = create_with(state, &[], serde_json::json!({ "url": url }).to_string()).await;
+        let status = resp.status();
+        (status, error_message(resp).await)
+    }
+
+    #[tokio::test]
+    async fn test_create_url_invalid_targets() {
+        let (status, _) = create_invalid_target("/relative/path").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, msg) = create_invalid_target("javascript:alert(1)").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(msg, "Target URL scheme is not allowed: javascript");
+
+        let (status, msg) = create_invalid_target("").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(msg, "Target URL is empty");
+    }
+
+    #[tokio::test]
+    async fn test_create_url_trims_target() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_if_absent()
+            .withf(|key, target| key == "12345678" && target.url == "http://x.com")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let response = create_with(CreateState { db_layer, ..CreateState::default() }, &[], r#"{"url": "http://x.com\n"}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_temporary() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_if_absent()
+            .withf(|key, target| key == "12345678" && *target == RedirectTarget::temporary("http://example.com"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let response = create_with(CreateState { db_layer, ..CreateState::default() }, &[], r#"{"url": "http://example.com", "permanent": false}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_schemeless_default_scheme() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_if_absent()
+            .withf(|key, target| key == "12345678" && target.url == "https://example.com/path")
+            .returning(|_, _| Ok(()));
+        let state = CreateState {
+            db_layer,
+            config: HandlerConfig { default_target_scheme: Some("https".to_string()), ..HandlerConfig::default() },
+            ..CreateState::default()
+        };
+
+        let response = create_with(state, &[], r#"{"url": "example.com/path"}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
     #[tokio::test]
     async fn test_get_url() {
         // Mock AppState and its dependencies
         let mut db_layer = MockDatabase::new();
         let mut task_sender = MockTaskSender::new();
 
-        db_layer.expect_get_key_url().returning(|_| Ok("http://example.com".to_string()));
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
         task_sender.expect_send_task().returning(|_| Ok(()));
 
         let state = AppState::new (
@@ -208,7 +1714,7 @@ mod tests {
         ).await.unwrap();
 
         // Call the handler
-        let response = get_url(State(state), Path("12345678".to_string())).await;
+        let response = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await;
 
         // Assert the response
         assert!(response.is_ok());
@@ -218,27 +1724,1141 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_get_url_err_task() {
-        // Mock AppState and its dependencies
+    async fn test_get_url_error_codes() {
         let mut db_layer = MockDatabase::new();
-        let mut task_sender = MockTaskSender::new();
-
-        db_layer.expect_get_key_url().returning(|_| Ok("http://example.com".to_string()));
-        task_sender.expect_send_task().returning(|_| Err(anyhow!("Error while sending task")));
+        db_layer.expect_get_key_url().returning(|key| Err(match key {
+            "missing1" => DatabaseError::NotExist(key.to_string()),
+            _ => DatabaseError::UnavailableError("connection refused".to_string()),
+        }));
 
         let state = AppState::new (
             Arc::new(db_layer),
-            Arc::new(task_sender),
+            Arc::new(MockTaskSender::new()),
             Arc::new(MockKeyGenerationService::new()),
         ).await.unwrap();
 
-        // Call the handler
-        let response = get_url(State(state), Path("12345678".to_string())).await;
+        for (key, status, code) in [
+            ("missing1", StatusCode::NOT_FOUND, "KEY_NOT_FOUND"),
+            ("down1234", StatusCode::SERVICE_UNAVAILABLE, "DB_UNAVAILABLE"),
+        ] {
+            let resp = get_url(State(state.clone()), ValidatedKey(key.to_string()), TrackVisit(true)).await.into_response();
+            assert_eq!(resp.status(), status, "{key}");
+            assert_eq!(resp.headers()[ERROR_CODE_HEADER], code, "{key}");
+        }
+    }
 
-        // Assert the response
+    async fn get_stats_response(db_layer: MockDatabase, key: &str) -> Response {
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_STATS, get(get_stats))
+            .with_state(state);
+
+        let req = Request::builder().uri(format!("/api/v1/stats/{key}")).body(Body::empty()).unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_stats() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_get_visit_count().withf(|key| key == "12345678").returning(|_| Ok(42));
+
+        let resp = get_stats_response(db_layer, "12345678").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        let stats: KeyStats = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(stats, KeyStats {
+            key: "12345678".to_string(),
+            original_url: "http://example.com".to_string(),
+            visits: 42,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_get_stats_missing_key() {
+        // No visit count expectation is set, so reading it for a missing key panics.
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|key| Err(DatabaseError::NotExist(key.to_string())));
+
+        let resp = get_stats_response(db_layer, "missing1").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "KEY_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_get_url_temporary() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::temporary("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        for track in [true, false] {
+            let resp = get_url(State(state.clone()), ValidatedKey("12345678".to_string()), TrackVisit(track)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+            assert_eq!(resp.headers()["Location"], "http://example.com");
+        }
+    }
+
+    /// A task sender that never finishes sending, as with an unreachable task queue.
+    #[derive(Debug, Default)]
+    struct HangingTaskSender;
+
+    #[async_trait]
+    impl TaskSender for HangingTaskSender {
+        async fn send_task(&self, _task: rust_proto_pkg::generated::Task) -> anyhow::Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_url_hanging_task_sender() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(HangingTaskSender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        // The redirect does not wait for the task to be sent.
+        let resp = tokio::time::timeout(
+            Duration::from_secs(5),
+            get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)),
+        ).await.unwrap().unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(resp.headers()[header::LOCATION], "http://example.com");
+    }
+
+    #[tokio::test]
+    async fn test_get_url_err_task() {
+        // Mock AppState and its dependencies
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Err(anyhow!("Error while sending task")));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        // Call the handler
+        let response = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await;
+
+        // Assert the response
         assert!(response.is_ok());
         let resp: Response = response.unwrap().into_response();
         assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
         assert_eq!(resp.headers()["Location"], "http://example.com");
     }
+
+    #[tokio::test]
+    async fn test_get_url_blocked_key() {
+        // No expectations are set, so any database or task sender call panics.
+        let mut config = HandlerConfig::default();
+        config.blocked_keys.insert("12345678".to_string());
+        config.blocked_notice = "Removed following a legal request".to_string();
+
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        let resp = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+
+        assert_eq!(error_message(resp).await, "Removed following a legal request");
+    }
+
+    #[tokio::test]
+    async fn test_get_url_blocked_url() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://blocked.example.com")));
+
+        let mut config = HandlerConfig::default();
+        config.blocked_urls.insert("http://blocked.example.com".to_string());
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        let resp = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_not_blocked() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let mut config = HandlerConfig::default();
+        config.blocked_keys.insert("87654321".to_string());
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        let resp = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.into_response();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(resp.headers()["Location"], "http://example.com");
+    }
+
+    #[tokio::test]
+    async fn test_get_url_beacon_mode() {
+        // No task sender expectations are set, so sending a server-side task panics.
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+
+        let config = HandlerConfig {
+            analytics: AnalyticsMode::Beacon { url: "https://beacon.example.com/visit".to_string() },
+            ..HandlerConfig::default()
+        };
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        let resp = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 4096_usize).await.unwrap();
+        let body = String::from_utf8(body_bytes.to_vec()).unwrap();
+        assert!(body.contains("https://beacon.example.com/visit"));
+        assert!(body.contains("12345678"));
+        assert!(body.contains("http://example.com"));
+    }
+
+    fn visit_stream_config() -> HandlerConfig {
+        HandlerConfig {
+            visit_stream: VisitStreamConfig { token: Some("secret".to_string()), capacity: 16 },
+            ..HandlerConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_visits_receives_redirects() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(visit_stream_config());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let resp = stream_visits(State(state.clone()), headers).await.into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut body = resp.into_body().into_data_stream();
+
+        let redirect = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.unwrap();
+        assert_eq!(redirect.status(), StatusCode::PERMANENT_REDIRECT);
+
+        let frame = body.next().await.unwrap().unwrap();
+        let frame = String::from_utf8(frame.to_vec()).unwrap();
+        assert!(frame.contains("event: visit"));
+        assert!(frame.contains(r#""key":"12345678""#));
+        assert!(frame.contains(r#""url":"http://example.com""#));
+    }
+
+    #[tokio::test]
+    async fn test_stream_visits_unauthorized() {
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(visit_stream_config());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        let resp = stream_visits(State(state.clone()), headers).await.into_response();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = stream_visits(State(state), HeaderMap::new()).await.into_response();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_stream_visits_disabled() {
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let resp = stream_visits(State(state), HeaderMap::new()).await.into_response();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_trace_level() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let config = HandlerConfig {
+            trace_levels: TraceLevelConfig::parse("get_url=debug").unwrap(),
+            ..HandlerConfig::default()
+        };
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+        let debug_state = state.clone().with_config(config);
+
+        let subscriber = RecordingSubscriber::new(tracing::Level::INFO);
+        let spans = subscriber.spans.clone();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        get_url(State(debug_state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.unwrap();
+        assert!(!spans.lock().unwrap().contains(&"get_url"));
+
+        get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.unwrap();
+        assert!(spans.lock().unwrap().contains(&"get_url"));
+    }
+
+    #[tokio::test]
+    async fn test_get_url_oversized_key() {
+        // No expectations are set, so any database or task sender call panics.
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state);
+
+        let key = "a".repeat(MAX_KEY_LENGTH + 1);
+        let req = Request::builder()
+            .uri(format!("/{key}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "KEY_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_get_url_reserved_key() {
+        // No expectations are set, so any database or task sender call panics.
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(HandlerConfig {
+            keys: KeySpecConfig { reserved: ["admin".to_string()].into(), ..KeySpecConfig::default() },
+            ..HandlerConfig::default()
+        });
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state);
+
+        for uri in ["/admin", "/bad%20key"] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_url_invalid_key_shape() {
+        // No expectations are set, so any database or task sender call panics.
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(HandlerConfig {
+            keys: KeySpecConfig { min_length: 8, max_length: 8, ..KeySpecConfig::default() },
+            ..HandlerConfig::default()
+        });
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state);
+
+        // Scanner paths are rejected for their characters or their length.
+        for uri in ["/favicon.ico", "/wp-login.php", "/admin", "/123456789"] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert_eq!(resp.headers()[ERROR_CODE_HEADER], "KEY_NOT_FOUND");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_url_invalid_generated_key() {
+        // No database expectations are set, so inserting the key panics.
+        let mut state = CreateState::unused();
+        state.key_generator.expect_generate_key().returning(|| Ok("bad/key".to_string()));
+
+        let response = create_with(state, &[], r#"{"url": "http://example.com"}"#).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_max_length_key() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().times(1).returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state);
+
+        let key = "a".repeat(MAX_KEY_LENGTH);
+        let req = Request::builder()
+            .uri(format!("/{key}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_disabled() {
+        // No expectations are set, so any database call panics.
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let response = resolve_url(State(state), ValidatedKey("12345678".to_string())).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn get_url_location(upgrade_http_targets: bool, target: &'static str) -> String {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(move |_| Ok(RedirectTarget::permanent(target)));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(HandlerConfig {
+            upgrade_http_targets,
+            ..HandlerConfig::default()
+        });
+
+        let resp = get_url(State(state), ValidatedKey("12345678".to_string()), TrackVisit(false)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        resp.headers()[header::LOCATION].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_get_url_upgrade_http_targets() {
+        assert_eq!(get_url_location(true, "http://example.com/path?q=1").await, "https://example.com/path?q=1");
+        assert_eq!(get_url_location(true, "https://example.com/path").await, "https://example.com/path");
+        assert_eq!(get_url_location(true, "ftp://example.com/file").await, "ftp://example.com/file");
+    }
+
+    #[tokio::test]
+    async fn test_get_url_http_targets_not_upgraded() {
+        assert_eq!(get_url_location(false, "http://example.com/path?q=1").await, "http://example.com/path?q=1");
+    }
+
+    #[test]
+    fn test_upgrade_http() {
+        assert_eq!(upgrade_http("http://example.com/a?q=1"), Some("https://example.com/a?q=1".to_string()));
+        assert_eq!(upgrade_http("HTTP://example.com"), Some("https://example.com/".to_string()));
+        assert_eq!(upgrade_http("http://example.com:80/a"), Some("https://example.com/a".to_string()));
+        assert_eq!(upgrade_http("http://example.com:8080/a"), None);
+        assert_eq!(upgrade_http("https://example.com"), None);
+        assert_eq!(upgrade_http("mailto:someone@example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url_matches_get_url() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().times(2).returning(|_| Ok(RedirectTarget::permanent("http://example.com/path?q=1")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().times(1).returning(|_| Ok(()));
+
+        let config = HandlerConfig {
+            debug_endpoints: true,
+            ..HandlerConfig::default()
+        };
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .route(ROUTE_DEBUG_RESOLVE, get(resolve_url))
+            .with_state(state.clone());
+
+        let req = Request::builder()
+            .uri("/api/v1/debug/resolve/12345678")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        let resolution: Resolution = serde_json::from_slice(&body_bytes).unwrap();
+        assert_eq!(resolution.key, "12345678");
+        assert_eq!(resolution.target, "http://example.com/path?q=1");
+
+        let req = Request::builder()
+            .uri("/12345678")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.headers()[header::LOCATION], resolution.location.as_str());
+        state.wait_for_background().await;
+    }
+
+    #[tokio::test]
+    async fn test_ready_during_shutdown() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(READY_URL, get(get_ready))
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state.clone());
+
+        let ready = || Request::builder().uri(READY_URL).body(Body::empty()).unwrap();
+        let resp = app.clone().oneshot(ready()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        state.begin_shutdown();
+
+        let resp = app.clone().oneshot(ready()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let req = Request::builder().uri("/12345678").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    async fn create_with_extra_field(strict_request_validation: bool) -> Response {
+        let state = CreateState {
+            config: HandlerConfig { strict_request_validation, ..HandlerConfig::default() },
+            ..CreateState::default()
+        };
+        create_with(state, &[], r#"{"url": "http://example.com", "urls": "http://example.org"}"#).await
+    }
+
+    #[tokio::test]
+    async fn test_create_url_unknown_field_ignored() {
+        let resp = create_with_extra_field(false).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_unknown_field_strict() {
+        let resp = create_with_extra_field(true).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        assert!(String::from_utf8_lossy(&body_bytes).contains("unknown field `urls`"));
+    }
+
+    async fn create_with_noisy_body(lenient_json_parsing: bool, body: &'static str) -> Response {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_if_absent()
+            .withf(|_, target| target.url == "http://example.com")
+            .returning(|_, _| Ok(()));
+        let state = CreateState {
+            db_layer,
+            config: HandlerConfig { lenient_json_parsing, ..HandlerConfig::default() },
+            ..CreateState::default()
+        };
+        create_with(state, &[], body).await
+    }
+
+    #[tokio::test]
+    async fn test_create_url_lenient_json() {
+        for body in [
+            r#"{"url": "http://example.com"} trailing noise"#,
+            r#"garbage {"url": "http://example.com"}}"#,
+            r#"{"url": {"url": "http://example.com"}"#,
+        ] {
+            let resp = create_with_noisy_body(true, body).await;
+            assert_eq!(resp.status(), StatusCode::CREATED, "{body}");
+        }
+
+        let resp = create_with_noisy_body(true, r#"{"url": "http://example.com""#).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_strict_json() {
+        let resp = create_with_noisy_body(false, r#"{"url": "http://example.com"} trailing noise"#).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        assert!(String::from_utf8_lossy(&body_bytes).contains("trailing characters"));
+    }
+
+    #[tokio::test]
+    async fn test_create_url_max_uses() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_limited_key_if_absent()
+            .withf(|key, target, max_uses| key == "12345678" && target.url == "http://example.com" && *max_uses == 1)
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let response = create_with(CreateState { db_layer, ..CreateState::default() }, &[], r#"{"url": "http://example.com", "max_uses": 1}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_zero_max_uses() {
+        // No expectations are set, so any database or key generator call panics.
+        let response = create_with(CreateState::unused(), &[], r#"{"url": "http://example.com", "max_uses": 0}"#).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn create_with_ttl(db_layer: MockDatabase, body: &'static str) -> Response {
+        let limits = LimitsConfig { max_ttl: Duration::from_secs(86400), ..LimitsConfig::default() };
+        create_with(CreateState { db_layer, limits, ..CreateState::default() }, &[], body).await
+    }
+
+    #[tokio::test]
+    async fn test_create_url_default_ttl() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_if_absent().times(1).returning(|_, _| Ok(()));
+
+        let response = create_with_ttl(db_layer, r#"{"url": "http://example.com"}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_custom_ttl() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .withf(|key, target, ttl| key == "12345678" && target.url == "http://example.com" && *ttl == Some(Duration::from_secs(3600)))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let response = create_with_ttl(db_layer, r#"{"url": "http://example.com", "ttl_seconds": 3600}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_alias_ttl() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .withf(|key, _, ttl| key == "my-link" && *ttl == Some(Duration::from_secs(3600)))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let response = create_with_ttl(db_layer, r#"{"url": "http://example.com", "alias": "my-link", "ttl_seconds": 3600}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_max_ttl() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_insert_key_with_ttl_if_absent()
+            .withf(|_, _, ttl| *ttl == Some(Duration::from_secs(86400)))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let response = create_with_ttl(db_layer, r#"{"url": "http://example.com", "ttl_seconds": 86400}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_invalid_ttl() {
+        // No database expectations are set, so any insert panics.
+        for body in [
+            r#"{"url": "http://example.com", "ttl_seconds": 86401}"#,
+            r#"{"url": "http://example.com", "ttl_seconds": 0}"#,
+            r#"{"url": "http://example.com", "ttl_seconds": 60, "max_uses": 1}"#,
+        ] {
+            let response = create_with_ttl(MockDatabase::new(), body).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+        }
+    }
+
+    async fn create_with_user_agent(user_agent: Option<&str>, deny_empty: bool) -> StatusCode {
+        let config = HandlerConfig {
+            create_ua_denylist: BTreeSet::from(["scrapybot".to_string()]),
+            create_deny_empty_ua: deny_empty,
+            ..HandlerConfig::default()
+        };
+        let headers: Vec<_> = user_agent.map(|user_agent| (header::USER_AGENT, user_agent)).into_iter().collect();
+        create_with(CreateState { config, ..CreateState::default() }, &headers, r#"{"url": "http://example.com"}"#).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_create_url_denied_user_agent() {
+        assert_eq!(create_with_user_agent(Some("Mozilla/5.0 (compatible; ScrapyBot/2.1)"), false).await, StatusCode::FORBIDDEN);
+        assert_eq!(create_with_user_agent(None, true).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_create_url_allowed_user_agent() {
+        assert_eq!(create_with_user_agent(Some("Mozilla/5.0 (X11; Linux x86_64)"), false).await, StatusCode::CREATED);
+        assert_eq!(create_with_user_agent(None, false).await, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_one_time_link() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+        let mut seq = mockall::Sequence::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com").with_limit()));
+        db_layer.expect_consume_visit().times(1).in_sequence(&mut seq).returning(|_| Ok(()));
+        db_layer.expect_consume_visit().times(1).in_sequence(&mut seq)
+            .returning(|key| Err(DatabaseError::Exhausted(key.to_string())));
+        db_layer.expect_record_visit().times(1).returning(|_| Ok(()));
+        task_sender.expect_send_task().times(1).returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let response = get_url(State(state.clone()), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.into_response();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+
+        let response = get_url(State(state.clone()), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.into_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+        state.wait_for_background().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_after_warmup() {
+        let config = HandlerConfig {
+            readiness_warmup: std::time::Duration::from_secs(10),
+            ..HandlerConfig::default()
+        };
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        let response = get_ready(State(state.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        tokio::time::advance(std::time::Duration::from_secs(9)).await;
+        let response = get_ready(State(state.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        tokio::time::advance(std::time::Duration::from_secs(1)).await;
+        let response = get_ready(State(state)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_get_url_throttled() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|key| Ok(match key {
+            "flagged2" => RedirectTarget::permanent("http://flagged.com"),
+            _ => RedirectTarget::permanent("http://normal.com"),
+        }));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+        task_sender.expect_send_task().returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(HandlerConfig {
+            throttled_keys: ["flagged1".to_string()].into(),
+            throttled_urls: ["http://flagged.com".to_string()].into(),
+            throttle_delay: std::time::Duration::from_secs(2),
+            ..HandlerConfig::default()
+        });
+
+        for (key, delay) in [("flagged1", 2), ("flagged2", 2), ("normal12", 0)] {
+            let start = tokio::time::Instant::now();
+            let resp = get_url(State(state.clone()), ValidatedKey(key.to_string()), TrackVisit(true)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(start.elapsed(), std::time::Duration::from_secs(delay), "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_url_no_track() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com").with_limit()));
+        // Only the last, tracked request uses up a visit, is counted and sends a task.
+        db_layer.expect_consume_visit().times(1).returning(|_| Ok(()));
+        db_layer.expect_record_visit().times(1).returning(|_| Ok(()));
+        task_sender.expect_send_task().times(1).returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state.clone());
+
+        let requests = [
+            Request::builder().uri("/12345678").header("X-No-Track", "1").body(Body::empty()).unwrap(),
+            Request::builder().uri("/12345678?track=false").body(Body::empty()).unwrap(),
+            Request::builder().method("HEAD").uri("/12345678").body(Body::empty()).unwrap(),
+            Request::builder().uri("/12345678?track=true").body(Body::empty()).unwrap(),
+        ];
+        for req in requests {
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(resp.headers()[header::LOCATION], "http://example.com");
+        }
+        state.wait_for_background().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_url_no_track_keeps_limited_key() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com").with_limit()));
+        db_layer.expect_consume_visit().never();
+        db_layer.expect_record_visit().never();
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state.clone());
+
+        let requests = [
+            Request::builder().uri("/12345678").header("X-No-Track", "1").body(Body::empty()).unwrap(),
+            Request::builder().uri("/12345678?track=false").body(Body::empty()).unwrap(),
+            Request::builder().method("HEAD").uri("/12345678").body(Body::empty()).unwrap(),
+        ];
+        for req in requests {
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        }
+        state.wait_for_background().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_url_unlimited_key_skips_consume() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().never();
+        db_layer.expect_record_visit().times(1).returning(|_| Ok(()));
+        task_sender.expect_send_task().times(1).returning(|_| Ok(()));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let response = get_url(State(state.clone()), ValidatedKey("12345678".to_string()), TrackVisit(true)).await.into_response();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        state.wait_for_background().await;
+    }
+
+    #[tokio::test]
+    async fn test_head_url() {
+        let mut db_layer = MockDatabase::new();
+        let mut task_sender = MockTaskSender::new();
+
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().never();
+        db_layer.expect_record_visit().never();
+        task_sender.expect_send_task().never();
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(task_sender),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        // `get` routes also answer `HEAD` requests, without a body.
+        let app = Router::new()
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state.clone());
+
+        let req = Request::builder().method("HEAD").uri("/12345678").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(resp.headers()[header::LOCATION], "http://example.com");
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        assert!(body_bytes.is_empty());
+        state.wait_for_background().await;
+    }
+
+    #[tokio::test]
+    async fn test_preview_url() {
+        // No visit expectations are set, so consuming or recording the visit panics.
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com/path?q=1&r=2")));
+
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_PREVIEW, get(preview_url))
+            .with_state(state.clone());
+
+        let req = Request::builder().uri("/api/v1/preview/12345678").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert!(!resp.headers().contains_key(header::LOCATION));
+
+        let body_bytes = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+        let page = String::from_utf8(body_bytes.to_vec()).unwrap();
+        assert!(page.contains(r#"<a href="http://example.com/path?q=1&amp;r=2" rel="noreferrer">Continue</a>"#), "{page}");
+        state.wait_for_background().await;
+    }
+
+    /// A task sender that only sends once released.
+    #[derive(Debug, Default)]
+    struct SlowTaskSender {
+        release: Notify,
+        sent: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TaskSender for SlowTaskSender {
+        async fn send_task(&self, _task: rust_proto_pkg::generated::Task) -> anyhow::Result<()> {
+            self.release.notified().await;
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_url_does_not_wait_for_task_sender() {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+        db_layer.expect_consume_visit().returning(|_| Ok(()));
+        db_layer.expect_record_visit().returning(|_| Ok(()));
+
+        let task_sender = Arc::new(SlowTaskSender::default());
+        let state = AppState::new (
+            Arc::new(db_layer),
+            task_sender.clone(),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let response = tokio::time::timeout(
+            Duration::from_secs(1),
+            get_url(State(state.clone()), ValidatedKey("12345678".to_string()), TrackVisit(true)),
+        ).await.expect("get_url waited for the task sender");
+        assert_eq!(response.unwrap().status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(task_sender.sent.load(Ordering::SeqCst), 0);
+
+        // The visit is still recorded in the background.
+        task_sender.release.notify_one();
+        state.wait_for_background().await;
+        assert_eq!(task_sender.sent.load(Ordering::SeqCst), 1);
+    }
+
+    /// Returns a state whose dependencies are each checked once, and are healthy if set.
+    async fn health_checked_state(db: bool, task_sender: bool, key_generator: bool) -> AppState {
+        let mut db_layer = MockDatabase::new();
+        let mut sender = MockTaskSender::new();
+        let mut generator = MockKeyGenerationService::new();
+
+        db_layer.expect_health_check().times(1)
+            .returning(move || if db { Ok(()) } else { Err(DatabaseError::UnavailableError("down".to_string())) });
+        sender.expect_health_check().times(1)
+            .returning(move || if task_sender { Ok(()) } else { Err(anyhow!("down")) });
+        generator.expect_health_check().times(1)
+            .returning(move || if key_generator { Ok(()) } else { Err(GeneratorError::ConnectionError) });
+        AppState::new (
+            Arc::new(db_layer),
+            Arc::new(sender),
+            Arc::new(generator),
+        ).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ready_dependencies() {
+        let state = health_checked_state(true, true, true).await;
+
+        let response = get_ready_dependencies(State(state)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ready_dependencies_unavailable() {
+        let state = health_checked_state(false, true, false).await;
+
+        let response = get_ready_dependencies(State(state)).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error_message(response).await, "Unavailable: database, key generator");
+    }
+
+    /// A task sender and key generator whose health checks take `delay`.
+    #[derive(Debug)]
+    struct SlowHealthCheck {
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl TaskSender for SlowHealthCheck {
+        async fn send_task(&self, _task: rust_proto_pkg::generated::Task) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn health_check(&self) -> anyhow::Result<()> {
+            tokio::time::sleep(self.delay).await;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl KeyGenerationService for SlowHealthCheck {
+        async fn generate_key(&self) -> Result<String, GeneratorError> {
+            Ok("12345678".to_string())
+        }
+
+        async fn health_check(&self) -> Result<(), GeneratorError> {
+            tokio::time::sleep(self.delay).await;
+            Ok(())
+        }
+    }
+
+    async fn ready_with_slow_dependencies(task_sender_delay: Duration, key_generator_delay: Duration) -> (Response, Duration) {
+        let mut db_layer = MockDatabase::new();
+        db_layer.expect_health_check().returning(|| Ok(()));
+        let state = AppState::new (
+            Arc::new(db_layer),
+            Arc::new(SlowHealthCheck { delay: task_sender_delay }),
+            Arc::new(SlowHealthCheck { delay: key_generator_delay }),
+        ).await.unwrap().with_config(HandlerConfig {
+            health_check_timeout: Duration::from_secs(5),
+            ..HandlerConfig::default()
+        });
+
+        let start = tokio::time::Instant::now();
+        let response = get_ready_dependencies(State(state)).await.into_response();
+        (response, start.elapsed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_dependencies_checked_concurrently() {
+        let (response, elapsed) = ready_with_slow_dependencies(Duration::from_secs(3), Duration::from_secs(4)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The probe takes as long as the slowest check, not the sum of the checks.
+        assert_eq!(elapsed, Duration::from_secs(4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_dependencies_timeout() {
+        let (response, elapsed) = ready_with_slow_dependencies(Duration::from_secs(60), Duration::from_secs(3)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(elapsed, Duration::from_secs(5));
+        assert_eq!(error_message(response).await, "Unavailable: task sender");
+    }
+
+    #[tokio::test]
+    async fn test_health_route() {
+        // No expectations are set, so checking any dependency panics.
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap();
+
+        let app = Router::new()
+            .route(ROUTE_HEALTH, get(get_healthy))
+            .route(ROUTE_GET_URL, get(get_url))
+            .with_state(state);
+
+        let resp = app.oneshot(Request::builder().uri(ROUTE_HEALTH).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
 }
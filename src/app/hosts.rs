@@ -0,0 +1,114 @@
+//! This module restricts the hostnames the service answers on.
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::http::uri::Authority;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use tracing::log::debug;
+use crate::app::AppState;
+use crate::app::errors::{ApiError, ErrorCode};
+use crate::app::handlers::{HEALTHY_URL, READY_URL, ROUTE_HEALTH, ROUTE_READY};
+use crate::app::prometheus::ROUTE_METRICS;
+
+
+/// Returns the lowercase host of the request, without its port.
+fn request_host(req: &Request) -> Option<String> {
+    let authority = match req.headers().get(header::HOST).and_then(|h| h.to_str().ok()) {
+        Some(host) => host.parse::<Authority>().ok()?,
+        None => req.uri().authority()?.clone(),
+    };
+    Some(authority.host().to_ascii_lowercase())
+}
+
+
+/// This middleware only serves requests whose host is in the configured allowlist.
+///
+/// Requests on other hosts are redirected to the canonical host, keeping their path and query,
+/// or rejected with `421 Misdirected Request` if no canonical host is configured. Health and
+/// readiness checks and metrics scrapes, usually made by IP, are always served.
+pub async fn canonical_host(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let allowlist = &state.config.host_allowlist;
+    let path = req.uri().path();
+    if allowlist.is_empty() || [HEALTHY_URL, READY_URL, ROUTE_HEALTH, ROUTE_READY, ROUTE_METRICS].contains(&path) {
+        return next.run(req).await;
+    }
+
+    let host = request_host(&req);
+    if host.as_ref().is_some_and(|host| allowlist.contains(host)) {
+        return next.run(req).await;
+    }
+
+    debug!("Request on unlisted host {:?}", host);
+    match &state.config.canonical_host {
+        Some(canonical) => {
+            let path_and_query = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+            Redirect::permanent(&format!("{}{}", canonical.trim_end_matches('/'), path_and_query)).into_response()
+        },
+        None => ApiError::new(StatusCode::MISDIRECTED_REQUEST, ErrorCode::UnknownHost, "Unknown host").into_response(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tower::ServiceExt;
+    use crate::config::HandlerConfig;
+    use crate::database::MockDatabase;
+    use crate::key_generator::MockKeyGenerationService;
+    use crate::task_sender::MockTaskSender;
+
+    async fn app(canonical: Option<&str>) -> Router {
+        let config = HandlerConfig {
+            host_allowlist: BTreeSet::from(["sho.rt".to_string()]),
+            canonical_host: canonical.map(str::to_string),
+            ..HandlerConfig::default()
+        };
+        let state = AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap().with_config(config);
+
+        Router::new()
+            .route("/{url_key}", get(|| async { StatusCode::OK }))
+            .route(HEALTHY_URL, get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(state.clone(), canonical_host))
+            .with_state(state)
+    }
+
+    fn request(host: &str, uri: &str) -> Request {
+        Request::builder().uri(uri).header(header::HOST, host).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_listed_host_served() {
+        let resp = app(Some("https://sho.rt")).await.oneshot(request("SHO.RT:8081", "/12345678")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_unlisted_host_redirected() {
+        let resp = app(Some("https://sho.rt")).await.oneshot(request("other.example", "/12345678?q=1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(resp.headers()[header::LOCATION], "https://sho.rt/12345678?q=1");
+    }
+
+    #[tokio::test]
+    async fn test_unlisted_host_rejected_without_canonical() {
+        let resp = app(None).await.oneshot(request("other.example", "/12345678")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::MISDIRECTED_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_unlisted_host_health_served() {
+        let resp = app(Some("https://sho.rt")).await.oneshot(request("10.0.0.1:8081", HEALTHY_URL)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,109 @@
+//! This module renders the HTML pages served by the application handlers.
+
+
+/// Escapes a string so it can be safely embedded in HTML text or attribute values.
+pub fn escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+
+/// Encodes a string as a JavaScript string literal that can be embedded in a `<script>` block.
+fn js_string(input: &str) -> String {
+    // A JSON string is a valid JS string literal; `</` is escaped so it can't close the script.
+    serde_json::to_string(input)
+        .unwrap_or_else(|_| "\"\"".to_string())
+        .replace("</", "<\\/")
+}
+
+
+/// Renders the page served in beacon analytics mode.
+/// The page fires a beacon to `beacon_url` with the visited `key` and then navigates to `destination`.
+/// Clients without JavaScript fall back to a meta refresh.
+pub fn beacon_page(beacon_url: &str, key: &str, destination: &str) -> String {
+    let destination_attr = escape(destination);
+    let beacon_js = js_string(beacon_url);
+    let key_js = js_string(key);
+    let destination_js = js_string(destination);
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Redirecting</title>
+<noscript><meta http-equiv="refresh" content="0;url={destination_attr}"></noscript>
+</head>
+<body>
+<script>
+(function () {{
+  var beacon = {beacon_js} + "?key=" + encodeURIComponent({key_js});
+  if (navigator.sendBeacon) {{
+    navigator.sendBeacon(beacon);
+  }} else {{
+    new Image().src = beacon;
+  }}
+  window.location.replace({destination_js});
+}})();
+</script>
+<a href="{destination_attr}">Continue</a>
+</body>
+</html>
+"#)
+}
+
+
+/// Renders the page previewing where a key leads, with a link to continue to `destination`.
+pub fn preview_page(key: &str, destination: &str) -> String {
+    let key = escape(key);
+    let destination = escape(destination);
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="robots" content="noindex">
+<title>Preview of {key}</title>
+</head>
+<body>
+<p>This link leads to:</p>
+<p><code>{destination}</code></p>
+<a href="{destination}" rel="noreferrer">Continue</a>
+</body>
+</html>
+"#)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r#"<a href="x">'&'</a>"#), "&lt;a href=&quot;x&quot;&gt;&#x27;&amp;&#x27;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn test_preview_page_escapes_destination() {
+        let page = preview_page("abc", r#"http://example.com/"><script>alert(1)</script>"#);
+        assert!(!page.contains("<script>"));
+        assert!(page.contains(r#"<a href="http://example.com/&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;" rel="noreferrer">"#));
+    }
+
+    #[test]
+    fn test_beacon_page_escapes_script() {
+        let page = beacon_page("https://beacon.example.com/v", "abc", "http://example.com/</script><script>alert(1)");
+        assert!(!page.contains("</script><script>"));
+        assert!(page.contains(r#""https://beacon.example.com/v""#));
+    }
+}
@@ -0,0 +1,152 @@
+//! This module writes records as JSON lines to a shared sink, for the logs kept apart from the
+//! application logs.
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard, DEFAULT_BUFFERED_LINES_LIMIT};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+
+/// A shared sink receiving records, one JSON line each.
+#[derive(Clone)]
+pub struct JsonLines {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+
+impl fmt::Debug for JsonLines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonLines").finish_non_exhaustive()
+    }
+}
+
+
+impl JsonLines {
+    /// Creates a new `JsonLines` writing to `writer` from the calling thread.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self { writer: Arc::new(Mutex::new(Box::new(writer))) }
+    }
+
+    /// Creates a new `JsonLines` writing to `writer` from a background thread, so slow sinks only
+    /// block the callers once the buffer is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The sink receiving the records.
+    ///
+    /// # Returns
+    ///
+    /// The new `JsonLines` together with the guard that flushes it when dropped.
+    pub fn non_blocking(writer: impl Write + Send + 'static) -> (Self, WorkerGuard) {
+        Self::buffered(writer, DEFAULT_BUFFERED_LINES_LIMIT)
+    }
+
+    /// Creates a new `JsonLines` writing to `path` from a background thread. The file is appended
+    /// to, and rotated as set by `rotation`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file.
+    /// * `rotation` - How often a new file is started.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new `JsonLines` together with the guard that flushes it when
+    /// dropped, or an error if the path has no file name.
+    pub fn file(path: &Path, rotation: Rotation) -> Result<(Self, WorkerGuard)> {
+        let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(".".as_ref());
+        let file_name = path.file_name().ok_or_else(|| anyhow!("Invalid log file path: {}", path.display()))?;
+        Ok(Self::non_blocking(RollingFileAppender::new(rotation, directory, file_name)))
+    }
+
+    /// Writes `writer` from a background thread buffering up to `lines` records. Writes block once
+    /// the buffer is full instead of being dropped, so no record is ever lost.
+    fn buffered(writer: impl Write + Send + 'static, lines: usize) -> (Self, WorkerGuard) {
+        let (writer, guard) = NonBlockingBuilder::default()
+            .buffered_lines_limit(lines)
+            .lossy(false)
+            .finish(writer);
+        (Self::new(writer), guard)
+    }
+
+    /// Writes `record` as a JSON line.
+    pub fn write(&self, record: &impl Serialize) -> io::Result<()> {
+        let mut writer = self.writer.lock().map_err(|_| io::Error::other("The writer lock is poisoned"))?;
+        // The record and its newline go out in a single write, so records are never interleaved.
+        let mut buf = serde_json::to_vec(record)?;
+        buf.push(b'\n');
+        writer.write_all(&buf)
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A writer appending to a buffer shared with the test.
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuffer(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        /// Returns the records written so far.
+        pub(crate) fn entries(&self) -> Vec<serde_json::Value> {
+            let contents = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A writer keeping every write apart, and taking a while over each one.
+    #[derive(Clone, Default)]
+    struct SlowWrites(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Write for SlowWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(1));
+            self.0.lock().unwrap().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_one_line_per_write() {
+        let writes = SlowWrites::default();
+        let lines = JsonLines::new(writes.clone());
+        lines.write(&serde_json::json!({"key": "12345678"})).unwrap();
+        lines.write(&serde_json::json!({"key": "abcdefgh"})).unwrap();
+
+        let writes = writes.0.lock().unwrap();
+        assert_eq!(*writes, [b"{\"key\":\"12345678\"}\n".to_vec(), b"{\"key\":\"abcdefgh\"}\n".to_vec()]);
+    }
+
+    #[test]
+    fn test_full_buffer_drops_nothing() {
+        let writes = SlowWrites::default();
+        let (lines, guard) = JsonLines::buffered(writes.clone(), 1);
+        for i in 0..50 {
+            lines.write(&serde_json::json!({"i": i})).unwrap();
+        }
+        // Dropping the guard flushes the pending records.
+        drop(guard);
+
+        assert_eq!(writes.0.lock().unwrap().len(), 50);
+    }
+}
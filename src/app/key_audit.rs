@@ -0,0 +1,138 @@
+//! This module writes the audit log of created keys, one JSON line per key, to an append-only sink.
+use std::io;
+use std::time::SystemTime;
+use anyhow::Result;
+use axum::http::HeaderMap;
+use serde::Serialize;
+use tracing::log::warn;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::Rotation;
+use crate::app::json_lines::JsonLines;
+use crate::config::{KeyAuditConfig, KeyAuditDestination};
+
+
+/// An entry of the key audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyAuditEntry {
+    /// The created key.
+    pub key: String,
+    /// The time the key was created, in milliseconds since the Unix epoch.
+    pub timestamp: u128,
+    /// The owner of the key, read from the configured owner header. If `None`, the request had no owner.
+    pub owner: Option<String>,
+}
+
+
+/// A shared sink receiving an entry for every key created.
+#[derive(Debug, Clone)]
+pub struct KeyAudit {
+    lines: JsonLines,
+    owner_header: String,
+}
+
+
+impl KeyAudit {
+    /// Creates a new `KeyAudit` writing to `lines`.
+    ///
+    /// # Arguments
+    ///
+    /// * `lines` - The sink receiving the audit entries.
+    /// * `owner_header` - The request header carrying the owner of a created key.
+    pub fn new(lines: JsonLines, owner_header: impl Into<String>) -> Self {
+        Self { lines, owner_header: owner_header.into() }
+    }
+
+    /// Opens the sink described by `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The key audit configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `None` if the audit log is disabled, or the audit log together with
+    /// the guard that flushes it when dropped.
+    pub fn from_config(config: &KeyAuditConfig) -> Result<Option<(Self, WorkerGuard)>> {
+        let (lines, guard) = match &config.destination {
+            KeyAuditDestination::Disabled => return Ok(None),
+            KeyAuditDestination::Stdout => JsonLines::non_blocking(io::stdout()),
+            // The file is never rotated, so no entry is ever lost.
+            KeyAuditDestination::File(path) => JsonLines::file(path, Rotation::NEVER)?,
+        };
+        Ok(Some((Self::new(lines, config.owner_header.clone()), guard)))
+    }
+
+    /// Records the creation of `key` by the owner found in the request `headers`.
+    pub fn record(&self, key: &str, headers: &HeaderMap) {
+        let owner = headers
+            .get(self.owner_header.as_str())
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        self.write(&KeyAuditEntry {
+            key: key.to_string(),
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis(),
+            owner,
+        });
+    }
+
+    fn write(&self, entry: &KeyAuditEntry) {
+        if let Err(err) = self.lines.write(entry) {
+            warn!("Error writing key audit entry for {}: {}", entry.key, err);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use crate::app::json_lines::tests::SharedBuffer;
+
+    #[test]
+    fn test_key_audit_record() {
+        let buffer = SharedBuffer::default();
+        let audit = KeyAudit::new(JsonLines::new(buffer.clone()), "x-owner");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-owner", HeaderValue::from_static("team-a"));
+        audit.record("12345678", &headers);
+        audit.record("abcdefgh", &HeaderMap::new());
+
+        let entries = buffer.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["key"], "12345678");
+        assert_eq!(entries[0]["owner"], "team-a");
+        assert!(entries[0]["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(entries[1]["key"], "abcdefgh");
+        assert!(entries[1]["owner"].is_null());
+    }
+
+    #[test]
+    fn test_key_audit_file() {
+        let directory = std::env::temp_dir().join(format!("key-audit-test-{}", std::process::id()));
+        let path = directory.join("keys.log");
+        let config = KeyAuditConfig { destination: KeyAuditDestination::File(path.clone()), owner_header: "x-owner".to_string() };
+
+        // Entries of a previous run are kept, as the file is only ever appended to.
+        for key in ["12345678", "abcdefgh"] {
+            let (audit, guard) = KeyAudit::from_config(&config).unwrap().unwrap();
+            audit.record(key, &HeaderMap::new());
+            drop(guard);
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let keys: Vec<serde_json::Value> = contents.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["key"].clone())
+            .collect();
+        assert_eq!(keys, ["12345678", "abcdefgh"]);
+    }
+
+    #[test]
+    fn test_key_audit_disabled() {
+        let config = KeyAuditConfig { destination: KeyAuditDestination::Disabled, owner_header: "x-owner".to_string() };
+        assert!(KeyAudit::from_config(&config).unwrap().is_none());
+    }
+}
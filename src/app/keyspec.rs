@@ -0,0 +1,145 @@
+//! This module contains the rules a shortened URL key must follow.
+use std::collections::BTreeSet;
+use thiserror::Error;
+use crate::config::KeySpecConfig;
+
+
+/// The reasons a key can be invalid.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum KeyError {
+    /// The key is shorter than the minimum length.
+    #[error("Key must be at least {0} characters long")]
+    TooShort(usize),
+    /// The key is longer than the maximum length.
+    #[error("Key must be at most {0} characters long")]
+    TooLong(usize),
+    /// The key contains a character outside the alphabet.
+    #[error("Key contains an invalid character: {0:?}")]
+    InvalidCharacter(char),
+    /// The key is a reserved word.
+    #[error("Key is reserved: {0}")]
+    Reserved(String),
+}
+
+
+/// What a valid key is: its alphabet, its length bounds and the words it cannot be.
+///
+/// The same spec checks generated keys, keys requested in the path and vanity aliases, so
+/// they all agree on what a valid key is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySpec {
+    alphabet: BTreeSet<char>,
+    min_length: usize,
+    max_length: usize,
+    reserved: BTreeSet<String>,
+}
+
+
+impl KeySpec {
+    /// Creates a new `KeySpec`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the key format.
+    ///
+    /// # Returns
+    ///
+    /// A new `KeySpec` instance.
+    pub fn new(config: &KeySpecConfig) -> Self {
+        Self {
+            alphabet: config.alphabet.chars().collect(),
+            min_length: config.min_length,
+            max_length: config.max_length,
+            reserved: config.reserved.iter().map(|word| word.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    /// Checks that a key follows the spec.
+    /// Lengths are counted in characters, and reserved words are matched case-insensitively.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to check.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is either empty or the first rule the key breaks.
+    pub fn validate(&self, key: &str) -> Result<(), KeyError> {
+        let length = key.chars().count();
+        if length < self.min_length {
+            return Err(KeyError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(KeyError::TooLong(self.max_length));
+        }
+
+        if let Some(ch) = key.chars().find(|ch| !self.alphabet.contains(ch)) {
+            return Err(KeyError::InvalidCharacter(ch));
+        }
+
+        if self.reserved.contains(&key.to_ascii_lowercase()) {
+            return Err(KeyError::Reserved(key.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Default for KeySpec {
+    fn default() -> Self {
+        Self::new(&KeySpecConfig::default())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_generator::MAX_KEY_LENGTH;
+
+    fn spec() -> KeySpec {
+        KeySpec::new(&KeySpecConfig {
+            alphabet: "abc123-_".to_string(),
+            min_length: 2,
+            max_length: 4,
+            reserved: BTreeSet::from(["ABC".to_string()]),
+        })
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        assert_eq!(spec().validate("ab"), Ok(()));
+        assert_eq!(spec().validate("a-_1"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_length() {
+        assert_eq!(spec().validate(""), Err(KeyError::TooShort(2)));
+        assert_eq!(spec().validate("a"), Err(KeyError::TooShort(2)));
+        assert_eq!(spec().validate("abcab"), Err(KeyError::TooLong(4)));
+        // Lengths are counted in characters, not bytes.
+        assert_eq!(KeySpec::new(&KeySpecConfig { alphabet: "é".to_string(), ..KeySpecConfig::default() }).validate("éé"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_alphabet() {
+        assert_eq!(spec().validate("ab/c"), Err(KeyError::InvalidCharacter('/')));
+        assert_eq!(spec().validate("aB"), Err(KeyError::InvalidCharacter('B')));
+        assert_eq!(spec().validate("a c"), Err(KeyError::InvalidCharacter(' ')));
+    }
+
+    #[test]
+    fn test_validate_reserved() {
+        assert_eq!(spec().validate("abc"), Err(KeyError::Reserved("abc".to_string())));
+        assert_eq!(spec().validate("abc1"), Ok(()));
+    }
+
+    #[test]
+    fn test_default_spec() {
+        let spec = KeySpec::default();
+        assert_eq!(spec.validate("aZ09-_"), Ok(()));
+        assert_eq!(spec.validate(&"a".repeat(MAX_KEY_LENGTH)), Ok(()));
+        assert_eq!(spec.validate(&"a".repeat(MAX_KEY_LENGTH + 1)), Err(KeyError::TooLong(MAX_KEY_LENGTH)));
+    }
+}
@@ -0,0 +1,132 @@
+//! This module sheds the requests beyond the in-flight budget with `503 Service Unavailable`.
+//! The budget also bounds the database operations of requests, and backpressure is applied
+//! before any work is accepted. Visits recorded in the background after the response are
+//! bounded by the background task limit instead.
+use std::sync::Arc;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio::sync::Semaphore;
+use tracing::log::debug;
+use crate::app::errors::{ApiError, ErrorCode};
+use crate::app::handlers::{HEALTHY_URL, READY_URL, ROUTE_HEALTH, ROUTE_READY};
+use crate::app::prometheus::ROUTE_METRICS;
+
+
+/// The budget of requests, and thus of their database operations, in flight at once.
+#[derive(Clone, Debug)]
+pub struct InFlightLimit {
+    permits: Arc<Semaphore>,
+}
+
+
+impl InFlightLimit {
+    /// Creates a new `InFlightLimit` allowing `max_in_flight` requests at once.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self { permits: Arc::new(Semaphore::new(max_in_flight)) }
+    }
+}
+
+
+/// This middleware serves a request only while the in-flight budget has room, and rejects it
+/// with `503 Service Unavailable` otherwise, instead of queueing it.
+/// Health and readiness checks and metrics scrapes are always served, so probes do not restart
+/// or hide a busy instance.
+pub async fn shed_load(State(limit): State<InFlightLimit>, req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if [HEALTHY_URL, READY_URL, ROUTE_HEALTH, ROUTE_READY, ROUTE_METRICS].contains(&path) {
+        return next.run(req).await;
+    }
+
+    // The permit is held until the response is ready, so it covers every database operation of the request.
+    let Ok(_permit) = limit.permits.clone().try_acquire_owned() else {
+        debug!("Shedding request to {}, too many requests in flight", path);
+        return (
+            [(header::RETRY_AFTER, "1")],
+            ApiError::new(StatusCode::SERVICE_UNAVAILABLE, ErrorCode::RateLimited, "Too many requests in flight"),
+        ).into_response();
+    };
+    next.run(req).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    /// Tracks the database operations in flight, and blocks them until released.
+    #[derive(Default)]
+    struct SlowDatabase {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        started: AtomicUsize,
+        release: Notify,
+    }
+
+    impl SlowDatabase {
+        async fn query(&self) {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            self.started.fetch_add(1, Ordering::SeqCst);
+            self.release.notified().await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn app(db: Arc<SlowDatabase>, max_in_flight: usize) -> Router {
+        Router::new()
+            .route("/{url_key}", get(move || async move {
+                db.query().await;
+                StatusCode::OK
+            }))
+            .route(ROUTE_HEALTH, get(|| async { StatusCode::OK }))
+            .layer(from_fn_with_state(InFlightLimit::new(max_in_flight), shed_load))
+    }
+
+    fn request(uri: &str) -> Request {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shed_load_under_load() {
+        let db = Arc::new(SlowDatabase::default());
+        let app = app(db.clone(), 2);
+
+        let accepted: Vec<_> = (0..2).map(|_| tokio::spawn(app.clone().oneshot(request("/12345678")))).collect();
+        while db.started.load(Ordering::SeqCst) < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // The budget is used up, so further requests are shed before reaching the database.
+        for _ in 0..3 {
+            let resp = app.clone().oneshot(request("/12345678")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(resp.headers()[header::RETRY_AFTER], "1");
+        }
+        let resp = app.clone().oneshot(request(ROUTE_HEALTH)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        db.release.notify_waiters();
+        for handle in accepted {
+            assert_eq!(handle.await.unwrap().unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(db.started.load(Ordering::SeqCst), 2);
+        assert_eq!(db.max_in_flight.load(Ordering::SeqCst), 2);
+
+        // Finished requests return their permits.
+        let pending = tokio::spawn(app.oneshot(request("/12345678")));
+        while db.started.load(Ordering::SeqCst) < 3 {
+            tokio::task::yield_now().await;
+        }
+        db.release.notify_waiters();
+        assert_eq!(pending.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+}
@@ -1,9 +1,41 @@
 //! This module contains the application state and handlers for the redirection service.
 
+pub(crate) mod access_log;
+pub(crate) mod auth;
+pub(crate) mod brand;
+pub(crate) mod deprecation;
+pub(crate) mod error_pages;
+pub(crate) mod errors;
+pub(crate) mod extractors;
 pub(crate) mod handlers;
+pub(crate) mod hosts;
+pub(crate) mod html;
+pub(crate) mod json_lines;
+pub(crate) mod key_audit;
+pub(crate) mod keyspec;
+pub(crate) mod load_shed;
+pub(crate) mod prometheus;
+pub(crate) mod responses;
+pub(crate) mod routes;
+pub(crate) mod spans;
+pub(crate) mod target;
+pub(crate) mod visits;
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use anyhow::Result;
+use metrics::counter;
+use tokio::sync::{broadcast, Semaphore};
+use tokio::time::Instant;
+use tokio_util::task::TaskTracker;
+use tracing::Instrument;
+use tracing::log::warn;
+use crate::app::key_audit::KeyAudit;
+use crate::app::keyspec::KeySpec;
+use crate::app::prometheus::BACKGROUND_TASKS_DROPPED;
+use crate::app::visits::VisitEvent;
+use crate::config::{HandlerConfig, LimitsConfig};
 use crate::database::Database;
 use crate::key_generator::KeyGenerationService;
 use crate::task_sender::TaskSender;
@@ -13,6 +45,15 @@ pub(crate) struct AppState {
     db_layer: Arc<dyn Database>,
     task_sender: Arc<dyn TaskSender>,
     key_generator: Arc<dyn KeyGenerationService>,
+    config: Arc<HandlerConfig>,
+    limits: Arc<LimitsConfig>,
+    key_spec: Arc<KeySpec>,
+    key_audit: Option<KeyAudit>,
+    visits: broadcast::Sender<VisitEvent>,
+    shutting_down: Arc<AtomicBool>,
+    started_at: Instant,
+    background: TaskTracker,
+    background_slots: Arc<Semaphore>,
 }
 
 
@@ -22,6 +63,170 @@ impl AppState {
         task_sender: Arc<dyn TaskSender>,
         key_generator: Arc<dyn KeyGenerationService>,
     ) -> Result<Self> {
-        Ok(AppState { db_layer, task_sender, key_generator })
+        let config = HandlerConfig::default();
+        let limits = LimitsConfig::default();
+        let (visits, _) = broadcast::channel(config.visit_stream.capacity);
+        Ok(AppState {
+            db_layer,
+            task_sender,
+            key_generator,
+            key_spec: Arc::new(KeySpec::new(&config.keys)),
+            config: Arc::new(config),
+            background_slots: Arc::new(Semaphore::new(limits.max_background_tasks)),
+            limits: Arc::new(limits),
+            key_audit: None,
+            visits,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            started_at: Instant::now(),
+            background: TaskTracker::new(),
+        })
+    }
+
+    /// Replaces the handler configuration, which defaults to `HandlerConfig::default()`.
+    pub fn with_config(mut self, config: HandlerConfig) -> Self {
+        (self.visits, _) = broadcast::channel(config.visit_stream.capacity);
+        self.key_spec = Arc::new(KeySpec::new(&config.keys));
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Replaces the limits on the requests, which default to `LimitsConfig::default()`.
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.background_slots = Arc::new(Semaphore::new(limits.max_background_tasks));
+        self.limits = Arc::new(limits);
+        self
+    }
+
+    /// Sets the audit log recording every key created, which is disabled by default.
+    pub fn with_key_audit(mut self, key_audit: KeyAudit) -> Self {
+        self.key_audit = Some(key_audit);
+        self
+    }
+
+    /// Marks the service as shutting down, so it reports not-ready while in-flight requests drain.
+    /// Every clone of the state shares the flag.
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once `begin_shutdown` has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Runs best-effort work, such as recording a visit, in the background so the response is
+    /// not held back by it. The work keeps the current tracing span.
+    ///
+    /// At most `max_background_tasks` run at once, so a slow dependency cannot pile up tasks
+    /// without bound. Beyond it, the work is dropped and counted.
+    pub fn spawn_background(&self, work: impl Future<Output = ()> + Send + 'static) {
+        let Ok(slot) = self.background_slots.clone().try_acquire_owned() else {
+            warn!("Too many background tasks running, dropping one");
+            counter!(BACKGROUND_TASKS_DROPPED).increment(1);
+            return;
+        };
+        self.background.spawn(async move {
+            work.await;
+            drop(slot);
+        }.in_current_span());
+    }
+
+    /// Waits until the background work, including any spawned while waiting, is done.
+    pub async fn wait_for_background(&self) {
+        self.background.close();
+        self.background.wait().await;
+    }
+
+    /// Waits until the background work is done, for at most `timeout`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait for the background work.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the background work is done, `false` if some was still running at the timeout.
+    pub async fn drain_background(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.wait_for_background()).await.is_ok()
+    }
+
+    /// Returns `true` while the configured readiness warmup, counted from the creation of the
+    /// state, has not elapsed yet.
+    pub fn is_warming_up(&self) -> bool {
+        self.started_at.elapsed() < self.config.readiness_warmup
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MockDatabase;
+    use crate::key_generator::MockKeyGenerationService;
+    use crate::task_sender::MockTaskSender;
+
+    async fn state() -> AppState {
+        AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drain_background_finishes_work() {
+        let state = state().await;
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = done.clone();
+        state.spawn_background(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            finished.store(true, Ordering::SeqCst);
+        });
+
+        state.begin_shutdown();
+        assert!(state.drain_background(Duration::from_secs(5)).await);
+        assert!(done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_background_drops_work_beyond_limit() {
+        let state = state().await.with_limits(LimitsConfig { max_background_tasks: 1, ..LimitsConfig::default() });
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        state.spawn_background(async move {
+            let _ = released.await;
+        });
+
+        let dropped = Arc::new(AtomicBool::new(true));
+        let ran = dropped.clone();
+        state.spawn_background(async move { ran.store(false, Ordering::SeqCst) });
+
+        release.send(()).unwrap();
+        state.wait_for_background().await;
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_background_frees_slots() {
+        let state = state().await.with_limits(LimitsConfig { max_background_tasks: 1, ..LimitsConfig::default() });
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        for _ in 0..3 {
+            let runs = runs.clone();
+            state.spawn_background(async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+            });
+            // Each task is done, and its slot freed, before the next one is spawned.
+            while !state.background.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_drain_background_timeout() {
+        let state = state().await;
+        state.spawn_background(std::future::pending());
+
+        assert!(!state.drain_background(Duration::from_millis(50)).await);
     }
 }
@@ -0,0 +1,126 @@
+//! This module records the Prometheus metrics of the service and serves them on `/metrics`.
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use tokio::time::Instant;
+use crate::database::DatabaseError;
+
+
+/// The route serving the metrics in the Prometheus text format.
+pub const ROUTE_METRICS: &str = "/metrics";
+
+/// The counter of requests to the create endpoint.
+pub const CREATE_URL_REQUESTS: &str = "create_url_requests_total";
+
+/// The counter of redirects served by the get endpoint.
+pub const GET_URL_REDIRECTS: &str = "get_url_redirects_total";
+
+/// The counter of lookups of keys that do not exist.
+pub const KEYS_NOT_FOUND: &str = "keys_not_found_total";
+
+/// The counter of best-effort background tasks dropped as too many were running.
+pub const BACKGROUND_TASKS_DROPPED: &str = "background_tasks_dropped_total";
+
+/// The histogram of request latencies, labelled by route.
+pub const REQUEST_DURATION: &str = "http_request_duration_seconds";
+
+/// The buckets of the request latency histogram, in seconds.
+const REQUEST_DURATION_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+
+/// Returns the builder of the Prometheus recorder, with the latency histogram buckets set.
+pub fn prometheus_builder() -> Result<PrometheusBuilder, BuildError> {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(REQUEST_DURATION.to_string()), REQUEST_DURATION_BUCKETS)
+}
+
+
+/// Installs the Prometheus recorder as the global metrics recorder.
+///
+/// # Returns
+///
+/// A `Result` which is either the handle rendering the recorded metrics or a `BuildError`
+/// if a recorder is already installed.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    prometheus_builder()?.install_recorder()
+}
+
+
+/// Counts the lookup of a key that does not exist.
+pub fn record_database_error(err: &DatabaseError) {
+    if matches!(err, DatabaseError::NotExist(_)) {
+        counter!(KEYS_NOT_FOUND).increment(1);
+    }
+}
+
+
+/// This middleware records the latency of each request, labelled by its matched route.
+/// Requests to `/metrics` are not recorded, so scrapes do not skew the latencies.
+pub async fn track_latency(req: Request, next: Next) -> Response {
+    let route = req.extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .filter(|path| path != ROUTE_METRICS);
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+
+    if let Some(route) = route {
+        histogram!(REQUEST_DURATION, "route" => route).record(start.elapsed().as_secs_f64());
+    }
+    response
+}
+
+
+/// This handler renders the recorded metrics in the Prometheus text format.
+pub async fn get_metrics(State(handle): State<PrometheusHandle>) -> String {
+    handle.render()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_track_latency() {
+        let recorder = prometheus_builder().unwrap().build_recorder();
+        let handle = recorder.handle();
+        // The test runtime is single-threaded, so the local recorder sees every request.
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let app = Router::new()
+            .route("/{url_key}", get(|| async { StatusCode::OK }))
+            .route(ROUTE_METRICS, get(get_metrics))
+            .route_layer(from_fn(track_latency))
+            .with_state(handle.clone());
+
+        for uri in ["/abc", ROUTE_METRICS] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let rendered = handle.render();
+        assert!(rendered.contains(&format!("{REQUEST_DURATION}_count{{route=\"/{{url_key}}\"}} 1")));
+        assert!(!rendered.contains(&format!("route=\"{ROUTE_METRICS}\"")));
+    }
+
+    #[test]
+    fn test_record_database_error() {
+        let recorder = prometheus_builder().unwrap().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            record_database_error(&DatabaseError::NotExist("abc".to_string()));
+            record_database_error(&DatabaseError::UnavailableError("down".to_string()));
+        });
+        assert!(handle.render().contains(&format!("{KEYS_NOT_FOUND} 1")));
+    }
+}
@@ -0,0 +1,62 @@
+//! This module contains the response types setting an explicit content type and charset.
+use axum::Json;
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+
+/// The content type of JSON responses.
+pub const JSON_CONTENT_TYPE: &str = "application/json; charset=utf-8";
+
+/// The content type of HTML responses.
+pub const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+
+
+/// A JSON response with the `application/json; charset=utf-8` content type.
+#[derive(Debug, Clone)]
+pub struct JsonBody<T>(pub T);
+
+
+impl<T: Serialize> IntoResponse for JsonBody<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.0).into_response();
+        // A value that fails to serialize returns a plain text error, which keeps its content type.
+        if response.status().is_success() {
+            response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE));
+        }
+        response
+    }
+}
+
+
+/// An HTML response with the `text/html; charset=utf-8` content type.
+#[derive(Debug, Clone)]
+pub struct HtmlBody(pub String);
+
+
+impl IntoResponse for HtmlBody {
+    fn into_response(self) -> Response {
+        ([(header::CONTENT_TYPE, HTML_CONTENT_TYPE)], self.0).into_response()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_json_body_content_type() {
+        let response = JsonBody(BTreeMap::from([("key", "abc")])).into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], JSON_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn test_html_body_content_type() {
+        let response = HtmlBody("<p>abc</p>".to_string()).into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], HTML_CONTENT_TYPE);
+    }
+}
@@ -0,0 +1,148 @@
+//! This module builds the routers of the service: the public one, serving the redirects and the
+//! API, and the admin one, serving the metrics, probes and debug endpoints on a separate port.
+use std::sync::Arc;
+use axum::Router;
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::routing::{get, post};
+use metrics_exporter_prometheus::PrometheusHandle;
+use crate::app::AppState;
+use crate::app::auth::require_api_key;
+use crate::app::deprecation::deprecation_headers;
+use crate::app::handlers::{create_url, create_url_bulk, get_healthy, get_ready, get_ready_dependencies, get_stats, get_url, preview_url, resolve_url, stream_visits, HEALTHY_URL, READY_URL, ROUTE_CREATE_URL, ROUTE_CREATE_URL_BULK, ROUTE_DEBUG_RESOLVE, ROUTE_GET_URL, ROUTE_HEALTH, ROUTE_PREVIEW, ROUTE_READY, ROUTE_STATS, ROUTE_VISIT_STREAM};
+use crate::app::hosts::canonical_host;
+use crate::app::prometheus::{get_metrics, track_latency, ROUTE_METRICS};
+use crate::config::AuthConfig;
+
+
+/// The route for the liveness probe of the admin server.
+pub const ROUTE_HEALTHZ: &str = "/healthz";
+
+
+/// Returns the routes for operators rather than clients, served on the admin server if it runs.
+fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route(ROUTE_VISIT_STREAM, get(stream_visits))
+        .route(ROUTE_DEBUG_RESOLVE, get(resolve_url))
+}
+
+
+/// Returns the route serving the metrics, which is not timed so scrapes do not skew the latencies.
+fn metrics_routes<S: Clone + Send + Sync + 'static>(metrics_handle: PrometheusHandle) -> Router<S> {
+    Router::new()
+        .route(ROUTE_METRICS, get(get_metrics))
+        .with_state(metrics_handle)
+}
+
+
+/// Builds the router of the public server.
+///
+/// # Arguments
+///
+/// * `state` - The state shared by the handlers.
+/// * `auth` - The authentication of the create and stats routes.
+/// * `metrics_handle` - The handle rendering the metrics. If `None`, the metrics and the admin
+///   routes are left out, as the admin server serves them.
+///
+/// # Returns
+///
+/// The public `Router`.
+pub fn public_router(state: AppState, auth: &AuthConfig, metrics_handle: Option<PrometheusHandle>) -> Router {
+    // Only the create and stats routes require an API key, redirects stay public.
+    let create_routes = Router::new()
+        .route(ROUTE_CREATE_URL, post(create_url))
+        .route(ROUTE_CREATE_URL_BULK, post(create_url_bulk))
+        .route(ROUTE_STATS, get(get_stats))
+        .route_layer(from_fn_with_state(Arc::new(auth.clone()), require_api_key));
+    let mut app = Router::new()
+        .merge(create_routes)
+        .route(ROUTE_GET_URL, get(get_url))
+        .route(ROUTE_PREVIEW, get(preview_url))
+        .route(HEALTHY_URL, get(get_healthy))
+        .route(READY_URL, get(get_ready))
+        .route(ROUTE_HEALTH, get(get_healthy))
+        .route(ROUTE_READY, get(get_ready_dependencies));
+    if metrics_handle.is_some() {
+        app = app.merge(admin_routes());
+    }
+    // Only the routes above are timed, so `/metrics` is left out of its own latencies.
+    app = app.route_layer(from_fn(track_latency));
+    if let Some(metrics_handle) = metrics_handle {
+        app = app.merge(metrics_routes(metrics_handle));
+    }
+    app
+        .layer(from_fn_with_state(state.clone(), deprecation_headers))
+        .layer(from_fn_with_state(state.clone(), canonical_host))
+        .with_state(state)
+}
+
+
+/// Builds the router of the admin server. Its requests are not timed, so probes and scrapes do
+/// not skew the latencies of the public traffic.
+///
+/// # Arguments
+///
+/// * `state` - The state shared with the public server.
+/// * `metrics_handle` - The handle rendering the metrics.
+///
+/// # Returns
+///
+/// The admin `Router`.
+pub fn admin_router(state: AppState, metrics_handle: PrometheusHandle) -> Router {
+    Router::new()
+        .route(ROUTE_HEALTHZ, get(get_healthy))
+        .route(ROUTE_HEALTH, get(get_healthy))
+        .route(READY_URL, get(get_ready))
+        .route(ROUTE_READY, get(get_ready_dependencies))
+        .merge(admin_routes())
+        .merge(metrics_routes(metrics_handle))
+        .with_state(state)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+    use crate::app::prometheus::prometheus_builder;
+    use crate::database::MockDatabase;
+    use crate::key_generator::MockKeyGenerationService;
+    use crate::task_sender::MockTaskSender;
+
+    async fn state() -> AppState {
+        AppState::new (
+            Arc::new(MockDatabase::new()),
+            Arc::new(MockTaskSender::new()),
+            Arc::new(MockKeyGenerationService::new()),
+        ).await.unwrap()
+    }
+
+    async fn status(app: Router, uri: &str) -> StatusCode {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_on_admin_router_only() {
+        let handle = prometheus_builder().unwrap().build_recorder().handle();
+        let state = state().await;
+        let admin = admin_router(state.clone(), handle);
+        let public = public_router(state, &AuthConfig::default(), None);
+
+        assert_eq!(status(admin.clone(), ROUTE_METRICS).await, StatusCode::OK);
+        assert_eq!(status(admin, ROUTE_HEALTHZ).await, StatusCode::OK);
+        // `/metrics` would otherwise be looked up as a key.
+        assert_eq!(status(public.clone(), ROUTE_METRICS).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(public, ROUTE_HEALTH).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_on_public_router_without_admin_server() {
+        let handle = prometheus_builder().unwrap().build_recorder().handle();
+        let public = public_router(state().await, &AuthConfig::default(), Some(handle));
+
+        assert_eq!(status(public, ROUTE_METRICS).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,83 @@
+//! This module provides spans whose level is chosen at runtime, so the tracing verbosity of
+//! each handler can be tuned through configuration instead of a fixed `#[instrument]` level.
+
+
+/// Creates a span at a level only known at runtime.
+///
+/// `tracing` requires span levels to be constants, so this expands to one `span!` call per level.
+///
+/// # Example
+///
+/// ```ignore
+/// let span = handler_span!(level, "get_url", url_key = %url_key);
+/// ```
+macro_rules! handler_span {
+    ($level:expr, $name:literal $(, $($fields:tt)*)?) => {
+        match $level {
+            tracing::Level::ERROR => tracing::span!(target: $name, tracing::Level::ERROR, $name $(, $($fields)*)?),
+            tracing::Level::WARN => tracing::span!(target: $name, tracing::Level::WARN, $name $(, $($fields)*)?),
+            tracing::Level::INFO => tracing::span!(target: $name, tracing::Level::INFO, $name $(, $($fields)*)?),
+            tracing::Level::DEBUG => tracing::span!(target: $name, tracing::Level::DEBUG, $name $(, $($fields)*)?),
+            tracing::Level::TRACE => tracing::span!(target: $name, tracing::Level::TRACE, $name $(, $($fields)*)?),
+        }
+    };
+}
+
+pub(crate) use handler_span;
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::subscriber::Interest;
+    use tracing::{Event, Level, Metadata, Subscriber};
+
+    /// A subscriber that records the names of the spans enabled at or above `max_level`.
+    pub(crate) struct RecordingSubscriber {
+        max_level: Level,
+        next_id: AtomicU64,
+        pub(crate) spans: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl RecordingSubscriber {
+        pub(crate) fn new(max_level: Level) -> Self {
+            Self { max_level, next_id: AtomicU64::new(1), spans: Arc::new(Mutex::new(Vec::new())) }
+        }
+    }
+
+    impl Subscriber for RecordingSubscriber {
+        fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+            Interest::sometimes()
+        }
+
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            *metadata.level() <= self.max_level
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.spans.lock().unwrap().push(span.metadata().name());
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed))
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_handler_span_level() {
+        let subscriber = RecordingSubscriber::new(Level::INFO);
+        let spans = subscriber.spans.clone();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _ = handler_span!(Level::INFO, "test_info");
+            let _ = handler_span!(Level::DEBUG, "test_debug", field = 1);
+        });
+
+        assert_eq!(*spans.lock().unwrap(), vec!["test_info"]);
+    }
+}
@@ -0,0 +1,228 @@
+//! This module normalizes and validates the target URLs submitted to the create endpoint.
+use std::collections::BTreeSet;
+use unicode_script::{Script, UnicodeScript};
+use url::{ParseError, Url};
+
+
+/// Returns `true` if the target does not start with a URL scheme, e.g. `example.com/path`.
+fn is_schemeless(target: &str) -> bool {
+    match Url::parse(target) {
+        Ok(url) => {
+            // `example.com:8080/path` and `localhost:8080/path` parse with their host as the
+            // scheme, but a real scheme never contains a dot, nor is followed by a port.
+            url.scheme().contains('.') || target.split_once(':').is_some_and(|(_, rest)| starts_with_port(rest))
+        },
+        Err(ParseError::RelativeUrlWithoutBase) => true,
+        Err(_) => false,
+    }
+}
+
+
+/// Returns `true` if `rest` starts with a port, followed by the end of the URL or its path,
+/// query or fragment.
+fn starts_with_port(rest: &str) -> bool {
+    let port = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    port.bytes().all(|byte| byte.is_ascii_digit()) && port.parse::<u16>().is_ok()
+}
+
+
+/// Returns `true` if any label of the domain mixes characters from different scripts,
+/// e.g. a Cyrillic `а` inside an otherwise Latin label.
+///
+/// Japanese and Korean scripts are commonly written together with Han, so they count as Han.
+fn is_mixed_script(domain: &str) -> bool {
+    let (domain, _) = idna::domain_to_unicode(domain);
+    domain.split('.').any(|label| {
+        let mut scripts = label
+            .chars()
+            .map(|ch| ch.script())
+            .filter(|script| !matches!(script, Script::Common | Script::Inherited))
+            .map(|script| match script {
+                Script::Hiragana | Script::Katakana | Script::Hangul | Script::Bopomofo => Script::Han,
+                script => script,
+            });
+        match scripts.next() {
+            Some(first) => scripts.any(|script| script != first),
+            None => false,
+        }
+    })
+}
+
+
+/// Normalizes the host of a target URL.
+///
+/// Unicode hosts are converted to punycode, and mixed-script hosts are rejected
+/// if `block_homographs` is set.
+fn normalize_host(target: String, block_homographs: bool) -> Result<String, String> {
+    let Ok(url) = Url::parse(&target) else {
+        return Ok(target);
+    };
+
+    if block_homographs && url.domain().is_some_and(is_mixed_script) {
+        return Err(format!("Target host mixes scripts: {target}"));
+    }
+
+    if target.is_ascii() {
+        Ok(target)
+    } else {
+        // The parsed URL holds the punycode host and the percent-encoded path.
+        Ok(url.into())
+    }
+}
+
+
+/// Normalizes a target URL before it is stored.
+///
+/// Leading and trailing whitespace and control characters, e.g. a newline pasted along with the
+/// URL, are trimmed, and targets left empty are rejected. Schemeless targets get `default_scheme`
+/// prepended when one is configured, and are rejected otherwise, as are relative paths. Unicode
+/// hosts are stored in their punycode form.
+///
+/// # Arguments
+///
+/// * `target` - The target URL submitted by the client.
+/// * `default_scheme` - The scheme to prepend to schemeless targets, if any.
+/// * `block_homographs` - Whether to reject hosts mixing scripts within a label.
+///
+/// # Returns
+///
+/// A `Result` containing the normalized target or a message describing why it was rejected.
+pub fn normalize_target(target: &str, default_scheme: Option<&str>, block_homographs: bool) -> Result<String, String> {
+    let target = target.trim_matches(|ch: char| ch.is_whitespace() || ch.is_control());
+    if target.is_empty() {
+        return Err("Target URL is empty".to_string());
+    }
+
+    if !is_schemeless(target) {
+        return normalize_host(target.to_string(), block_homographs);
+    }
+
+    let Some(scheme) = default_scheme.filter(|_| !target.starts_with('/')) else {
+        return Err(format!("Target URL has no scheme: {target}"));
+    };
+
+    let target = format!("{scheme}://{target}");
+    Url::parse(&target).map_err(|err| format!("Invalid target URL {target}: {err}"))?;
+    normalize_host(target, block_homographs)
+}
+
+
+/// Validates a normalized target URL before it is stored.
+///
+/// The target must be an absolute URL whose scheme is one of `allowed_schemes`. URLs with an
+/// authority, e.g. `https://` ones, must also have a host, while URLs without one, e.g.
+/// `mailto:` ones, are only accepted if their scheme is allowed.
+///
+/// # Arguments
+///
+/// * `target` - The normalized target URL.
+/// * `allowed_schemes` - The lowercase schemes the target may use.
+///
+/// # Returns
+///
+/// A `Result` which is either empty or a message describing why the target was rejected.
+pub fn validate_target(target: &str, allowed_schemes: &BTreeSet<String>) -> Result<(), String> {
+    let url = Url::parse(target).map_err(|err| format!("Invalid target URL {target}: {err}"))?;
+
+    if !allowed_schemes.contains(url.scheme()) {
+        return Err(format!("Target URL scheme is not allowed: {}", url.scheme()));
+    }
+
+    // `cannot_be_a_base` URLs, e.g. `mailto:user@example.com`, have no authority, so no host.
+    if !url.cannot_be_a_base() && url.host().is_none() {
+        return Err(format!("Target URL has no host: {target}"));
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn web_schemes() -> BTreeSet<String> {
+        BTreeSet::from(["http".to_string(), "https".to_string()])
+    }
+
+    #[test]
+    fn test_validate_target() {
+        assert!(validate_target("https://example.com/path", &web_schemes()).is_ok());
+        assert!(validate_target("/path", &web_schemes()).is_err());
+        assert!(validate_target("", &web_schemes()).is_err());
+        let schemes = BTreeSet::from(["file".to_string()]);
+        assert!(validate_target("file:///etc/passwd", &schemes).is_err());
+    }
+
+    #[test]
+    fn test_validate_target_scheme() {
+        assert_eq!(
+            validate_target("javascript:alert(1)", &web_schemes()).unwrap_err(),
+            "Target URL scheme is not allowed: javascript",
+        );
+        assert!(validate_target("ftp://example.com/file", &web_schemes()).is_err());
+
+        let schemes = BTreeSet::from(["ftp".to_string()]);
+        assert!(validate_target("ftp://example.com/file", &schemes).is_ok());
+        assert!(validate_target("https://example.com/", &schemes).is_err());
+    }
+
+    #[test]
+    fn test_validate_target_hostless_scheme() {
+        assert!(validate_target("mailto:user@example.com", &web_schemes()).is_err());
+
+        let schemes = BTreeSet::from(["mailto".to_string(), "custom".to_string()]);
+        assert!(validate_target("mailto:user@example.com", &schemes).is_ok());
+        // URLs with an authority still need a host.
+        assert_eq!(validate_target("custom:///path", &schemes).unwrap_err(), "Target URL has no host: custom:///path");
+    }
+
+    #[test]
+    fn test_normalize_target_with_scheme() {
+        assert_eq!(normalize_target("https://example.com/path", None, false).unwrap(), "https://example.com/path");
+        assert_eq!(normalize_target("https://example.com/path", Some("http"), false).unwrap(), "https://example.com/path");
+    }
+
+    #[test]
+    fn test_normalize_target_schemeless_rejected() {
+        assert!(normalize_target("example.com/path", None, false).is_err());
+        assert!(normalize_target("example.com:8080/path", None, false).is_err());
+        assert!(normalize_target("localhost:8080/path", None, false).is_err());
+        assert!(normalize_target("/relative/path", Some("https"), false).is_err());
+        assert!(normalize_target("//example.com/path", Some("https"), false).is_err());
+    }
+
+    #[test]
+    fn test_normalize_target_schemeless_default_scheme() {
+        assert_eq!(normalize_target("example.com/path", Some("https"), false).unwrap(), "https://example.com/path");
+        assert_eq!(normalize_target("example.com:8080/path", Some("https"), false).unwrap(), "https://example.com:8080/path");
+        assert_eq!(normalize_target("localhost:8080/path", Some("https"), false).unwrap(), "https://localhost:8080/path");
+        assert_eq!(normalize_target("localhost:8080", Some("https"), false).unwrap(), "https://localhost:8080");
+        // A scheme followed by a number that is not a port is kept.
+        assert_eq!(normalize_target("tel:5551234567", Some("https"), false).unwrap(), "tel:5551234567");
+    }
+
+    #[test]
+    fn test_normalize_target_trimmed() {
+        assert_eq!(normalize_target("http://x.com\n", None, false).unwrap(), "http://x.com");
+        assert_eq!(normalize_target(" \t\u{0}http://x.com/path\r\n", None, false).unwrap(), "http://x.com/path");
+        assert_eq!(normalize_target(" x.com ", Some("https"), false).unwrap(), "https://x.com");
+        assert!(normalize_target(" \r\n", Some("https"), false).is_err());
+    }
+
+    #[test]
+    fn test_normalize_target_unicode_host() {
+        assert_eq!(normalize_target("https://bücher.example/path", None, false).unwrap(), "https://xn--bcher-kva.example/path");
+        assert_eq!(normalize_target("bücher.example/path", Some("https"), true).unwrap(), "https://xn--bcher-kva.example/path");
+        assert_eq!(normalize_target("https://日本語.jp/", None, true).unwrap(), "https://xn--wgv71a119e.jp/");
+    }
+
+    #[test]
+    fn test_normalize_target_homograph_host() {
+        // The first letter is a Cyrillic `а`.
+        assert_eq!(normalize_target("https://аpple.com/", None, false).unwrap(), "https://xn--pple-43d.com/");
+        assert!(normalize_target("https://аpple.com/", None, true).is_err());
+        assert!(normalize_target("https://xn--pple-43d.com/", None, true).is_err());
+        assert!(normalize_target("https://apple.com/", None, true).is_ok());
+    }
+}
@@ -0,0 +1,44 @@
+//! This module contains the live visit events published when a shortened URL is visited.
+use std::convert::Infallible;
+use axum::response::sse::Event;
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::log::warn;
+
+
+/// A visit to a shortened URL, as published on the live visit stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VisitEvent {
+    /// The key that was visited.
+    pub key: String,
+    /// The URL the visitor was redirected to.
+    pub url: String,
+    /// The time of the visit, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+
+/// Turns a visit subscription into a stream of Server-Sent Events.
+///
+/// A subscriber that falls behind the bounded buffer skips the oldest events and receives a
+/// `lagged` event with the number of skipped visits, so a slow consumer never blocks redirects.
+pub fn visit_stream(rx: broadcast::Receiver<VisitEvent>) -> impl Stream<Item = Result<Event, Infallible>> {
+    futures::stream::unfold(rx, |mut rx| async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(visit) => match Event::default().event("visit").json_data(&visit) {
+                    Ok(event) => event,
+                    Err(err) => {
+                        warn!("Error serializing visit event: {}", err);
+                        continue;
+                    },
+                },
+                Err(RecvError::Lagged(skipped)) => Event::default().event("lagged").data(skipped.to_string()),
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), rx));
+        }
+    })
+}
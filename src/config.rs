@@ -1,140 +1,1516 @@
 //! This module contains the configuration for the redirection service.
+//!
+//! The configuration is read from environment variables, or from a TOML file whose values are
+//! overridden by the environment variables that are set.
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as _;
+use base64::engine::general_purpose::STANDARD;
+use tracing::Level;
+use crate::key_generator::MAX_KEY_LENGTH;
 
 /// This struct contains the configuration for the redirection service.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct RedirectionServiceConfig {
     /// The port on which the service will listen.
     pub port: u16,
+    /// The port of the admin server, serving the metrics, probes and debug endpoints apart from
+    /// the public traffic. If `None`, they are served on the public port.
+    pub admin_port: Option<u16>,
     /// The database configuration.
     pub db_config: DBConfig,
+    /// The cache of redirect targets in front of the database. If `None`, every lookup hits the database.
+    pub cache: Option<CacheConfig>,
     /// The task sender configuration.
     pub task_sender: TaskSender,
     /// The key generator configuration.
     pub key_generator: KeyGeneratorConfig,
+    /// The HTTP handlers configuration.
+    pub handler: HandlerConfig,
+    /// The limits on the size of the requests and of the URLs they create.
+    pub limits: LimitsConfig,
+    /// How long the service keeps serving, while reporting not-ready, after a termination signal.
+    #[serde(rename = "shutdown_drain_seconds", deserialize_with = "deserialize_secs")]
+    pub shutdown_drain: Duration,
+    /// How long the in-flight requests, then the background work, may take to finish once the
+    /// service stops accepting connections.
+    #[serde(rename = "shutdown_timeout_seconds", deserialize_with = "deserialize_secs")]
+    pub shutdown_timeout: Duration,
+    /// Where access logs are written.
+    pub access_log: AccessLogConfig,
+    /// Where the audit log of created keys is written.
+    pub key_audit: KeyAuditConfig,
+    /// The authentication of create requests.
+    pub auth: AuthConfig,
+    /// The Unix domain socket the service listens on instead of the TCP port, if any.
+    pub listen_uds: Option<UdsConfig>,
+    /// The number of requests, and thus of their database operations, served at once. Requests
+    /// beyond it are shed with `503 Service Unavailable`. If `None`, requests are not limited.
+    pub max_in_flight: Option<usize>,
+    /// The directory containing the error page templates. If `None`, handler errors keep their JSON
+    /// body, and errors of the router, e.g. unknown routes, are returned as plain text.
+    pub error_pages_dir: Option<PathBuf>,
+}
+
+
+/// This enum represents where access logs are written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum AccessLogConfig {
+    /// Access logs are not written.
+    #[default]
+    Disabled,
+    /// Access logs are written to stdout.
+    Stdout,
+    /// Access logs are written to a file, rotated periodically.
+    File {
+        /// The path of the log file. Rotated files get a date suffix.
+        path: PathBuf,
+        /// How often the file is rotated.
+        #[serde(default = "default_log_rotation")]
+        rotation: LogRotation,
+    },
+}
+
+
+/// This struct contains the configuration of the in-memory cache of redirect targets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// The maximum number of cached keys, for existing and non-existent keys each.
+    pub capacity: u64,
+    /// How long an existing key is cached.
+    #[serde(rename = "ttl_seconds", deserialize_with = "deserialize_secs")]
+    pub ttl: Duration,
+    /// How long a non-existent key is cached, short so new keys created elsewhere show up soon.
+    #[serde(rename = "negative_ttl_seconds", deserialize_with = "deserialize_secs")]
+    pub negative_ttl: Duration,
+    /// Which entries are evicted when the cache is full.
+    pub eviction_policy: CacheEvictionPolicy,
+}
+
+
+/// This enum represents which entries the cache evicts when it is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheEvictionPolicy {
+    /// The least recently used entry is evicted.
+    #[default]
+    Lru,
+    /// A new entry is only cached if it is used more often than the entry it would evict (TinyLFU).
+    Lfu,
+    /// The cache of existing keys is not bounded, its entries are only evicted once they expire.
+    /// Non-existent keys are still cached up to the capacity, evicting the least recently used.
+    Ttl,
+}
+
+
+/// This struct contains the configuration of a Unix domain socket listener.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UdsConfig {
+    /// The path of the socket file.
+    pub path: PathBuf,
+    /// The permissions of the socket file, e.g. `0o660` so only the owner and its group can connect.
+    #[serde(default = "default_uds_mode")]
+    pub mode: u32,
+}
+
+
+/// This struct contains the configuration of the API key authentication of create requests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The request header carrying the API key.
+    pub header: String,
+    /// The valid API keys. If empty, create requests are not authenticated.
+    pub api_keys: BTreeSet<String>,
+}
+
+
+/// This struct contains the limits on the size of the requests and of the URLs they create.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The maximum size of a create request body, in bytes.
+    pub max_payload_bytes: usize,
+    /// The maximum number of URLs created by a bulk create request.
+    pub max_bulk_items: usize,
+    /// The maximum length of a short URL, scheme and host included.
+    pub max_short_url_length: usize,
+    /// The longest expiration a create request may ask for.
+    #[serde(rename = "max_ttl_seconds", deserialize_with = "deserialize_secs")]
+    pub max_ttl: Duration,
+    /// The maximum number of best-effort background tasks, e.g. recording visits, running at
+    /// once. Tasks beyond it are dropped.
+    pub max_background_tasks: usize,
+}
+
+
+/// This struct contains the configuration of the audit log, which records every key created.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyAuditConfig {
+    /// Where the audit entries are written.
+    pub destination: KeyAuditDestination,
+    /// The request header carrying the owner of a created key, e.g. set by an authenticating proxy.
+    pub owner_header: String,
+}
+
+
+/// This enum represents where the audit log of created keys is written.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAuditDestination {
+    /// Created keys are not audited.
+    Disabled,
+    /// Audit entries are written to stdout.
+    Stdout,
+    /// Audit entries are appended to a file, which is never rotated.
+    File(PathBuf),
+}
+
+
+/// This enum represents how often a log file is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// A new file every hour.
+    Hourly,
+    /// A new file every day.
+    Daily,
+    /// A single file, never rotated.
+    Never,
+}
+
+
+/// This struct contains the configuration for the HTTP handlers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HandlerConfig {
+    /// Keys that must return `451 Unavailable For Legal Reasons` instead of redirecting.
+    pub blocked_keys: BTreeSet<String>,
+    /// Destination URLs that must return `451 Unavailable For Legal Reasons` instead of redirecting.
+    pub blocked_urls: BTreeSet<String>,
+    /// The body returned for legally-blocked keys or destinations.
+    pub blocked_notice: String,
+    /// Keys whose redirects are delayed by `throttle_delay`.
+    pub throttled_keys: BTreeSet<String>,
+    /// Destination URLs whose redirects are delayed by `throttle_delay`.
+    pub throttled_urls: BTreeSet<String>,
+    /// How long redirects of throttled keys or destinations are delayed.
+    #[serde(rename = "throttle_delay_ms", deserialize_with = "deserialize_millis")]
+    pub throttle_delay: Duration,
+    /// How URL visits are recorded.
+    pub analytics: AnalyticsMode,
+    /// The scheme prepended to targets submitted without one. If `None`, such targets are rejected.
+    pub default_target_scheme: Option<String>,
+    /// Whether targets whose host mixes scripts within a label (a common homograph attack) are rejected.
+    pub block_homograph_hosts: bool,
+    /// The lowercase schemes targets may use. Targets with any other scheme are rejected.
+    pub allowed_target_schemes: BTreeSet<String>,
+    /// The live visit stream configuration.
+    pub visit_stream: VisitStreamConfig,
+    /// The tracing level of the span created by each handler.
+    pub trace_levels: TraceLevelConfig,
+    /// Whether the debug endpoints, e.g. resolving a key without redirecting, are served.
+    pub debug_endpoints: bool,
+    /// Whether `http` targets are redirected to their `https` counterpart.
+    pub upgrade_http_targets: bool,
+    /// The lowercase hostnames the service answers on. If empty, every host is served.
+    pub host_allowlist: BTreeSet<String>,
+    /// The origin, e.g. `https://sho.rt`, requests on unlisted hosts are redirected to.
+    /// If `None`, such requests are rejected.
+    pub canonical_host: Option<String>,
+    /// The route prefixes whose responses are marked as deprecated.
+    pub deprecated_routes: Vec<DeprecatedRoute>,
+    /// Whether request bodies with unknown fields are rejected instead of ignored.
+    pub strict_request_validation: bool,
+    /// How many keys are generated for a create request before giving up, while they are taken.
+    pub key_generation_attempts: u32,
+    /// Whether create bodies that are not valid JSON fall back to the first valid object they contain.
+    pub lenient_json_parsing: bool,
+    /// How long after startup the service reports not-ready, while its connections warm up.
+    #[serde(rename = "readiness_warmup_seconds", deserialize_with = "deserialize_secs")]
+    pub readiness_warmup: Duration,
+    /// How long each dependency health check of the readiness probe may take before the
+    /// dependency is reported unavailable.
+    #[serde(rename = "health_check_timeout_ms", deserialize_with = "deserialize_millis")]
+    pub health_check_timeout: Duration,
+    /// The format of the shortened URL keys.
+    pub keys: KeySpecConfig,
+    /// Lowercase substrings of the user agents whose create requests are rejected, e.g. bots.
+    pub create_ua_denylist: BTreeSet<String>,
+    /// Whether create requests without a user agent are rejected.
+    pub create_deny_empty_ua: bool,
+    /// The secret signing the brand tokens that select the base of created short URLs.
+    /// If `None`, short URLs are always built on the request host.
+    pub brand_token_secret: Option<String>,
+    /// The base created short URLs are built on, e.g. `https://sho.rt/`, ending with a `/`.
+    /// If `None`, they are built on the scheme and `Host` header of the request.
+    pub public_base_url: Option<String>,
+    /// Whether the `X-Forwarded-Proto` and `X-Forwarded-Host` headers, set by a trusted reverse
+    /// proxy, take precedence over the scheme and `Host` header of the request.
+    pub trust_forwarded_headers: bool,
+}
+
+
+/// Checks that a base URL is an `http` or `https` URL without query or fragment, and makes it
+/// end with exactly one `/`, so keys can be appended to it.
+/// Both the configured public base URL and the base URLs of brand tokens are checked with it.
+pub(crate) fn normalize_base_url(base: &str) -> Result<String, String> {
+    let url = url::Url::parse(base).map_err(|err| format!("{base}: {err}"))?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() || url.query().is_some() || url.fragment().is_some() {
+        return Err(format!("{base}, expected an http or https URL without query or fragment"));
+    }
+    Ok(format!("{}/", url.as_str().trim_end_matches('/')))
+}
+
+
+/// This struct contains the format of the shortened URL keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeySpecConfig {
+    /// The characters a key may contain.
+    pub alphabet: String,
+    /// The minimum length of a key, in characters.
+    pub min_length: usize,
+    /// The maximum length of a key, in characters.
+    pub max_length: usize,
+    /// The words a key cannot be, matched case-insensitively.
+    pub reserved: BTreeSet<String>,
+}
+
+
+/// This struct describes a deprecated route prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeprecatedRoute {
+    /// The path prefix of the deprecated routes, e.g. `/api/v1/`.
+    pub prefix: String,
+    /// When the routes were deprecated, in seconds since the Unix epoch.
+    pub deprecated_at: u64,
+    /// When the routes stop being served, in seconds since the Unix epoch, if scheduled.
+    pub sunset: Option<u64>,
+}
+
+
+/// This struct contains the tracing level of the span created by each handler.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TraceLevelConfig {
+    /// The level of the `create_url` span.
+    #[serde(deserialize_with = "deserialize_level")]
+    pub create_url: Level,
+    /// The level of the `get_url` span.
+    #[serde(deserialize_with = "deserialize_level")]
+    pub get_url: Level,
+}
+
+
+/// This struct contains the configuration for the live visit stream.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VisitStreamConfig {
+    /// The bearer token required to subscribe to the stream. If `None`, the stream is disabled.
+    pub token: Option<String>,
+    /// The number of visit events buffered for each subscriber. Subscribers that fall further
+    /// behind skip the oldest events.
+    pub capacity: usize,
+}
+
+
+/// This enum represents the different ways URL visits can be recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum AnalyticsMode {
+    /// Visits are recorded server-side by sending a task to the task sender.
+    #[default]
+    Server,
+    /// Visits are recorded client-side: the redirect is served as an HTML page that fires
+    /// a beacon to the given URL and then navigates to the destination.
+    Beacon {
+        /// The URL the beacon is sent to. The visited key is appended as the `key` query parameter.
+        url: String,
+    },
 }
 
 
 /// This struct contains the configuration for a ScyllaDB database.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ScyllaDBConfig {
     /// The URL of the ScyllaDB instance.
     pub url : String,
     /// The keyspace to use in ScyllaDB.
     pub keyspace: String,
-    /// The replication factor for the keyspace.
+    /// The replication factor for the keyspace, used when no replication strategy is set.
     pub replication_factor: i32,
+    /// The replication strategy of the keyspace. If `None`, the keyspace uses
+    /// `NetworkTopologyStrategy` with `replication_factor` in every datacenter.
+    pub replication_strategy: Option<ReplicationStrategy>,
+    /// Whether rows are partitioned by a hash of the key instead of the key itself.
+    pub hash_partition_keys: bool,
+    /// Whether the session is warmed up after connecting.
+    pub warmup: ScyllaWarmup,
+    /// The default TTL of the tables, in seconds. `0` disables expiry.
+    pub default_ttl_seconds: u32,
+    /// Whether the default TTL is also applied to existing tables at startup.
+    pub alter_ttl: bool,
+}
+
+
+/// The largest TTL ScyllaDB accepts, in seconds, i.e. 20 years.
+const MAX_SCYLLA_TTL_SECONDS: u32 = 630_720_000;
+
+
+/// This enum represents how the ScyllaDB keyspace is replicated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ReplicationStrategy {
+    /// The replicas are placed on consecutive nodes, regardless of datacenters, e.g. on
+    /// single-node development clusters.
+    #[serde(rename = "simple")]
+    SimpleStrategy {
+        /// The number of replicas.
+        factor: i32,
+    },
+    /// The replicas are placed in each datacenter.
+    #[serde(rename = "network_topology")]
+    NetworkTopologyStrategy {
+        /// The number of replicas in each datacenter, by datacenter name.
+        per_dc: BTreeMap<String, i32>,
+    },
+}
+
+
+/// This enum represents whether a ScyllaDB session is warmed up after connecting, and how
+/// warmup failures are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScyllaWarmup {
+    /// The session is not warmed up.
+    Disabled,
+    /// The session is warmed up, and failures are logged as warnings.
+    Warn,
+    /// The session is warmed up, and failures abort startup.
+    Strict,
 }
 
 
 /// This enum represents the different database configurations that can be used.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum DBConfig {
     /// A ScyllaDB configuration.
+    #[serde(rename = "scylla")]
     ScyllaDB(ScyllaDBConfig),
+    /// A Redis configuration.
+    Redis(RedisConfig),
+    /// An in-memory database configuration, for local development and tests.
+    Memory(MemoryConfig),
+    /// A configuration that dual-writes to two databases.
+    DualWrite(DualWriteConfig),
+    /// A configuration that stores the targets encrypted in another database.
+    Encrypted(EncryptedConfig),
+}
+
+
+/// This struct contains the configuration for a Redis database.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// The URL of the Redis instance.
+    pub url: String,
+    /// The TTL of the stored keys, in seconds. `0` disables expiry.
+    pub ttl_seconds: u64,
+}
+
+
+/// This struct contains the configuration for an in-memory database.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    /// How long keys are kept. If `None`, keys never expire.
+    #[serde(rename = "ttl_seconds", deserialize_with = "deserialize_option_secs")]
+    pub ttl: Option<Duration>,
+    /// How often expired keys are removed. If `None`, they are removed every `ttl`.
+    #[serde(rename = "sweep_interval_seconds", deserialize_with = "deserialize_option_secs")]
+    pub sweep_interval: Option<Duration>,
+}
+
+
+/// This struct contains the configuration for dual-writing to two databases.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DualWriteConfig {
+    /// The database that serves reads and whose writes must succeed.
+    pub primary: Box<DBConfig>,
+    /// The database that receives best-effort copies of the writes.
+    pub secondary: Box<DBConfig>,
+}
+
+
+/// This struct contains the configuration for storing the targets encrypted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptedConfig {
+    /// The database storing the encrypted targets.
+    pub inner: Box<DBConfig>,
+    /// The key the targets are encrypted with.
+    pub key: EncryptionKey,
+}
+
+
+/// A 256-bit AES key. Its `Debug` output is redacted.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct EncryptionKey(pub [u8; 32]);
+
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(<redacted>)")
+    }
+}
+
+
+/// Deserializes a key from its base64 encoding, as in `TARGET_ENCRYPTION_KEY`.
+impl<'de> Deserialize<'de> for EncryptionKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        Self::from_base64(&key).map_err(D::Error::custom)
+    }
 }
 
 
 /// This enum represents the different task senders that can be used.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum TaskSender {
     /// A NATS configuration.
     Nats(NatsConfig),
+    /// A Kafka configuration.
+    Kafka(KafkaConfig),
 }
 
 
 /// This struct contains the configuration for a NATS task sender.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct NatsConfig {
     /// The URL of the NATS server.
     pub url: String,
     /// The subject to which tasks will be sent.
     pub subject: String,
+    /// How long to wait for the ack of a published task before publishing it again.
+    #[serde(rename = "ack_timeout_ms", deserialize_with = "deserialize_millis")]
+    pub ack_timeout: Duration,
+    /// Whether to skip checking at startup that a JetStream stream is bound to the subject.
+    pub skip_stream_check: bool,
+    /// How many times a task is published before giving up, counting the first attempt.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry. Each further retry waits twice as long.
+    #[serde(rename = "base_delay_ms", deserialize_with = "deserialize_millis")]
+    pub base_delay: Duration,
+}
+
+
+/// This struct contains the configuration for a Kafka task sender.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    /// The comma-separated list of Kafka brokers.
+    pub brokers: String,
+    /// The topic to which tasks will be sent.
+    pub topic: String,
+    /// How long to wait for a task to be enqueued, and then delivered.
+    #[serde(rename = "timeout_ms", deserialize_with = "deserialize_millis")]
+    pub timeout: Duration,
 }
 
 
 /// This enum represents the different key generator configurations that can be used.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum KeyGeneratorConfig {
     /// A gRPC key generator configuration.
-    GRPCKeyGeneratorConfig(GRPCKeyGeneratorConfig)
+    #[serde(rename = "grpc")]
+    GRPCKeyGeneratorConfig(GRPCKeyGeneratorConfig),
+    /// An in-process random key generator configuration.
+    Local(LocalKeyGeneratorConfig),
+    /// A hashids key generator configuration.
+    Hashids(HashidsKeyGeneratorConfig),
 }
 
 
 /// This struct contains the configuration for a gRPC key generator.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GRPCKeyGeneratorConfig {
     /// The URL of the gRPC key generator service.
     pub url: String,
+    /// The API key sent as `x-api-key` metadata on each request, if any.
+    pub api_key: Option<String>,
+    /// Additional metadata sent on each request.
+    pub metadata: BTreeMap<String, String>,
+    /// Whether the requests are sent, and the responses accepted, compressed with gzip.
+    pub compression: bool,
+}
+
+
+/// This struct contains the configuration for an in-process random key generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalKeyGeneratorConfig {
+    /// The length of the generated keys, in characters.
+    pub length: usize,
+    /// The characters the generated keys are drawn from.
+    pub alphabet: String,
+}
+
+
+/// This struct contains the configuration for a key generator encoding a counter with hashids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HashidsKeyGeneratorConfig {
+    /// The salt the keys are encoded with, needed to decode them.
+    pub salt: String,
+    /// The minimum length of the generated keys, in characters.
+    pub min_length: usize,
+    /// The URL of the Redis instance storing the counter.
+    pub counter_url: String,
+    /// The Redis key storing the counter.
+    pub counter_key: String,
+}
+
+
+/// Deserializes a duration from a number of seconds.
+fn deserialize_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+
+/// Deserializes an optional duration from a number of seconds.
+fn deserialize_option_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<u64>::deserialize(deserializer).map(|seconds| seconds.map(Duration::from_secs))
+}
+
+
+/// Deserializes a duration from a number of milliseconds.
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+
+/// Deserializes a tracing level from its name, e.g. `debug`.
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Level, D::Error> {
+    let level = String::deserialize(deserializer)?;
+    level.parse().map_err(|_| D::Error::custom(format!("invalid trace level: {level}")))
+}
+
+
+fn default_log_rotation() -> LogRotation {
+    LogRotation::Daily
+}
+
+
+fn default_uds_mode() -> u32 {
+    0o660
+}
+
+
+/// Overrides `field` with the value of the environment variable `name` parsed with `parse`,
+/// if the variable is set.
+fn override_from_env<T>(name: &str, field: &mut T, parse: impl FnOnce(String) -> Result<T>) -> Result<()> {
+    if let Ok(value) = env::var(name) {
+        *field = parse(value)?;
+    }
+    Ok(())
+}
+
+
+/// Parses a boolean environment variable, which is `true` if set to `true` or `1`.
+fn flag(value: String) -> Result<bool> {
+    Ok(matches!(value.as_str(), "true" | "1"))
+}
+
+
+/// Parses a comma-separated list of `datacenter=factor` pairs, e.g. `dc1=3,dc2=2`.
+fn replication_per_dc(value: String) -> Result<BTreeMap<String, i32>> {
+    list(value)?
+        .into_iter()
+        .map(|entry| {
+            let (dc, factor) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid replication factor, expected datacenter=factor: {}", entry))?;
+            Ok((dc.trim().to_string(), factor.trim().parse()?))
+        })
+        .collect()
+}
+
+
+/// Parses a number of seconds.
+fn seconds(value: String) -> Result<Duration> {
+    Ok(Duration::from_secs(value.parse::<u64>()?))
+}
+
+
+/// Parses a number of milliseconds.
+fn millis(value: String) -> Result<Duration> {
+    Ok(Duration::from_millis(value.parse::<u64>()?))
+}
+
+
+/// Parses a comma-separated list, ignoring empty entries.
+fn list(value: String) -> Result<BTreeSet<String>> {
+    Ok(value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+
+/// Checks that a configured header name is a valid HTTP header name.
+fn check_header_name(header: &str, description: &str) -> Result<()> {
+    axum::http::HeaderName::try_from(header)
+        .map(|_| ())
+        .map_err(|_| anyhow!("Invalid {}: {}", description, header))
+}
+
+
+impl Default for DBConfig {
+    fn default() -> Self {
+        DBConfig::ScyllaDB(ScyllaDBConfig::default())
+    }
 }
 
 
 impl DBConfig {
-    /// This function creates a new `DBConfig` from environment variables.
-    pub fn from_env() -> Result<Self> {
-        let db_type = env::var("DATABASE_TYPE").unwrap_or("scylla".into());
-        match db_type.as_str() {
-            "scylla" => Ok(DBConfig::ScyllaDB(ScyllaDBConfig::from_env()?)),
-            _ => Err(anyhow!("Unsupported database type: {}", db_type)),
+    /// This function overrides the configuration with the environment variables that are set.
+    /// A database type variable naming another type replaces the database, and
+    /// `SECONDARY_DATABASE_TYPE` and `ENCRYPT_TARGETS` add or remove the dual-write and
+    /// encryption layers.
+    pub fn with_env(self) -> Result<Self> {
+        let (db, key) = match self {
+            DBConfig::Encrypted(config) => (*config.inner, Some(config.key)),
+            db => (db, None),
+        };
+
+        let db = match db {
+            DBConfig::DualWrite(config) => DBConfig::DualWrite(DualWriteConfig {
+                primary: Box::new(config.primary.with_env_prefix("")?),
+                secondary: Box::new(config.secondary.with_env_prefix("SECONDARY_")?),
+            }),
+            db if env::var("SECONDARY_DATABASE_TYPE").is_ok() => DBConfig::DualWrite(DualWriteConfig {
+                primary: Box::new(db.with_env_prefix("")?),
+                secondary: Box::new(Self::default().with_env_prefix("SECONDARY_")?),
+            }),
+            db => db.with_env_prefix("")?,
+        };
+
+        let encrypt = match env::var("ENCRYPT_TARGETS") {
+            Ok(encrypt) => flag(encrypt)?,
+            Err(_) => key.is_some(),
+        };
+        if !encrypt {
+            return Ok(db);
+        }
+
+        let key = match (env::var("TARGET_ENCRYPTION_KEY"), key) {
+            (Ok(key), _) => EncryptionKey::from_base64(&key)?,
+            (Err(_), Some(key)) => key,
+            (Err(_), None) => return Err(anyhow!("TARGET_ENCRYPTION_KEY is required when ENCRYPT_TARGETS is set")),
+        };
+        Ok(DBConfig::Encrypted(EncryptedConfig {
+            inner: Box::new(db),
+            key,
+        }))
+    }
+
+    /// This function overrides a single database with the environment variables whose names start with `prefix`.
+    fn with_env_prefix(self, prefix: &str) -> Result<Self> {
+        let db_type = env::var(format!("{prefix}DATABASE_TYPE")).ok();
+        match (db_type.as_deref(), self) {
+            (None | Some("scylla"), DBConfig::ScyllaDB(config)) => Ok(DBConfig::ScyllaDB(config.with_env_prefix(prefix)?)),
+            (Some("scylla"), _) => Ok(DBConfig::ScyllaDB(ScyllaDBConfig::default().with_env_prefix(prefix)?)),
+            (None | Some("redis"), DBConfig::Redis(config)) => Ok(DBConfig::Redis(config.with_env_prefix(prefix)?)),
+            (Some("redis"), _) => Ok(DBConfig::Redis(RedisConfig::default().with_env_prefix(prefix)?)),
+            (None | Some("memory"), DBConfig::Memory(config)) => Ok(DBConfig::Memory(config.with_env_prefix(prefix)?)),
+            (Some("memory"), _) => Ok(DBConfig::Memory(MemoryConfig::default().with_env_prefix(prefix)?)),
+            // Layers nested deeper in a configuration file have no environment variables.
+            (None, db) => Ok(db),
+            (Some(db_type), _) => Err(anyhow!("Unsupported database type: {}", db_type)),
         }
     }
 }
 
+impl EncryptionKey {
+    /// This function decodes a base64-encoded 32-byte key.
+    pub fn from_base64(key: &str) -> Result<Self> {
+        let bytes = STANDARD.decode(key.trim()).map_err(|err| anyhow!("Invalid TARGET_ENCRYPTION_KEY: {}", err))?;
+        let key = bytes.try_into().map_err(|_| anyhow!("TARGET_ENCRYPTION_KEY must be 32 bytes long"))?;
+        Ok(Self(key))
+    }
+}
+
+impl Default for TaskSender {
+    fn default() -> Self {
+        TaskSender::Nats(NatsConfig::default())
+    }
+}
+
 impl TaskSender {
-    /// This function creates a new `TaskSender` from environment variables.
-    pub fn from_env() -> Result<Self> {
-        let task_sender_type = env::var("TASK_SENDER_TYPE").unwrap_or("nats".into());
-        match task_sender_type.as_str() {
-            "nats" => Ok(TaskSender::Nats(NatsConfig::from_env()?)),
-            _ => Err(anyhow!("Unsupported task sender type: {}", task_sender_type)),
+    /// This function overrides the configuration with the environment variables that are set.
+    /// A `TASK_SENDER_TYPE` naming another type replaces the task sender.
+    pub fn with_env(self) -> Result<Self> {
+        let task_sender_type = env::var("TASK_SENDER_TYPE").ok();
+        match (task_sender_type.as_deref(), self) {
+            (None | Some("nats"), TaskSender::Nats(config)) => Ok(TaskSender::Nats(config.with_env()?)),
+            (Some("nats"), _) => Ok(TaskSender::Nats(NatsConfig::default().with_env()?)),
+            (None | Some("kafka"), TaskSender::Kafka(config)) => Ok(TaskSender::Kafka(config.with_env()?)),
+            (Some("kafka"), _) => Ok(TaskSender::Kafka(KafkaConfig::default().with_env()?)),
+            (Some(task_sender_type), _) => Err(anyhow!("Unsupported task sender type: {}", task_sender_type)),
+        }
+    }
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        Self {
+            url: "nats://localhost:4222".into(),
+            subject: "tasks.visit".into(),
+            ack_timeout: Duration::from_millis(5000),
+            skip_stream_check: false,
+            max_attempts: 2,
+            base_delay: Duration::from_millis(100),
         }
     }
 }
 
 impl NatsConfig {
-    /// This function creates a new `NatsConfig` from environment variables.
-    pub fn from_env() -> Result<Self> {
-        let url = env::var("NATS_URL").unwrap_or("nats://localhost:4222".into());
-        let subject = env::var("NATS_TASK_SUBJECT").unwrap_or("tasks.visit".into());
-        Ok(Self { url, subject })
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("NATS_URL", &mut self.url, Ok)?;
+        override_from_env("NATS_TASK_SUBJECT", &mut self.subject, Ok)?;
+        override_from_env("NATS_ACK_TIMEOUT_MS", &mut self.ack_timeout, millis)?;
+        override_from_env("NATS_SKIP_STREAM_CHECK", &mut self.skip_stream_check, flag)?;
+        override_from_env("NATS_PUBLISH_MAX_ATTEMPTS", &mut self.max_attempts, |attempts| Ok(attempts.parse()?))?;
+        override_from_env("NATS_PUBLISH_BASE_DELAY_MS", &mut self.base_delay, millis)?;
+        self.max_attempts = self.max_attempts.max(1);
+        Ok(self)
+    }
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".into(),
+            topic: "tasks.visit".into(),
+            timeout: Duration::from_millis(5000),
+        }
+    }
+}
+
+impl KafkaConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("KAFKA_BROKERS", &mut self.brokers, Ok)?;
+        override_from_env("KAFKA_TOPIC", &mut self.topic, Ok)?;
+        override_from_env("KAFKA_TIMEOUT_MS", &mut self.timeout, millis)?;
+        Ok(self)
+    }
+}
+
+impl Default for KeyGeneratorConfig {
+    fn default() -> Self {
+        KeyGeneratorConfig::GRPCKeyGeneratorConfig(GRPCKeyGeneratorConfig::default())
     }
 }
 
 impl KeyGeneratorConfig {
-    /// This function creates a new `KeyGeneratorConfig` from environment variables.
-    pub fn from_env() -> Result<Self> {
-        let key_generator_type = env::var("KEY_GENERATOR_TYPE").unwrap_or("grpc".into());
-        match key_generator_type.as_str() {
-            "grpc" => Ok(KeyGeneratorConfig::GRPCKeyGeneratorConfig(GRPCKeyGeneratorConfig::from_env()?)),
-            _ => Err(anyhow!("Unsupported key_generator type: {}", key_generator_type)),
+    /// This function overrides the configuration with the environment variables that are set.
+    /// A `KEY_GENERATOR_TYPE` naming another type replaces the key generator.
+    pub fn with_env(self) -> Result<Self> {
+        let key_generator_type = env::var("KEY_GENERATOR_TYPE").ok();
+        match (key_generator_type.as_deref(), self) {
+            (None | Some("grpc"), KeyGeneratorConfig::GRPCKeyGeneratorConfig(config)) => Ok(KeyGeneratorConfig::GRPCKeyGeneratorConfig(config.with_env()?)),
+            (Some("grpc"), _) => Ok(KeyGeneratorConfig::GRPCKeyGeneratorConfig(GRPCKeyGeneratorConfig::default().with_env()?)),
+            (None | Some("local"), KeyGeneratorConfig::Local(config)) => Ok(KeyGeneratorConfig::Local(config.with_env()?)),
+            (Some("local"), _) => Ok(KeyGeneratorConfig::Local(LocalKeyGeneratorConfig::default().with_env()?)),
+            (None | Some("hashids"), KeyGeneratorConfig::Hashids(config)) => Ok(KeyGeneratorConfig::Hashids(config.with_env()?)),
+            (Some("hashids"), _) => Ok(KeyGeneratorConfig::Hashids(HashidsKeyGeneratorConfig::default().with_env()?)),
+            (Some(key_generator_type), _) => Err(anyhow!("Unsupported key_generator type: {}", key_generator_type)),
+        }
+    }
+}
+
+impl Default for GRPCKeyGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8080".into(),
+            api_key: None,
+            metadata: BTreeMap::new(),
+            compression: false,
         }
     }
 }
 
 impl GRPCKeyGeneratorConfig {
-    /// This function creates a new `GRPCKeyGeneratorConfig` from environment variables.
-    pub fn from_env() -> Result<Self> {
-        let url = env::var("KEY_GENERATION_SERVICE_URL").unwrap_or("http://localhost:8080".into());
-        Ok(Self { url })
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("KEY_GENERATION_SERVICE_URL", &mut self.url, Ok)?;
+        override_from_env("KEYGEN_API_KEY", &mut self.api_key, |api_key| Ok(Some(api_key).filter(|api_key| !api_key.is_empty())))?;
+        override_from_env("KEYGEN_METADATA", &mut self.metadata, |metadata| {
+            list(metadata)?
+                .into_iter()
+                .map(|entry| {
+                    entry
+                        .split_once('=')
+                        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                        .ok_or_else(|| anyhow!("Invalid key generator metadata, expected key=value: {}", entry))
+                })
+                .collect()
+        })?;
+        override_from_env("KEYGEN_GRPC_COMPRESSION", &mut self.compression, flag)?;
+        Ok(self)
+    }
+}
+
+
+impl Default for LocalKeyGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            length: 8,
+            alphabet: ('a'..='z').chain('A'..='Z').chain('0'..='9').collect(),
+        }
+    }
+}
+
+
+impl LocalKeyGeneratorConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("LOCAL_KEY_LENGTH", &mut self.length, |length| Ok(length.parse()?))?;
+        override_from_env("LOCAL_KEY_ALPHABET", &mut self.alphabet, Ok)?;
+        if !(1..=MAX_KEY_LENGTH).contains(&self.length) {
+            return Err(anyhow!("LOCAL_KEY_LENGTH must be between 1 and {}", MAX_KEY_LENGTH));
+        }
+        if self.alphabet.is_empty() {
+            return Err(anyhow!("LOCAL_KEY_ALPHABET cannot be empty"));
+        }
+        Ok(self)
+    }
+
+    /// Checks that the generated keys follow the key format, as keys that do not are rejected
+    /// once generated.
+    fn validate_keys(&self, keys: &KeySpecConfig) -> Result<()> {
+        if !self.alphabet.is_ascii() {
+            return Err(anyhow!("LOCAL_KEY_ALPHABET must only contain ASCII characters"));
+        }
+        if let Some(c) = self.alphabet.chars().find(|c| !keys.alphabet.contains(*c)) {
+            return Err(anyhow!("LOCAL_KEY_ALPHABET contains {:?}, which KEY_ALPHABET does not allow", c));
+        }
+        if !(keys.min_length..=keys.max_length).contains(&self.length) {
+            return Err(anyhow!("LOCAL_KEY_LENGTH ({}) must be between KEY_MIN_LENGTH ({}) and KEY_MAX_LENGTH ({})", self.length, keys.min_length, keys.max_length));
+        }
+        Ok(())
+    }
+}
+
+
+impl Default for HashidsKeyGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            salt: String::new(),
+            min_length: 6,
+            counter_url: "redis://localhost:6379".into(),
+            counter_key: "key_counter".into(),
+        }
+    }
+}
+
+
+impl HashidsKeyGeneratorConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("HASHIDS_SALT", &mut self.salt, Ok)?;
+        override_from_env("HASHIDS_MIN_LENGTH", &mut self.min_length, |length| Ok(length.parse()?))?;
+        if self.min_length > MAX_KEY_LENGTH {
+            return Err(anyhow!("HASHIDS_MIN_LENGTH must be at most {}", MAX_KEY_LENGTH));
+        }
+        override_from_env("HASHIDS_COUNTER_REDIS_URL", &mut self.counter_url, Ok)?;
+        override_from_env("HASHIDS_COUNTER_KEY", &mut self.counter_key, Ok)?;
+        Ok(self)
+    }
+}
+
+
+impl Default for HandlerConfig {
+    fn default() -> Self {
+        Self {
+            blocked_keys: BTreeSet::new(),
+            blocked_urls: BTreeSet::new(),
+            blocked_notice: "This content is unavailable for legal reasons".into(),
+            throttled_keys: BTreeSet::new(),
+            throttled_urls: BTreeSet::new(),
+            throttle_delay: Duration::from_millis(500),
+            analytics: AnalyticsMode::default(),
+            default_target_scheme: None,
+            block_homograph_hosts: false,
+            allowed_target_schemes: BTreeSet::from(["http".into(), "https".into()]),
+            visit_stream: VisitStreamConfig::default(),
+            trace_levels: TraceLevelConfig::default(),
+            debug_endpoints: false,
+            upgrade_http_targets: false,
+            host_allowlist: BTreeSet::new(),
+            canonical_host: None,
+            deprecated_routes: Vec::new(),
+            strict_request_validation: false,
+            lenient_json_parsing: false,
+            key_generation_attempts: 3,
+            readiness_warmup: Duration::ZERO,
+            health_check_timeout: Duration::from_millis(2000),
+            keys: KeySpecConfig::default(),
+            create_ua_denylist: BTreeSet::new(),
+            create_deny_empty_ua: false,
+            brand_token_secret: None,
+            public_base_url: None,
+            trust_forwarded_headers: false,
+        }
+    }
+}
+
+
+impl Default for KeySpecConfig {
+    fn default() -> Self {
+        Self {
+            alphabet: ('a'..='z').chain('A'..='Z').chain('0'..='9').chain(['-', '_']).collect(),
+            min_length: 1,
+            max_length: MAX_KEY_LENGTH,
+            // Keys shadowed by the probe and metrics routes could never be visited.
+            reserved: ["health", "ready", "readyz", "metrics"].into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+
+impl HandlerConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("BLOCKED_KEYS", &mut self.blocked_keys, list)?;
+        override_from_env("BLOCKED_URLS", &mut self.blocked_urls, list)?;
+        override_from_env("BLOCKED_NOTICE", &mut self.blocked_notice, Ok)?;
+        override_from_env("THROTTLED_KEYS", &mut self.throttled_keys, list)?;
+        override_from_env("THROTTLED_URLS", &mut self.throttled_urls, list)?;
+        override_from_env("THROTTLE_DELAY_MS", &mut self.throttle_delay, millis)?;
+        self.analytics = self.analytics.with_env()?;
+        override_from_env("DEFAULT_TARGET_SCHEME", &mut self.default_target_scheme, |scheme| match scheme.as_str() {
+            "" | "reject" => Ok(None),
+            _ => Ok(Some(scheme)),
+        })?;
+        override_from_env("BLOCK_HOMOGRAPH_HOSTS", &mut self.block_homograph_hosts, flag)?;
+        let allowed_target_schemes = list_from_env("ALLOWED_TARGET_SCHEMES");
+        if !allowed_target_schemes.is_empty() {
+            self.allowed_target_schemes = allowed_target_schemes;
+        }
+        self.visit_stream = self.visit_stream.with_env()?;
+        override_from_env("HANDLER_TRACE_LEVELS", &mut self.trace_levels, |levels| TraceLevelConfig::parse(&levels))?;
+        override_from_env("DEBUG_ENDPOINTS", &mut self.debug_endpoints, flag)?;
+        override_from_env("UPGRADE_HTTP_TARGETS", &mut self.upgrade_http_targets, flag)?;
+        override_from_env("HOST_ALLOWLIST", &mut self.host_allowlist, list)?;
+        override_from_env("CANONICAL_HOST_REDIRECT", &mut self.canonical_host, |host| Ok(Some(host).filter(|host| !host.is_empty())))?;
+        override_from_env("DEPRECATED_ROUTES", &mut self.deprecated_routes, |routes| DeprecatedRoute::parse_list(&routes))?;
+        override_from_env("STRICT_REQUEST_VALIDATION", &mut self.strict_request_validation, flag)?;
+        override_from_env("LENIENT_JSON_PARSING", &mut self.lenient_json_parsing, flag)?;
+        override_from_env("KEY_GENERATION_ATTEMPTS", &mut self.key_generation_attempts, |attempts| Ok(attempts.parse()?))?;
+        override_from_env("READINESS_WARMUP_SECONDS", &mut self.readiness_warmup, seconds)?;
+        override_from_env("HEALTH_CHECK_TIMEOUT_MS", &mut self.health_check_timeout, millis)?;
+        self.keys = self.keys.with_env()?;
+        override_from_env("CREATE_UA_DENYLIST", &mut self.create_ua_denylist, list)?;
+        override_from_env("CREATE_UA_DENY_EMPTY", &mut self.create_deny_empty_ua, flag)?;
+        override_from_env("BRAND_TOKEN_SECRET", &mut self.brand_token_secret, |secret| Ok(Some(secret).filter(|secret| !secret.is_empty())))?;
+        override_from_env("PUBLIC_BASE_URL", &mut self.public_base_url, |base| Ok(Some(base).filter(|base| !base.is_empty())))?;
+        self.public_base_url = self.public_base_url
+            .map(|base| normalize_base_url(&base).map_err(|err| anyhow!("Invalid PUBLIC_BASE_URL {err}")))
+            .transpose()?;
+        override_from_env("TRUST_FORWARDED_HEADERS", &mut self.trust_forwarded_headers, flag)?;
+
+        // These are matched against lowercase values, whether they come from the environment or a file.
+        self.allowed_target_schemes = self.allowed_target_schemes.into_iter().map(|scheme| scheme.to_ascii_lowercase()).collect();
+        self.host_allowlist = self.host_allowlist.into_iter().map(|host| host.to_ascii_lowercase()).collect();
+        self.create_ua_denylist = self.create_ua_denylist.into_iter().map(|ua| ua.to_lowercase()).collect();
+        self.key_generation_attempts = self.key_generation_attempts.max(1);
+        Ok(self)
+    }
+}
+
+
+impl KeySpecConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    /// The reserved keys of the environment are added to the configured ones.
+    pub fn with_env(mut self) -> Result<Self> {
+        if let Some(alphabet) = env::var("KEY_ALPHABET").ok().filter(|alphabet| !alphabet.is_empty()) {
+            self.alphabet = alphabet;
+        }
+        override_from_env("KEY_MIN_LENGTH", &mut self.min_length, |length| Ok(length.parse()?))?;
+        override_from_env("KEY_MAX_LENGTH", &mut self.max_length, |length| Ok(length.parse()?))?;
+        if self.min_length > self.max_length {
+            return Err(anyhow!("KEY_MIN_LENGTH ({}) is greater than KEY_MAX_LENGTH ({})", self.min_length, self.max_length));
+        }
+        // The routes shadowing the default reserved keys are always served.
+        self.reserved.extend(Self::default().reserved);
+        self.reserved.extend(list_from_env("RESERVED_KEYS"));
+        Ok(self)
+    }
+}
+
+
+impl DeprecatedRoute {
+    /// This function parses a comma-separated list of `prefix=deprecated_at[:sunset]` entries,
+    /// with both times in seconds since the Unix epoch, e.g. `/api/v1/=1767225600:1798761600`.
+    pub fn parse_list(routes: &str) -> Result<Vec<Self>> {
+        routes
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (prefix, times) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Invalid deprecated route, expected prefix=deprecated_at[:sunset]: {}", entry))?;
+                let (deprecated_at, sunset) = match times.split_once(':') {
+                    Some((deprecated_at, sunset)) => (deprecated_at, Some(sunset.parse::<u64>()?)),
+                    None => (times, None),
+                };
+
+                Ok(Self {
+                    prefix: prefix.to_string(),
+                    deprecated_at: deprecated_at.parse::<u64>()?,
+                    sunset,
+                })
+            })
+            .collect()
+    }
+}
+
+
+impl Default for TraceLevelConfig {
+    fn default() -> Self {
+        Self {
+            create_url: Level::INFO,
+            get_url: Level::INFO,
+        }
+    }
+}
+
+
+impl TraceLevelConfig {
+    /// This function parses a comma-separated list of `handler=level` pairs,
+    /// e.g. `get_url=debug,create_url=info`. Handlers that are not listed keep the `info` level.
+    pub fn parse(levels: &str) -> Result<Self> {
+        let mut config = Self::default();
+
+        for entry in levels.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (handler, level) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid handler trace level, expected handler=level: {}", entry))?;
+            let level: Level = level.trim().parse()
+                .map_err(|_| anyhow!("Invalid trace level for handler {}: {}", handler, level))?;
+
+            match handler.trim() {
+                "create_url" => config.create_url = level,
+                "get_url" => config.get_url = level,
+                _ => return Err(anyhow!("Unknown handler in HANDLER_TRACE_LEVELS: {}", handler)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+
+impl Default for VisitStreamConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            capacity: 1024,
+        }
+    }
+}
+
+
+impl VisitStreamConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("VISIT_STREAM_TOKEN", &mut self.token, |token| Ok(Some(token).filter(|token| !token.is_empty())))?;
+        override_from_env("VISIT_STREAM_CAPACITY", &mut self.capacity, |capacity| Ok(capacity.parse()?))?;
+
+        if self.capacity == 0 {
+            return Err(anyhow!("VISIT_STREAM_CAPACITY must be greater than 0"));
+        }
+
+        Ok(self)
+    }
+}
+
+
+impl AnalyticsMode {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(self) -> Result<Self> {
+        let analytics_mode = env::var("ANALYTICS_MODE").ok();
+        match (analytics_mode.as_deref(), self) {
+            (None | Some("server"), AnalyticsMode::Server) | (Some("server"), _) => Ok(AnalyticsMode::Server),
+            (None | Some("beacon"), AnalyticsMode::Beacon { url }) => Ok(AnalyticsMode::Beacon { url: env::var("ANALYTICS_BEACON_URL").unwrap_or(url) }),
+            (Some("beacon"), _) => {
+                let url = env::var("ANALYTICS_BEACON_URL")
+                    .map_err(|_| anyhow!("ANALYTICS_BEACON_URL must be set when ANALYTICS_MODE is beacon"))?;
+                Ok(AnalyticsMode::Beacon { url })
+            },
+            (Some(analytics_mode), _) => Err(anyhow!("Unsupported analytics mode: {}", analytics_mode)),
+        }
     }
 }
 
 
+/// Reads a comma-separated list from an environment variable, ignoring empty entries.
+fn list_from_env(name: &str) -> BTreeSet<String> {
+    list(env::var(name).unwrap_or_default()).unwrap_or_default()
+}
+
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://localhost:6379".into(),
+            ttl_seconds: 2592000, // 2,592,000 seconds = 30 days
+        }
+    }
+}
+
+impl RedisConfig {
+    /// This function overrides the configuration with the environment variables whose names
+    /// start with `prefix` that are set.
+    pub fn with_env_prefix(mut self, prefix: &str) -> Result<Self> {
+        override_from_env(&format!("{prefix}REDIS_URL"), &mut self.url, Ok)?;
+        override_from_env(&format!("{prefix}REDIS_TTL_SECONDS"), &mut self.ttl_seconds, |ttl| Ok(ttl.parse()?))?;
+        Ok(self)
+    }
+}
+
+impl MemoryConfig {
+    /// This function overrides the configuration with the environment variables whose names
+    /// start with `prefix` that are set.
+    pub fn with_env_prefix(mut self, prefix: &str) -> Result<Self> {
+        override_from_env(&format!("{prefix}MEMORY_TTL_SECONDS"), &mut self.ttl, |ttl| Ok(Some(seconds(ttl)?)))?;
+        override_from_env(&format!("{prefix}MEMORY_SWEEP_INTERVAL_SECONDS"), &mut self.sweep_interval, |interval| Ok(Some(seconds(interval)?)))?;
+        // A zero duration means keys never expire, or are swept every `ttl`.
+        self.ttl = self.ttl.filter(|ttl| !ttl.is_zero());
+        self.sweep_interval = self.sweep_interval.filter(|interval| !interval.is_zero());
+        Ok(self)
+    }
+}
+
+impl Default for ScyllaDBConfig {
+    fn default() -> Self {
+        Self {
+            url: "localhost:9042".into(),
+            keyspace: "examples_ks".into(),
+            replication_factor: 3,
+            replication_strategy: None,
+            hash_partition_keys: false,
+            warmup: ScyllaWarmup::Disabled,
+            default_ttl_seconds: 2592000, // 2,592,000 seconds = 30 days
+            alter_ttl: false,
+        }
+    }
+}
+
 impl ScyllaDBConfig {
-    /// This function creates a new `ScyllaDBConfig` from environment variables.
-    pub fn from_env() -> Result<Self> {
-        let url = env::var("SCYLLA_URI").unwrap_or("localhost:9042".into());
-        let keyspace = env::var("SCYLLA_KEYSPACE").unwrap_or("examples_ks".into());
-        let replication_factor = env::var("SCYLLA_REPLICATION_FACTOR")
-            .unwrap_or("3".into())
-            .parse()?;
-
-        Ok(Self {
-            url,
-            keyspace,
-            replication_factor,
-        })
+    /// This function overrides the configuration with the environment variables whose names
+    /// start with `prefix` that are set.
+    pub fn with_env_prefix(mut self, prefix: &str) -> Result<Self> {
+        override_from_env(&format!("{prefix}SCYLLA_URI"), &mut self.url, Ok)?;
+        override_from_env(&format!("{prefix}SCYLLA_KEYSPACE"), &mut self.keyspace, Ok)?;
+        override_from_env(&format!("{prefix}SCYLLA_REPLICATION_FACTOR"), &mut self.replication_factor, |factor| Ok(factor.parse()?))?;
+        let factor = self.replication_factor;
+        override_from_env(&format!("{prefix}SCYLLA_REPLICATION_STRATEGY"), &mut self.replication_strategy, |strategy| match strategy.as_str() {
+            "" => Ok(None),
+            "simple" => Ok(Some(ReplicationStrategy::SimpleStrategy { factor })),
+            "network_topology" => {
+                let per_dc = env::var(format!("{prefix}SCYLLA_REPLICATION_DCS"))
+                    .map_err(|_| anyhow!("{prefix}SCYLLA_REPLICATION_DCS must be set with the network_topology replication strategy"))?;
+                Ok(Some(ReplicationStrategy::NetworkTopologyStrategy { per_dc: replication_per_dc(per_dc)? }))
+            },
+            strategy => Err(anyhow!("Unsupported replication strategy: {}", strategy)),
+        })?;
+        // The factors also override those of a network_topology strategy set in the configuration file.
+        match &mut self.replication_strategy {
+            Some(ReplicationStrategy::NetworkTopologyStrategy { per_dc }) => {
+                override_from_env(&format!("{prefix}SCYLLA_REPLICATION_DCS"), per_dc, replication_per_dc)?;
+            },
+            _ if env::var_os(format!("{prefix}SCYLLA_REPLICATION_DCS")).is_some() => {
+                return Err(anyhow!("{prefix}SCYLLA_REPLICATION_DCS is only used with the network_topology replication strategy"));
+            },
+            _ => {},
+        }
+        override_from_env(&format!("{prefix}SCYLLA_HASH_PARTITION_KEYS"), &mut self.hash_partition_keys, flag)?;
+
+        let warmup = env::var(format!("{prefix}SCYLLA_WARMUP")).map(flag).ok().transpose()?;
+        let strict = env::var(format!("{prefix}SCYLLA_WARMUP_STRICT")).map(flag).ok().transpose()?;
+        if warmup.is_some() || strict.is_some() {
+            let warmup = warmup.unwrap_or(self.warmup != ScyllaWarmup::Disabled);
+            let strict = strict.unwrap_or(self.warmup == ScyllaWarmup::Strict);
+            self.warmup = match (warmup, strict) {
+                (true, true) => ScyllaWarmup::Strict,
+                (true, false) => ScyllaWarmup::Warn,
+                (false, _) => ScyllaWarmup::Disabled,
+            };
+        }
+
+        override_from_env(&format!("{prefix}SCYLLA_URL_TTL_SECONDS"), &mut self.default_ttl_seconds, |default_ttl_seconds| {
+            let default_ttl_seconds = default_ttl_seconds.parse::<i64>()?;
+            u32::try_from(default_ttl_seconds)
+                .map_err(|_| anyhow!("{prefix}SCYLLA_URL_TTL_SECONDS must be between 0 and {}: {}", MAX_SCYLLA_TTL_SECONDS, default_ttl_seconds))
+        })?;
+        override_from_env(&format!("{prefix}SCYLLA_ALTER_TTL"), &mut self.alter_ttl, flag)?;
+        self.validate_default_ttl(prefix)?;
+        self.validate_replication()?;
+        Ok(self)
+    }
+
+    /// Checks that ScyllaDB accepts the default TTL, as it would only reject it when creating or
+    /// altering the tables.
+    fn validate_default_ttl(&self, prefix: &str) -> Result<()> {
+        if self.default_ttl_seconds > MAX_SCYLLA_TTL_SECONDS {
+            return Err(anyhow!("{prefix}SCYLLA_URL_TTL_SECONDS must be between 0 and {}: {}", MAX_SCYLLA_TTL_SECONDS, self.default_ttl_seconds));
+        }
+        Ok(())
+    }
+
+    /// Checks that the keyspace would get at least one replica in every datacenter it names, as
+    /// ScyllaDB would only reject the replication when creating the keyspace.
+    fn validate_replication(&self) -> Result<()> {
+        match &self.replication_strategy {
+            None if self.replication_factor <= 0 => {
+                Err(anyhow!("The ScyllaDB replication factor must be greater than 0: {}", self.replication_factor))
+            },
+            Some(ReplicationStrategy::SimpleStrategy { factor }) if *factor <= 0 => {
+                Err(anyhow!("The ScyllaDB replication factor must be greater than 0: {}", factor))
+            },
+            Some(ReplicationStrategy::NetworkTopologyStrategy { per_dc }) if per_dc.is_empty() => {
+                Err(anyhow!("The network_topology replication strategy needs at least one datacenter"))
+            },
+            Some(ReplicationStrategy::NetworkTopologyStrategy { per_dc }) => match per_dc.iter().find(|(_, factor)| **factor <= 0) {
+                Some((dc, factor)) => Err(anyhow!("The replication factor of datacenter {} must be greater than 0: {}", dc, factor)),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+
+impl AccessLogConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(self) -> Result<Self> {
+        let rotation = match env::var("ACCESS_LOG_ROTATION") {
+            Ok(rotation) => match rotation.as_str() {
+                "hourly" => LogRotation::Hourly,
+                "daily" => LogRotation::Daily,
+                "never" => LogRotation::Never,
+                rotation => return Err(anyhow!("Unsupported access log rotation: {}", rotation)),
+            },
+            Err(_) => match &self {
+                Self::File { rotation, .. } => *rotation,
+                _ => default_log_rotation(),
+            },
+        };
+
+        let config = match env::var("ACCESS_LOG_PATH") {
+            Ok(path) => match path.as_str() {
+                "" => Self::Disabled,
+                "-" | "stdout" => Self::Stdout,
+                path => Self::File { path: path.into(), rotation },
+            },
+            Err(_) => self,
+        };
+        match config {
+            Self::File { path, .. } => Ok(Self::File { path, rotation }),
+            config => Ok(config),
+        }
+    }
+}
+
+
+impl Default for KeyAuditConfig {
+    fn default() -> Self {
+        Self {
+            destination: KeyAuditDestination::Disabled,
+            owner_header: "x-owner".into(),
+        }
+    }
+}
+
+
+impl KeyAuditConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        let enabled = match env::var("KEY_AUDIT_ENABLED") {
+            Ok(enabled) => flag(enabled)?,
+            Err(_) => self.destination != KeyAuditDestination::Disabled,
+        };
+        self.destination = match (enabled, env::var("KEY_AUDIT_PATH").ok().as_deref(), self.destination) {
+            (false, _, _) => KeyAuditDestination::Disabled,
+            (true, Some("" | "-" | "stdout"), _) | (true, None, KeyAuditDestination::Disabled) => KeyAuditDestination::Stdout,
+            (true, Some(path), _) => KeyAuditDestination::File(path.into()),
+            (true, None, destination) => destination,
+        };
+        override_from_env("KEY_AUDIT_OWNER_HEADER", &mut self.owner_header, Ok)?;
+        self.owner_header = self.owner_header.to_lowercase();
+        check_header_name(&self.owner_header, "key audit owner header")?;
+
+        Ok(self)
+    }
+}
+
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10000,
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(5),
+            eviction_policy: CacheEvictionPolicy::default(),
+        }
+    }
+}
+
+
+impl CacheConfig {
+    /// This function overrides an optional configuration with the environment variables that are set.
+    /// `CACHE_ENABLED` enables or disables the cache, which is otherwise enabled if configured.
+    pub fn with_env(config: Option<Self>) -> Result<Option<Self>> {
+        let enabled = match env::var("CACHE_ENABLED") {
+            Ok(enabled) => flag(enabled)?,
+            Err(_) => config.is_some(),
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let mut config = config.unwrap_or_default();
+        override_from_env("CACHE_CAPACITY", &mut config.capacity, |capacity| Ok(capacity.parse()?))?;
+        override_from_env("CACHE_TTL_SECONDS", &mut config.ttl, seconds)?;
+        override_from_env("CACHE_NEGATIVE_TTL_SECONDS", &mut config.negative_ttl, seconds)?;
+        override_from_env("CACHE_EVICTION_POLICY", &mut config.eviction_policy, |policy| match policy.as_str() {
+            "lru" => Ok(CacheEvictionPolicy::Lru),
+            "lfu" => Ok(CacheEvictionPolicy::Lfu),
+            "ttl" => Ok(CacheEvictionPolicy::Ttl),
+            policy => Err(anyhow!("Unsupported cache eviction policy: {}", policy)),
+        })?;
+        Ok(Some(config))
+    }
+}
+
+
+impl UdsConfig {
+    /// This function overrides an optional configuration with the environment variables that are set.
+    /// An empty `LISTEN_UDS_PATH` disables the Unix domain socket listener.
+    pub fn with_env(config: Option<Self>) -> Result<Option<Self>> {
+        let config = match env::var("LISTEN_UDS_PATH") {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => Some(Self { path: path.into(), mode: config.map_or_else(default_uds_mode, |config| config.mode) }),
+            Err(_) => config,
+        };
+        let Some(mut config) = config else {
+            return Ok(None);
+        };
+        override_from_env("LISTEN_UDS_MODE", &mut config.mode, |mode| {
+            u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .map_err(|_| anyhow!("Invalid LISTEN_UDS_MODE, expected octal permissions: {}", mode))
+        })?;
+        Ok(Some(config))
+    }
+}
+
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            header: "x-api-key".into(),
+            api_keys: BTreeSet::new(),
+        }
+    }
+}
+
+
+impl AuthConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("API_KEY_HEADER", &mut self.header, Ok)?;
+        self.header = self.header.to_lowercase();
+        check_header_name(&self.header, "API key header")?;
+        override_from_env("API_KEYS", &mut self.api_keys, list)?;
+
+        Ok(self)
+    }
+}
+
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_payload_bytes: 5 * 1024,
+            max_bulk_items: 1000,
+            max_short_url_length: 2048,
+            max_ttl: Duration::from_secs(365 * 24 * 60 * 60),
+            max_background_tasks: 10_000,
+        }
+    }
+}
+
+
+impl LimitsConfig {
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("MAX_PAYLOAD_BYTES", &mut self.max_payload_bytes, |max_bytes| Ok(max_bytes.parse()?))?;
+        override_from_env("MAX_BULK_ITEMS", &mut self.max_bulk_items, |max_items| Ok(max_items.parse()?))?;
+        override_from_env("MAX_SHORT_URL_LENGTH", &mut self.max_short_url_length, |length| Ok(length.parse()?))?;
+        override_from_env("MAX_TTL_SECONDS", &mut self.max_ttl, seconds)?;
+        override_from_env("MAX_BACKGROUND_TASKS", &mut self.max_background_tasks, |max_tasks| Ok(max_tasks.parse()?))?;
+
+        Ok(self)
+    }
+}
+
+
+impl Default for RedirectionServiceConfig {
+    fn default() -> Self {
+        Self {
+            port: 8081,
+            admin_port: None,
+            db_config: DBConfig::default(),
+            cache: None,
+            task_sender: TaskSender::default(),
+            key_generator: KeyGeneratorConfig::default(),
+            handler: HandlerConfig::default(),
+            limits: LimitsConfig::default(),
+            shutdown_drain: Duration::from_secs(1),
+            shutdown_timeout: Duration::from_secs(30),
+            access_log: AccessLogConfig::default(),
+            key_audit: KeyAuditConfig::default(),
+            auth: AuthConfig::default(),
+            listen_uds: None,
+            max_in_flight: None,
+            error_pages_dir: None,
+        }
     }
 }
 
@@ -142,19 +1518,296 @@ impl ScyllaDBConfig {
 impl RedirectionServiceConfig {
     /// This function creates a new `RedirectionServiceConfig` from environment variables.
     pub fn from_env() -> Result<Self> {
-        let port = env::var("REDIRECTION_SERVICE_PORT")
-            .unwrap_or_else(|_| "8081".to_string())
-            .parse::<u16>()?;
-        
-        let db_config: DBConfig = DBConfig::from_env()?;
-        let task_sender: TaskSender = TaskSender::from_env()?;
-        let key_generator: KeyGeneratorConfig = KeyGeneratorConfig::from_env()?;
-        
-        Ok(Self {
-            port,
-            db_config,
-            task_sender,
-            key_generator,
-        })
+        Self::default().with_env()
+    }
+
+    /// This function creates a new `RedirectionServiceConfig` from a TOML file, overridden by the
+    /// environment variables that are set. Values missing from the file take their defaults.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the TOML configuration file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the configuration, or an error if the file cannot be read or parsed.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Error reading configuration file {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Error parsing configuration file {}", path.display()))?;
+        config.with_env()
+    }
+
+    /// This function overrides the configuration with the environment variables that are set.
+    pub fn with_env(mut self) -> Result<Self> {
+        override_from_env("REDIRECTION_SERVICE_PORT", &mut self.port, |port| Ok(port.parse()?))?;
+        override_from_env("ADMIN_METRICS_PORT", &mut self.admin_port, |port| Ok(Some(port.parse()?)))?;
+        self.db_config = self.db_config.with_env()?;
+        self.cache = CacheConfig::with_env(self.cache)?;
+        self.task_sender = self.task_sender.with_env()?;
+        self.key_generator = self.key_generator.with_env()?;
+        self.handler = self.handler.with_env()?;
+        if let KeyGeneratorConfig::Local(local) = &self.key_generator {
+            local.validate_keys(&self.handler.keys)?;
+        }
+        self.limits = self.limits.with_env()?;
+        override_from_env("SHUTDOWN_DRAIN_SECONDS", &mut self.shutdown_drain, seconds)?;
+        override_from_env("SHUTDOWN_TIMEOUT_SECONDS", &mut self.shutdown_timeout, seconds)?;
+        self.access_log = self.access_log.with_env()?;
+        self.key_audit = self.key_audit.with_env()?;
+        self.auth = self.auth.with_env()?;
+        self.listen_uds = UdsConfig::with_env(self.listen_uds)?;
+        override_from_env("MAX_IN_FLIGHT", &mut self.max_in_flight, |max_in_flight| Ok(Some(max_in_flight.parse()?)))?;
+        // No requests could be served with a budget of 0, so it disables the limit.
+        self.max_in_flight = self.max_in_flight.filter(|max_in_flight| *max_in_flight > 0);
+        override_from_env("ERROR_PAGES_DIR", &mut self.error_pages_dir, |dir| Ok(Some(dir).filter(|dir| !dir.is_empty()).map(PathBuf::from)))?;
+
+        Ok(self)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+        port = 9090
+        admin_port = 9091
+        shutdown_drain_seconds = 5
+        shutdown_timeout_seconds = 10
+        max_in_flight = 100
+
+        [db_config]
+        type = "scylla"
+        url = "scylla:9042"
+        keyspace = "urls"
+        warmup = "strict"
+        replication_strategy = { type = "network_topology", per_dc = { dc1 = 3, dc2 = 2 } }
+
+        [cache]
+        capacity = 500
+        eviction_policy = "lfu"
+
+        [task_sender]
+        type = "nats"
+        url = "nats://nats:4222"
+        ack_timeout_ms = 2000
+
+        [key_generator]
+        type = "grpc"
+        url = "http://keygen:8080"
+        metadata = { tenant = "acme" }
+        compression = true
+
+        [handler]
+        blocked_keys = ["blocked"]
+        allowed_target_schemes = ["HTTPS"]
+        analytics = { type = "beacon", url = "https://beacon.example.com" }
+        deprecated_routes = [{ prefix = "/api/v1/", deprecated_at = 1767225600 }]
+
+        [handler.trace_levels]
+        get_url = "debug"
+
+        [limits]
+        max_ttl_seconds = 3600
+        max_bulk_items = 100
+
+        [access_log]
+        type = "file"
+        path = "/var/log/access.log"
+
+        [key_audit]
+        destination = { file = "/var/log/keys.log" }
+
+        [listen_uds]
+        path = "/run/redirection.sock"
+        mode = 0o600
+    "#;
+
+    #[test]
+    fn test_parse_sample() {
+        let config: RedirectionServiceConfig = toml::from_str(SAMPLE).unwrap();
+
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.admin_port, Some(9091));
+        assert_eq!(config.shutdown_drain, Duration::from_secs(5));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(10));
+        assert_eq!(config.max_in_flight, Some(100));
+        assert_eq!(config.db_config, DBConfig::ScyllaDB(ScyllaDBConfig {
+            url: "scylla:9042".into(),
+            keyspace: "urls".into(),
+            warmup: ScyllaWarmup::Strict,
+            replication_strategy: Some(ReplicationStrategy::NetworkTopologyStrategy {
+                per_dc: BTreeMap::from([("dc1".into(), 3), ("dc2".into(), 2)]),
+            }),
+            ..ScyllaDBConfig::default()
+        }));
+        assert_eq!(config.cache, Some(CacheConfig { capacity: 500, eviction_policy: CacheEvictionPolicy::Lfu, ..CacheConfig::default() }));
+        assert_eq!(config.task_sender, TaskSender::Nats(NatsConfig {
+            url: "nats://nats:4222".into(),
+            ack_timeout: Duration::from_secs(2),
+            ..NatsConfig::default()
+        }));
+        assert_eq!(config.key_generator, KeyGeneratorConfig::GRPCKeyGeneratorConfig(GRPCKeyGeneratorConfig {
+            url: "http://keygen:8080".into(),
+            api_key: None,
+            metadata: BTreeMap::from([("tenant".into(), "acme".into())]),
+            compression: true,
+        }));
+        assert_eq!(config.handler, HandlerConfig {
+            blocked_keys: BTreeSet::from(["blocked".into()]),
+            allowed_target_schemes: BTreeSet::from(["HTTPS".into()]),
+            analytics: AnalyticsMode::Beacon { url: "https://beacon.example.com".into() },
+            deprecated_routes: vec![DeprecatedRoute { prefix: "/api/v1/".into(), deprecated_at: 1767225600, sunset: None }],
+            trace_levels: TraceLevelConfig { create_url: Level::INFO, get_url: Level::DEBUG },
+            ..HandlerConfig::default()
+        });
+        assert_eq!(config.limits, LimitsConfig { max_ttl: Duration::from_secs(3600), max_bulk_items: 100, ..LimitsConfig::default() });
+        assert_eq!(config.access_log, AccessLogConfig::File { path: "/var/log/access.log".into(), rotation: LogRotation::Daily });
+        assert_eq!(config.key_audit.destination, KeyAuditDestination::File("/var/log/keys.log".into()));
+        assert_eq!(config.listen_uds, Some(UdsConfig { path: "/run/redirection.sock".into(), mode: 0o600 }));
+        assert_eq!(config.auth, AuthConfig::default());
+    }
+
+    #[test]
+    fn test_parse_empty_file_defaults() {
+        let config: RedirectionServiceConfig = toml::from_str("").unwrap();
+        assert_eq!(config, RedirectionServiceConfig::default());
+    }
+
+    #[test]
+    fn test_parse_scylla_url_ttl() {
+        let config: RedirectionServiceConfig = toml::from_str(r#"
+            [db_config]
+            type = "scylla"
+            default_ttl_seconds = 3600
+        "#).unwrap();
+        let DBConfig::ScyllaDB(scylla) = config.db_config else {
+            panic!("Unexpected database {:?}", config.db_config);
+        };
+        assert_eq!(scylla.default_ttl_seconds, 3600);
+    }
+
+    #[test]
+    fn test_parse_dual_write_encrypted() {
+        let config: RedirectionServiceConfig = toml::from_str(r#"
+            [db_config]
+            type = "encrypted"
+            key = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+
+            [db_config.inner]
+            type = "dual_write"
+            primary = { type = "redis", url = "redis://redis:6379" }
+            secondary = { type = "memory", ttl_seconds = 60 }
+
+            [task_sender]
+            type = "kafka"
+            topic = "visits"
+
+            [key_generator]
+            type = "local"
+            length = 10
+        "#).unwrap();
+
+        assert_eq!(config.db_config, DBConfig::Encrypted(EncryptedConfig {
+            inner: Box::new(DBConfig::DualWrite(DualWriteConfig {
+                primary: Box::new(DBConfig::Redis(RedisConfig { url: "redis://redis:6379".into(), ..RedisConfig::default() })),
+                secondary: Box::new(DBConfig::Memory(MemoryConfig { ttl: Some(Duration::from_secs(60)), sweep_interval: None })),
+            })),
+            key: EncryptionKey(std::array::from_fn(|i| i as u8)),
+        }));
+        assert_eq!(config.task_sender, TaskSender::Kafka(KafkaConfig { topic: "visits".into(), ..KafkaConfig::default() }));
+        assert_eq!(config.key_generator, KeyGeneratorConfig::Local(LocalKeyGeneratorConfig { length: 10, ..LocalKeyGeneratorConfig::default() }));
+    }
+
+    #[test]
+    fn test_parse_hashids_key_generator() {
+        let config: RedirectionServiceConfig = toml::from_str(r#"
+            [key_generator]
+            type = "hashids"
+            salt = "my salt"
+        "#).unwrap();
+
+        assert_eq!(config.key_generator, KeyGeneratorConfig::Hashids(HashidsKeyGeneratorConfig {
+            salt: "my salt".into(),
+            ..HashidsKeyGeneratorConfig::default()
+        }));
+    }
+
+    #[test]
+    fn test_local_key_generator_validate_keys() {
+        let keys = KeySpecConfig::default();
+        assert!(LocalKeyGeneratorConfig::default().validate_keys(&keys).is_ok());
+
+        for (config, error) in [
+            (LocalKeyGeneratorConfig { alphabet: "äö".into(), ..LocalKeyGeneratorConfig::default() }, "ASCII"),
+            (LocalKeyGeneratorConfig { alphabet: "abc.".into(), ..LocalKeyGeneratorConfig::default() }, "'.'"),
+            (LocalKeyGeneratorConfig { length: 20, ..LocalKeyGeneratorConfig::default() }, "LOCAL_KEY_LENGTH (20)"),
+        ] {
+            let keys = KeySpecConfig { max_length: 16, ..KeySpecConfig::default() };
+            let err = config.validate_keys(&keys).unwrap_err();
+            assert!(err.to_string().contains(error), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_validate_default_ttl() {
+        let config = |default_ttl_seconds| ScyllaDBConfig { default_ttl_seconds, ..ScyllaDBConfig::default() };
+        assert!(config(0).validate_default_ttl("").is_ok());
+        assert!(config(MAX_SCYLLA_TTL_SECONDS).validate_default_ttl("").is_ok());
+
+        let err = config(MAX_SCYLLA_TTL_SECONDS + 1).validate_default_ttl("SECONDARY_").unwrap_err();
+        assert_eq!(err.to_string(), "SECONDARY_SCYLLA_URL_TTL_SECONDS must be between 0 and 630720000: 630720001");
+    }
+
+    #[test]
+    fn test_validate_replication() {
+        let config = |replication_factor, replication_strategy| ScyllaDBConfig { replication_factor, replication_strategy, ..ScyllaDBConfig::default() };
+        let network_topology = |per_dc: &[(&str, i32)]| Some(ReplicationStrategy::NetworkTopologyStrategy {
+            per_dc: per_dc.iter().map(|(dc, factor)| (dc.to_string(), *factor)).collect(),
+        });
+
+        assert!(config(3, None).validate_replication().is_ok());
+        assert!(config(0, Some(ReplicationStrategy::SimpleStrategy { factor: 1 })).validate_replication().is_ok());
+        assert!(config(0, network_topology(&[("dc1", 3), ("dc2", 1)])).validate_replication().is_ok());
+
+        for (config, error) in [
+            (config(0, None), "replication factor must be greater than 0"),
+            (config(3, Some(ReplicationStrategy::SimpleStrategy { factor: -1 })), "replication factor must be greater than 0"),
+            (config(3, network_topology(&[])), "at least one datacenter"),
+            (config(3, network_topology(&[("dc1", 3), ("dc2", 0)])), "datacenter dc2 must be greater than 0"),
+        ] {
+            let err = config.validate_replication().unwrap_err();
+            assert!(err.to_string().contains(error), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_base_url() {
+        assert_eq!(normalize_base_url("https://sho.rt").unwrap(), "https://sho.rt/");
+        assert_eq!(normalize_base_url("https://sho.rt/").unwrap(), "https://sho.rt/");
+        assert_eq!(normalize_base_url("https://sho.rt/go//").unwrap(), "https://sho.rt/go/");
+        assert_eq!(normalize_base_url("http://localhost:8081/s").unwrap(), "http://localhost:8081/s/");
+
+        for base in ["sho.rt", "ftp://sho.rt", "https://sho.rt/?q=1", "https://sho.rt/#top"] {
+            assert!(normalize_base_url(base).is_err(), "{base}");
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (contents, error) in [
+            ("prot = 9090", "unknown field `prot`"),
+            ("[db_config]\ntype = \"mongo\"", "unknown variant `mongo`"),
+            ("[db_config]\ntype = \"scylla\"\nuri = \"scylla:9042\"", "unknown field `uri`"),
+            ("[db_config]\ntype = \"encrypted\"\nkey = \"c2hvcnQ=\"\ninner = { type = \"memory\" }", "TARGET_ENCRYPTION_KEY must be 32 bytes long"),
+            ("[handler.trace_levels]\nget_url = \"loud\"", "invalid trace level: loud"),
+            ("[cache]\neviction_policy = \"fifo\"", "unknown variant `fifo`"),
+        ] {
+            let err = toml::from_str::<RedirectionServiceConfig>(contents).unwrap_err();
+            assert!(err.to_string().contains(error), "{contents}: {err}");
+        }
     }
 }
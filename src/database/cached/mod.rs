@@ -0,0 +1,374 @@
+//! This module provides a database decorator that caches the redirect targets in memory.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use moka::Expiry;
+use moka::future::{Cache, CacheBuilder};
+use moka::policy::EvictionPolicy;
+use tracing::instrument;
+use crate::config::{CacheConfig, CacheEvictionPolicy};
+use crate::database::{Database, RedirectTarget};
+use crate::database::error::DatabaseError;
+
+
+/// A database that serves the lookups of popular keys from a bounded in-memory cache,
+/// populated on a miss, with the configured eviction policy.
+///
+/// Keys that do not exist are cached too, for a shorter time, so scans for non-existent keys
+/// are not amplified onto the inner database. Targets are never cached past the expiry of their
+/// key. Inserts through this database invalidate the cached entries of their key, but writes
+/// from other instances are only seen once the entries expire.
+#[derive(Clone)]
+pub struct CachedDatabase {
+    inner: Arc<dyn Database>,
+    found: Cache<String, RedirectTarget>,
+    missing: Cache<String, ()>,
+    /// Incremented on every write, so a lookup that raced with one drops what it cached.
+    generation: Arc<AtomicU64>,
+}
+
+
+impl std::fmt::Debug for CachedDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedDatabase").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+
+impl CachedDatabase {
+    /// Creates a new `CachedDatabase`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The database the lookups fall back to.
+    /// * `config` - The configuration for the cache.
+    ///
+    /// # Returns
+    ///
+    /// A new `CachedDatabase` instance.
+    pub fn new(inner: Arc<dyn Database>, config: &CacheConfig) -> Self {
+        let found = Cache::builder().time_to_live(config.ttl).expire_after(KeyExpiry);
+        let found = build_cache(found, config.eviction_policy, config.capacity);
+        // Lookups can name any number of non-existent keys, so their cache is always bounded.
+        let missing_policy = match config.eviction_policy {
+            CacheEvictionPolicy::Ttl => CacheEvictionPolicy::Lru,
+            policy => policy,
+        };
+        let missing = build_cache(Cache::builder().time_to_live(config.negative_ttl), missing_policy, config.capacity);
+        Self { inner, found, missing, generation: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Drops the cached entries of a key, once it is written.
+    ///
+    /// The generation is incremented first, so a lookup of the key still in flight either sees
+    /// it changed and drops what it cached, or caches before the entries are dropped here.
+    async fn invalidate(&self, key_id: &str) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.found.invalidate(key_id).await;
+        self.missing.invalidate(key_id).await;
+    }
+
+    /// Returns whether a write happened since `generation` was read, so what a lookup read
+    /// before it may be stale.
+    fn written_since(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) != generation
+    }
+}
+
+
+/// Expires the cached targets with their key, when it expires before the cache TTL.
+struct KeyExpiry;
+
+
+impl Expiry<String, RedirectTarget> for KeyExpiry {
+    fn expire_after_create(&self, _key: &String, target: &RedirectTarget, _created_at: Instant) -> Option<Duration> {
+        target.expires_in
+    }
+}
+
+
+/// Builds a cache from `builder`, bounded to `capacity` entries unless the eviction policy is
+/// `Ttl`.
+fn build_cache<V: Clone + Send + Sync + 'static>(builder: CacheBuilder<String, V, Cache<String, V>>, eviction_policy: CacheEvictionPolicy, capacity: u64) -> Cache<String, V> {
+    match eviction_policy {
+        CacheEvictionPolicy::Lru => builder.max_capacity(capacity).eviction_policy(EvictionPolicy::lru()).build(),
+        CacheEvictionPolicy::Lfu => builder.max_capacity(capacity).eviction_policy(EvictionPolicy::tiny_lfu()).build(),
+        CacheEvictionPolicy::Ttl => builder.build(),
+    }
+}
+
+
+#[async_trait]
+impl Database for CachedDatabase {
+    /// Retrieves the URL associated with a given key from the cache, or from the inner database
+    /// on a miss. Concurrent misses of a key share a single lookup of the inner database.
+    #[instrument(level = "info", target = "CachedDatabase::get_key_url")]
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError> {
+        if self.missing.contains_key(key_id) {
+            return Err(DatabaseError::NotExist(key_id.to_string()));
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let result = self.found
+            .entry_by_ref(key_id)
+            .or_try_insert_with(self.inner.get_key_url(key_id))
+            .await;
+        match result {
+            Ok(entry) => {
+                if entry.is_fresh() && self.written_since(generation) {
+                    self.found.invalidate(key_id).await;
+                }
+                Ok(entry.into_value())
+            },
+            Err(err) => match err.as_ref() {
+                DatabaseError::NotExist(key) => {
+                    self.missing.insert(key_id.to_string(), ()).await;
+                    if self.written_since(generation) {
+                        self.missing.invalidate(key_id).await;
+                    }
+                    Err(DatabaseError::NotExist(key.clone()))
+                },
+                err => Err(err.clone()),
+            },
+        }
+    }
+
+    /// Inserts a new key-URL pair into the inner database.
+    #[instrument(level = "info", target = "CachedDatabase::insert_key_with_ttl")]
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.inner.insert_key_with_ttl(key_id.clone(), target, ttl).await?;
+        self.invalidate(&key_id).await;
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair into the inner database, unless the key already exists.
+    #[instrument(level = "info", target = "CachedDatabase::insert_key_if_absent")]
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        self.inner.insert_key_if_absent(key_id.clone(), target).await?;
+        self.invalidate(&key_id).await;
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair that expires after `ttl` into the inner database, unless the key
+    /// already exists.
+    #[instrument(level = "info", target = "CachedDatabase::insert_key_with_ttl_if_absent")]
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.inner.insert_key_with_ttl_if_absent(key_id.clone(), target, ttl).await?;
+        self.invalidate(&key_id).await;
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into the inner database.
+    #[instrument(level = "info", target = "CachedDatabase::insert_limited_key")]
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.inner.insert_limited_key(key_id.clone(), target, max_uses).await?;
+        self.invalidate(&key_id).await;
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into the inner database, unless
+    /// the key already exists.
+    #[instrument(level = "info", target = "CachedDatabase::insert_limited_key_if_absent")]
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.inner.insert_limited_key_if_absent(key_id.clone(), target, max_uses).await?;
+        self.invalidate(&key_id).await;
+        Ok(())
+    }
+
+    /// Consumes one visit of a key. Visits are never cached, so limits are enforced by the inner database.
+    #[instrument(level = "info", target = "CachedDatabase::consume_visit")]
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        self.inner.consume_visit(key_id).await
+    }
+
+    /// Increments the visit count of a key in the inner database. Visit counts are never cached.
+    #[instrument(level = "info", target = "CachedDatabase::record_visit")]
+    async fn record_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        self.inner.record_visit(key_id).await
+    }
+
+    /// Retrieves the visit count of a key from the inner database.
+    #[instrument(level = "info", target = "CachedDatabase::get_visit_count")]
+    async fn get_visit_count(&self, key_id: &str) -> Result<u64, DatabaseError> {
+        self.inner.get_visit_count(key_id).await
+    }
+
+    /// Checks that the inner database is reachable.
+    #[instrument(level = "debug", target = "CachedDatabase::health_check")]
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        self.inner.health_check().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MockDatabase;
+
+    fn config() -> CacheConfig {
+        CacheConfig { capacity: 100, ttl: Duration::from_secs(60), negative_ttl: Duration::from_secs(5), ..CacheConfig::default() }
+    }
+
+    /// Looks up `keys` in order through a cache of 2 entries, then returns the keys still cached.
+    async fn cached_keys(eviction_policy: CacheEvictionPolicy, keys: &[&str]) -> Vec<String> {
+        let mut inner = MockDatabase::new();
+        inner.expect_get_key_url().returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+
+        let config = CacheConfig { capacity: 2, eviction_policy, ..config() };
+        let db = CachedDatabase::new(Arc::new(inner), &config);
+        for key in keys {
+            db.get_key_url(key).await.unwrap();
+            db.found.run_pending_tasks().await;
+        }
+        let mut cached: Vec<_> = db.found.iter().map(|(key, _)| key.to_string()).collect();
+        cached.sort();
+        cached
+    }
+
+    #[tokio::test]
+    async fn test_lru_evicts_least_recently_used() {
+        let cached = cached_keys(CacheEvictionPolicy::Lru, &["a", "b", "a", "c"]).await;
+        assert_eq!(cached, ["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_lfu_keeps_frequently_used() {
+        // The new key is used less often than any cached key, so it is not admitted.
+        let cached = cached_keys(CacheEvictionPolicy::Lfu, &["a", "b", "a", "b", "a", "b", "c"]).await;
+        assert_eq!(cached, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_is_unbounded() {
+        let cached = cached_keys(CacheEvictionPolicy::Ttl, &["a", "b", "c"]).await;
+        assert_eq!(cached, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_bounds_missing_keys() {
+        let mut inner = MockDatabase::new();
+        inner.expect_get_key_url().returning(|key| Err(DatabaseError::NotExist(key.to_string())));
+
+        let config = CacheConfig { capacity: 2, eviction_policy: CacheEvictionPolicy::Ttl, ..config() };
+        let db = CachedDatabase::new(Arc::new(inner), &config);
+        for key in ["a", "b", "c", "d"] {
+            assert!(db.get_key_url(key).await.is_err());
+            db.missing.run_pending_tasks().await;
+        }
+        assert_eq!(db.missing.entry_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_key_url_served_from_cache() {
+        let mut inner = MockDatabase::new();
+        inner.expect_get_key_url()
+            .times(1)
+            .returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+
+        let db = CachedDatabase::new(Arc::new(inner), &config());
+        for _ in 0..2 {
+            assert_eq!(db.get_key_url("12345678").await.unwrap(), RedirectTarget::permanent("http://example.com"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_key_url_missing_cached() {
+        let mut inner = MockDatabase::new();
+        inner.expect_get_key_url()
+            .times(1)
+            .returning(|key| Err(DatabaseError::NotExist(key.to_string())));
+
+        let db = CachedDatabase::new(Arc::new(inner), &config());
+        for _ in 0..2 {
+            assert!(matches!(db.get_key_url("12345678").await, Err(DatabaseError::NotExist(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_key_url_errors_not_cached() {
+        let mut inner = MockDatabase::new();
+        inner.expect_get_key_url()
+            .times(2)
+            .returning(|_| Err(DatabaseError::UnavailableError("down".to_string())));
+
+        let db = CachedDatabase::new(Arc::new(inner), &config());
+        for _ in 0..2 {
+            assert!(matches!(db.get_key_url("12345678").await, Err(DatabaseError::UnavailableError(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_key_url_expires_with_key() {
+        let mut inner = MockDatabase::new();
+        inner.expect_get_key_url()
+            .times(2)
+            .returning(|_| Ok(RedirectTarget::permanent("http://example.com").expiring_in(Duration::from_millis(50))));
+
+        let db = CachedDatabase::new(Arc::new(inner), &config());
+        db.get_key_url("12345678").await.unwrap();
+        assert!(db.found.contains_key("12345678"));
+
+        // The key expires long before the cache TTL, and so does its entry.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!db.found.contains_key("12345678"));
+        db.get_key_url("12345678").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lookup_racing_write_not_cached() {
+        // The key is written while the lookups read it, so what they read may be stale.
+        let generation = Arc::new(AtomicU64::new(0));
+        let written = generation.clone();
+        let mut inner = MockDatabase::new();
+        let mut seq = mockall::Sequence::new();
+        inner.expect_get_key_url()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(move |key| {
+                written.fetch_add(1, Ordering::SeqCst);
+                Err(DatabaseError::NotExist(key.to_string()))
+            });
+        let written = generation.clone();
+        inner.expect_get_key_url()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(move |_| {
+                written.fetch_add(1, Ordering::SeqCst);
+                Ok(RedirectTarget::permanent("http://example.com"))
+            });
+        inner.expect_get_key_url()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(RedirectTarget::permanent("http://other.com")));
+
+        let mut db = CachedDatabase::new(Arc::new(inner), &config());
+        db.generation = generation;
+        assert!(matches!(db.get_key_url("12345678").await, Err(DatabaseError::NotExist(_))));
+        assert!(!db.missing.contains_key("12345678"));
+        assert_eq!(db.get_key_url("12345678").await.unwrap(), RedirectTarget::permanent("http://example.com"));
+        assert!(!db.found.contains_key("12345678"));
+        assert_eq!(db.get_key_url("12345678").await.unwrap(), RedirectTarget::permanent("http://other.com"));
+    }
+
+    #[tokio::test]
+    async fn test_insert_invalidates_missing_key() {
+        let mut inner = MockDatabase::new();
+        let mut seq = mockall::Sequence::new();
+        inner.expect_get_key_url()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|key| Err(DatabaseError::NotExist(key.to_string())));
+        inner.expect_insert_key_if_absent().returning(|_, _| Ok(()));
+        inner.expect_get_key_url()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+
+        let db = CachedDatabase::new(Arc::new(inner), &config());
+        assert!(db.get_key_url("12345678").await.is_err());
+        db.insert_key_if_absent("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+        assert_eq!(db.get_key_url("12345678").await.unwrap(), RedirectTarget::permanent("http://example.com"));
+    }
+}
@@ -0,0 +1,207 @@
+//! This module provides a database decorator that writes to two backends at once.
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use tracing::instrument;
+use tracing::log::error;
+use crate::database::{Database, RedirectTarget};
+use crate::database::error::DatabaseError;
+
+
+/// A database that dual-writes to a primary and a secondary backend, e.g. while migrating
+/// between backends.
+///
+/// Reads are served from the primary only. Writes go to the primary first and, if they
+/// succeed, to the secondary. A failed secondary write is logged but not reported to the
+/// caller, so the secondary can never take the service down.
+#[derive(Clone, Debug)]
+pub struct DualWriteDatabase {
+    primary: Arc<dyn Database>,
+    secondary: Arc<dyn Database>,
+}
+
+
+impl DualWriteDatabase {
+    /// Creates a new `DualWriteDatabase`.
+    ///
+    /// # Arguments
+    ///
+    /// * `primary` - The backend that serves reads and whose writes must succeed.
+    /// * `secondary` - The backend that receives best-effort copies of the writes.
+    ///
+    /// # Returns
+    ///
+    /// A new `DualWriteDatabase` instance.
+    pub fn new(primary: Arc<dyn Database>, secondary: Arc<dyn Database>) -> Self {
+        Self { primary, secondary }
+    }
+}
+
+
+#[async_trait]
+impl Database for DualWriteDatabase {
+    /// Retrieves the URL associated with a given key from the primary database.
+    #[instrument(level = "info", target = "DualWriteDatabase::get_key_url")]
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError> {
+        self.primary.get_key_url(key_id).await
+    }
+
+    /// Inserts a new key-URL pair into the primary database, and then into the secondary one.
+    #[instrument(level = "info", target = "DualWriteDatabase::insert_key_with_ttl")]
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.primary.insert_key_with_ttl(key_id.clone(), target.clone(), ttl).await?;
+
+        if let Err(err) = self.secondary.insert_key_with_ttl(key_id.clone(), target, ttl).await {
+            error!("Error writing key {} to the secondary database: {}", key_id, err);
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair into the primary database unless the key exists there, and then
+    /// into the secondary one. The primary database decides whether the key is taken.
+    #[instrument(level = "info", target = "DualWriteDatabase::insert_key_if_absent")]
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        self.primary.insert_key_if_absent(key_id.clone(), target.clone()).await?;
+
+        if let Err(err) = self.secondary.insert_key(key_id.clone(), target).await {
+            error!("Error writing key {} to the secondary database: {}", key_id, err);
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair that expires after `ttl` into the primary database unless the key
+    /// exists there, and then into the secondary one.
+    #[instrument(level = "info", target = "DualWriteDatabase::insert_key_with_ttl_if_absent")]
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.primary.insert_key_with_ttl_if_absent(key_id.clone(), target.clone(), ttl).await?;
+
+        if let Err(err) = self.secondary.insert_key_with_ttl(key_id.clone(), target, ttl).await {
+            error!("Error writing key {} to the secondary database: {}", key_id, err);
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a new limited key-URL pair into the primary database, and then into the secondary one.
+    #[instrument(level = "info", target = "DualWriteDatabase::insert_limited_key")]
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.primary.insert_limited_key(key_id.clone(), target.clone(), max_uses).await?;
+
+        if let Err(err) = self.secondary.insert_limited_key(key_id.clone(), target, max_uses).await {
+            error!("Error writing key {} to the secondary database: {}", key_id, err);
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a new limited key-URL pair into the primary database unless the key exists there,
+    /// and then into the secondary one.
+    #[instrument(level = "info", target = "DualWriteDatabase::insert_limited_key_if_absent")]
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.primary.insert_limited_key_if_absent(key_id.clone(), target.clone(), max_uses).await?;
+
+        if let Err(err) = self.secondary.insert_limited_key(key_id.clone(), target, max_uses).await {
+            error!("Error writing key {} to the secondary database: {}", key_id, err);
+        }
+
+        Ok(())
+    }
+
+    /// Consumes one visit of a key in the primary database.
+    /// Visits are not mirrored, so the secondary keeps the initial number of visits of each key.
+    #[instrument(level = "info", target = "DualWriteDatabase::consume_visit")]
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        self.primary.consume_visit(key_id).await
+    }
+
+    /// Increments the visit count of a key in the primary database.
+    /// Visit counts are not mirrored, like visits.
+    #[instrument(level = "info", target = "DualWriteDatabase::record_visit")]
+    async fn record_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        self.primary.record_visit(key_id).await
+    }
+
+    /// Retrieves the visit count of a key from the primary database.
+    #[instrument(level = "info", target = "DualWriteDatabase::get_visit_count")]
+    async fn get_visit_count(&self, key_id: &str) -> Result<u64, DatabaseError> {
+        self.primary.get_visit_count(key_id).await
+    }
+
+    /// Checks that the primary database is reachable.
+    /// The secondary one is not checked, as its failures never fail a request.
+    #[instrument(level = "debug", target = "DualWriteDatabase::health_check")]
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        self.primary.health_check().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MockDatabase;
+
+    #[tokio::test]
+    async fn test_insert_key_writes_both() {
+        let mut primary = MockDatabase::new();
+        let mut secondary = MockDatabase::new();
+
+        primary.expect_insert_key_with_ttl()
+            .withf(|key, target, ttl| key == "12345678" && target.url == "http://example.com" && *ttl == Some(Duration::from_secs(60)))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        secondary.expect_insert_key_with_ttl()
+            .withf(|key, target, ttl| key == "12345678" && target.url == "http://example.com" && *ttl == Some(Duration::from_secs(60)))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let db = DualWriteDatabase::new(Arc::new(primary), Arc::new(secondary));
+        db.insert_key_with_ttl("12345678".to_string(), RedirectTarget::permanent("http://example.com"), Some(Duration::from_secs(60))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_insert_key_primary_failure() {
+        // The secondary has no expectations, so writing to it panics.
+        let mut primary = MockDatabase::new();
+        primary.expect_insert_key_with_ttl().returning(|_, _, _| Err(DatabaseError::UnavailableError("down".to_string())));
+
+        let db = DualWriteDatabase::new(Arc::new(primary), Arc::new(MockDatabase::new()));
+        let err = db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::UnavailableError(_)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_key_secondary_failure() {
+        let mut primary = MockDatabase::new();
+        let mut secondary = MockDatabase::new();
+
+        primary.expect_insert_key_with_ttl().returning(|_, _, _| Ok(()));
+        secondary.expect_insert_key_with_ttl().returning(|_, _, _| Err(DatabaseError::UnavailableError("down".to_string())));
+
+        let db = DualWriteDatabase::new(Arc::new(primary), Arc::new(secondary));
+        assert!(db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_insert_key_if_absent_conflict() {
+        // The secondary has no expectations, so writing to it panics.
+        let mut primary = MockDatabase::new();
+        primary.expect_insert_key_if_absent().returning(|key, _| Err(DatabaseError::AlreadyExists(key)));
+
+        let db = DualWriteDatabase::new(Arc::new(primary), Arc::new(MockDatabase::new()));
+        let err = db.insert_key_if_absent("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::AlreadyExists(_)));
+    }
+
+    #[tokio::test]
+    async fn test_get_key_url_reads_primary() {
+        // The secondary has no expectations, so reading from it panics.
+        let mut primary = MockDatabase::new();
+        primary.expect_get_key_url().times(1).returning(|_| Ok(RedirectTarget::permanent("http://example.com")));
+
+        let db = DualWriteDatabase::new(Arc::new(primary), Arc::new(MockDatabase::new()));
+        assert_eq!(db.get_key_url("12345678").await.unwrap().url, "http://example.com");
+    }
+}
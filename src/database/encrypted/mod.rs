@@ -0,0 +1,202 @@
+//! This module provides a database decorator that stores the redirect targets encrypted.
+use std::sync::Arc;
+use std::time::Duration;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use tracing::instrument;
+use crate::config::EncryptionKey;
+use crate::database::{Database, RedirectTarget};
+use crate::database::error::DatabaseError;
+
+
+/// The size of an AES-GCM nonce, in bytes.
+const NONCE_SIZE: usize = 12;
+
+
+/// A database that encrypts the redirect targets with AES-256-GCM before storing them.
+///
+/// Each target is stored as the base64 encoding of a random nonce followed by the ciphertext.
+/// Keys are stored in clear, as they are needed for lookups.
+#[derive(Clone)]
+pub struct EncryptedDatabase {
+    inner: Arc<dyn Database>,
+    cipher: Aes256Gcm,
+}
+
+
+impl std::fmt::Debug for EncryptedDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedDatabase").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+
+impl EncryptedDatabase {
+    /// Creates a new `EncryptedDatabase`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The database storing the encrypted targets.
+    /// * `key` - The key the targets are encrypted with.
+    ///
+    /// # Returns
+    ///
+    /// A new `EncryptedDatabase` instance.
+    pub fn new(inner: Arc<dyn Database>, key: &EncryptionKey) -> Self {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0));
+        Self { inner, cipher }
+    }
+
+    fn encrypt(&self, url: &str) -> Result<String, DatabaseError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher
+            .encrypt(&nonce, url.as_bytes())
+            .map_err(|err| DatabaseError::UnknownError(format!("Error encrypting target: {err}")))?;
+
+        let mut stored = nonce.to_vec();
+        stored.extend_from_slice(&ciphertext);
+        Ok(STANDARD.encode(stored))
+    }
+
+    fn decrypt(&self, key_id: &str, stored: &str) -> Result<String, DatabaseError> {
+        let corrupt = || DatabaseError::CorruptData(key_id.to_string());
+
+        let stored = STANDARD.decode(stored).map_err(|_| corrupt())?;
+        if stored.len() < NONCE_SIZE {
+            return Err(corrupt());
+        }
+        let (nonce, ciphertext) = stored.split_at(NONCE_SIZE);
+        let url = self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| corrupt())?;
+        String::from_utf8(url).map_err(|_| corrupt())
+    }
+}
+
+
+#[async_trait]
+impl Database for EncryptedDatabase {
+    /// Retrieves and decrypts the URL associated with a given key.
+    #[instrument(level = "info", target = "EncryptedDatabase::get_key_url")]
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError> {
+        let stored = self.inner.get_key_url(key_id).await?;
+        Ok(RedirectTarget { url: self.decrypt(key_id, &stored.url)?, ..stored })
+    }
+
+    /// Encrypts the URL and inserts it with its key.
+    #[instrument(level = "info", target = "EncryptedDatabase::insert_key_with_ttl", skip(target))]
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        let stored = RedirectTarget { url: self.encrypt(&target.url)?, ..target };
+        self.inner.insert_key_with_ttl(key_id, stored, ttl).await
+    }
+
+    /// Encrypts the URL and inserts it with its key, unless the key already exists.
+    #[instrument(level = "info", target = "EncryptedDatabase::insert_key_if_absent", skip(target))]
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        let stored = RedirectTarget { url: self.encrypt(&target.url)?, ..target };
+        self.inner.insert_key_if_absent(key_id, stored).await
+    }
+
+    /// Encrypts the URL and inserts it with its key and TTL, unless the key already exists.
+    #[instrument(level = "info", target = "EncryptedDatabase::insert_key_with_ttl_if_absent", skip(target))]
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        let stored = RedirectTarget { url: self.encrypt(&target.url)?, ..target };
+        self.inner.insert_key_with_ttl_if_absent(key_id, stored, ttl).await
+    }
+
+    /// Encrypts the URL and inserts it with its key and number of visits.
+    #[instrument(level = "info", target = "EncryptedDatabase::insert_limited_key", skip(target))]
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        let stored = RedirectTarget { url: self.encrypt(&target.url)?, ..target };
+        self.inner.insert_limited_key(key_id, stored, max_uses).await
+    }
+
+    /// Encrypts the URL and inserts it with its key and number of visits, unless the key already
+    /// exists.
+    #[instrument(level = "info", target = "EncryptedDatabase::insert_limited_key_if_absent", skip(target))]
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        let stored = RedirectTarget { url: self.encrypt(&target.url)?, ..target };
+        self.inner.insert_limited_key_if_absent(key_id, stored, max_uses).await
+    }
+
+    /// Consumes one visit of a key.
+    #[instrument(level = "info", target = "EncryptedDatabase::consume_visit")]
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        self.inner.consume_visit(key_id).await
+    }
+
+    /// Increments the visit count of a key in the inner database.
+    #[instrument(level = "info", target = "EncryptedDatabase::record_visit")]
+    async fn record_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        self.inner.record_visit(key_id).await
+    }
+
+    /// Retrieves the visit count of a key from the inner database.
+    #[instrument(level = "info", target = "EncryptedDatabase::get_visit_count")]
+    async fn get_visit_count(&self, key_id: &str) -> Result<u64, DatabaseError> {
+        self.inner.get_visit_count(key_id).await
+    }
+
+    /// Checks that the inner database is reachable.
+    #[instrument(level = "debug", target = "EncryptedDatabase::health_check")]
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        self.inner.health_check().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::database::MockDatabase;
+
+    /// Returns a database whose inner database stores the inserted target in `stored`.
+    fn encrypted_db(stored: Arc<Mutex<String>>) -> EncryptedDatabase {
+        let mut inner = MockDatabase::new();
+
+        let insert = stored.clone();
+        inner.expect_insert_key_with_ttl().returning(move |_, target, _| {
+            *insert.lock().unwrap() = target.url;
+            Ok(())
+        });
+        inner.expect_get_key_url().returning(move |_| Ok(RedirectTarget::permanent(stored.lock().unwrap().clone())));
+
+        EncryptedDatabase::new(Arc::new(inner), &EncryptionKey([7; 32]))
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let stored = Arc::new(Mutex::new(String::new()));
+        let db = encrypted_db(stored.clone());
+
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+        assert!(!stored.lock().unwrap().contains("example.com"));
+        assert_eq!(db.get_key_url("12345678").await.unwrap().url, "http://example.com");
+    }
+
+    #[tokio::test]
+    async fn test_tampered_ciphertext() {
+        let stored = Arc::new(Mutex::new(String::new()));
+        let db = encrypted_db(stored.clone());
+
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+        {
+            let mut stored = stored.lock().unwrap();
+            let mut bytes = STANDARD.decode(stored.as_str()).unwrap();
+            *bytes.last_mut().unwrap() ^= 1;
+            *stored = STANDARD.encode(bytes);
+        }
+
+        assert!(matches!(db.get_key_url("12345678").await, Err(DatabaseError::CorruptData(key)) if key == "12345678"));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_is_corrupt() {
+        let stored = Arc::new(Mutex::new("http://example.com".to_string()));
+        let db = encrypted_db(stored);
+
+        assert!(matches!(db.get_key_url("12345678").await, Err(DatabaseError::CorruptData(_))));
+    }
+}
@@ -4,11 +4,21 @@ use axum::http::StatusCode;
 use thiserror::Error;
 
 /// This enum represents the different errors that can occur in the database layer.
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum DatabaseError {
     /// An error indicating that a key was not found in the database.
     #[error("Key not found: {0}")]
     NotExist (String),
+    /// An error indicating that a key is already taken.
+    #[error("Key already exists: {0}")]
+    AlreadyExists (String),
+    /// An error indicating that a key has used up its allowed visits.
+    #[error("Key has no visits left: {0}")]
+    Exhausted (String),
+    /// An error indicating that the data stored for a key cannot be read back, e.g. a target
+    /// that fails to decrypt.
+    #[error("Corrupt data for key: {0}")]
+    CorruptData (String),
     /// An error indicating that a feature is not implemented.
     #[error("Unimplemented error")]
     Unimplemented,
@@ -27,6 +37,9 @@ impl From<DatabaseError> for (StatusCode, String) {
     fn from(err: DatabaseError) -> Self {
         match err {
             DatabaseError::NotExist(key_id) => (StatusCode::NOT_FOUND, key_id),
+            DatabaseError::AlreadyExists(_) => (StatusCode::CONFLICT, err.to_string()),
+            DatabaseError::Exhausted(_) => (StatusCode::GONE, err.to_string()),
+            DatabaseError::CorruptData(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
             DatabaseError::Unimplemented => (StatusCode::NOT_IMPLEMENTED, err.to_string()),
             DatabaseError::UnavailableError(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             DatabaseError::UnknownError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
@@ -46,6 +59,21 @@ mod tests {
         assert_eq!(status.0, StatusCode::NOT_FOUND);
         assert_eq!(status.1, "123456ab");
 
+        let exhausted_error = DatabaseError::Exhausted("123456ab".to_string());
+        let status: (StatusCode, String) = exhausted_error.into();
+        assert_eq!(status.0, StatusCode::GONE);
+        assert_eq!(status.1, "Key has no visits left: 123456ab");
+
+        let exists_error = DatabaseError::AlreadyExists("123456ab".to_string());
+        let status: (StatusCode, String) = exists_error.into();
+        assert_eq!(status.0, StatusCode::CONFLICT);
+        assert_eq!(status.1, "Key already exists: 123456ab");
+
+        let corrupt_error = DatabaseError::CorruptData("123456ab".to_string());
+        let status: (StatusCode, String) = corrupt_error.into();
+        assert_eq!(status.0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status.1, "Corrupt data for key: 123456ab");
+
         let not_imp_error = DatabaseError::Unimplemented;
         let status: (StatusCode, String) = not_imp_error.into();
         assert_eq!(status.0, StatusCode::NOT_IMPLEMENTED);
@@ -3,7 +3,13 @@ use std::sync::Arc;
 use anyhow::Result;
 use crate::config::{DBConfig, RedirectionServiceConfig};
 use crate::database::Database;
+use crate::database::cached::CachedDatabase;
+use crate::database::dual_write::DualWriteDatabase;
+use crate::database::encrypted::EncryptedDatabase;
+use crate::database::memory::InMemoryDatabase;
+use crate::database::redis::RedisDB;
 use crate::database::scylladb::ScyllaDB;
+use crate::database::scylladb::sessions::SessionRegistry;
 
 
 /// This function creates a new database layer based on the provided configuration.
@@ -16,12 +22,36 @@ use crate::database::scylladb::ScyllaDB;
 ///
 /// A `Result` containing a new database layer or an error.
 pub async fn new_db_layer(config: &RedirectionServiceConfig) -> Result<Arc<dyn Database>> {
+    let mut sessions = SessionRegistry::default();
+    let db = new_db(&config.db_config, &mut sessions).await?;
+    match &config.cache {
+        Some(cache) => Ok(Arc::new(CachedDatabase::new(db, cache))),
+        None => Ok(db),
+    }
+}
+
+
+/// This function creates a new database based on the provided database configuration.
+/// ScyllaDB stores on the same cluster share a single session from `sessions`.
+async fn new_db(config: &DBConfig, sessions: &mut SessionRegistry) -> Result<Arc<dyn Database>> {
     // This function creates a new database layer.
     // It returns an Arc<dyn Database> which is a trait object.
-    match config.db_config {
-        DBConfig::ScyllaDB(ref config) => {
-            let db = ScyllaDB::new(config).await?;
+    match config {
+        DBConfig::ScyllaDB(config) => {
+            let session = sessions.get_or_connect(&config.url, || ScyllaDB::connect(&config.url)).await?;
+            let db = ScyllaDB::with_session(session, config).await?;
             Ok(Arc::new(db))
         },
+        DBConfig::Redis(config) => Ok(Arc::new(RedisDB::new(config)?)),
+        DBConfig::Memory(config) => Ok(Arc::new(InMemoryDatabase::new(config))),
+        DBConfig::DualWrite(config) => {
+            let primary = Box::pin(new_db(&config.primary, sessions)).await?;
+            let secondary = Box::pin(new_db(&config.secondary, sessions)).await?;
+            Ok(Arc::new(DualWriteDatabase::new(primary, secondary)))
+        },
+        DBConfig::Encrypted(config) => {
+            let inner = Box::pin(new_db(&config.inner, sessions)).await?;
+            Ok(Arc::new(EncryptedDatabase::new(inner, &config.key)))
+        },
     }
 }
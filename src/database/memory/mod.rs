@@ -0,0 +1,332 @@
+//! This module provides an in-memory database, for local development and tests.
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tracing::instrument;
+use tracing::log::debug;
+use crate::config::MemoryConfig;
+use crate::database::{Database, RedirectTarget};
+use crate::database::error::DatabaseError;
+
+
+/// A stored key.
+#[derive(Debug, Clone)]
+struct StoredUrl {
+    target: RedirectTarget,
+    /// The remaining visits, if the key has a limit.
+    remaining: Option<u32>,
+    /// When the key expires, if it has a TTL.
+    expires_at: Option<Instant>,
+    /// The number of recorded visits.
+    visits: u64,
+}
+
+
+impl StoredUrl {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+
+type Store = RwLock<HashMap<String, StoredUrl>>;
+
+
+/// A database that keeps its keys in memory, so they are lost on restart.
+///
+/// Keys expire after the configured TTL like in the real backends: expired keys are never
+/// returned, and are removed by a periodic sweep.
+#[derive(Debug, Clone)]
+pub struct InMemoryDatabase {
+    store: Arc<Store>,
+    ttl: Option<Duration>,
+}
+
+
+impl InMemoryDatabase {
+    /// Creates a new `InMemoryDatabase`, and spawns its TTL sweep if a TTL is configured.
+    /// The sweep stops once the database is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration for the in-memory database.
+    ///
+    /// # Returns
+    ///
+    /// A new `InMemoryDatabase` instance.
+    pub fn new(config: &MemoryConfig) -> Self {
+        let store = Arc::new(Store::default());
+        if let Some(ttl) = config.ttl {
+            tokio::spawn(sweep_periodically(Arc::downgrade(&store), config.sweep_interval.unwrap_or(ttl)));
+        }
+        Self { store, ttl: config.ttl }
+    }
+
+    /// Builds the entry of a URL, expiring after `ttl` or, if `None`, the configured TTL.
+    fn stored_url(&self, target: RedirectTarget, remaining: Option<u32>, ttl: Option<Duration>) -> StoredUrl {
+        StoredUrl { target, remaining, expires_at: ttl.or(self.ttl).map(|ttl| Instant::now() + ttl), visits: 0 }
+    }
+
+    /// Stores the entry of a URL, unless its key exists and has not expired.
+    async fn insert_if_absent(&self, key_id: String, stored: StoredUrl) -> Result<(), DatabaseError> {
+        let now = Instant::now();
+        match self.store.write().await.entry(key_id) {
+            Entry::Occupied(entry) if !entry.get().is_expired(now) => Err(DatabaseError::AlreadyExists(entry.key().clone())),
+            Entry::Occupied(mut entry) => {
+                entry.insert(stored);
+                Ok(())
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(stored);
+                Ok(())
+            },
+        }
+    }
+}
+
+
+/// Removes the expired keys of a store.
+async fn sweep(store: &Store) -> usize {
+    let now = Instant::now();
+    let mut store = store.write().await;
+    let before = store.len();
+    store.retain(|_, stored| !stored.is_expired(now));
+    before - store.len()
+}
+
+
+/// Sweeps the store every `interval`, until it is dropped.
+async fn sweep_periodically(store: Weak<Store>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Some(store) = store.upgrade() else {
+            return;
+        };
+        let removed = sweep(&store).await;
+        debug!("Swept {} expired keys from the in-memory database", removed);
+    }
+}
+
+
+#[async_trait]
+impl Database for InMemoryDatabase {
+    /// Retrieves the URL associated with a given key from memory.
+    #[instrument(level = "info", target = "InMemoryDatabase::get_key_url")]
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError> {
+        let store = self.store.read().await;
+        let now = Instant::now();
+        match store.get(key_id) {
+            Some(stored) if !stored.is_expired(now) => Ok(RedirectTarget {
+                limited: stored.remaining.is_some(),
+                expires_in: stored.expires_at.map(|expires_at| expires_at - now),
+                ..stored.target.clone()
+            }),
+            _ => Err(DatabaseError::NotExist(key_id.to_string())),
+        }
+    }
+
+    /// Inserts a new key-URL pair into memory.
+    #[instrument(level = "info", target = "InMemoryDatabase::insert_key_with_ttl")]
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        let stored = self.stored_url(target, None, ttl);
+        self.store.write().await.insert(key_id, stored);
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair into memory, unless the key already exists.
+    #[instrument(level = "info", target = "InMemoryDatabase::insert_key_if_absent")]
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        let stored = self.stored_url(target, None, None);
+        self.insert_if_absent(key_id, stored).await
+    }
+
+    /// Inserts a new key-URL pair that expires after `ttl` into memory, unless the key already exists.
+    #[instrument(level = "info", target = "InMemoryDatabase::insert_key_with_ttl_if_absent")]
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        let stored = self.stored_url(target, None, ttl);
+        self.insert_if_absent(key_id, stored).await
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into memory.
+    #[instrument(level = "info", target = "InMemoryDatabase::insert_limited_key")]
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        let stored = self.stored_url(target, Some(max_uses), None);
+        self.store.write().await.insert(key_id, stored);
+        Ok(())
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into memory, unless the key
+    /// already exists.
+    #[instrument(level = "info", target = "InMemoryDatabase::insert_limited_key_if_absent")]
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        let stored = self.stored_url(target, Some(max_uses), None);
+        self.insert_if_absent(key_id, stored).await
+    }
+
+    /// Consumes one visit of a key under the write lock, so concurrent visits are serialized.
+    #[instrument(level = "info", target = "InMemoryDatabase::consume_visit")]
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        let mut store = self.store.write().await;
+        match store.get_mut(key_id).and_then(|stored| stored.remaining.as_mut()) {
+            Some(0) => Err(DatabaseError::Exhausted(key_id.to_string())),
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(())
+            },
+            // Keys without a limit can be visited any number of times.
+            None => Ok(()),
+        }
+    }
+
+    /// Increments the visit count of a key. Visits of missing keys are ignored.
+    #[instrument(level = "info", target = "InMemoryDatabase::record_visit")]
+    async fn record_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        if let Some(stored) = self.store.write().await.get_mut(key_id) {
+            stored.visits += 1;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the visit count of a key from memory.
+    #[instrument(level = "info", target = "InMemoryDatabase::get_visit_count")]
+    async fn get_visit_count(&self, key_id: &str) -> Result<u64, DatabaseError> {
+        let store = self.store.read().await;
+        match store.get(key_id) {
+            Some(stored) if !stored.is_expired(Instant::now()) => Ok(stored.visits),
+            _ => Err(DatabaseError::NotExist(key_id.to_string())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db(ttl: Option<u64>) -> InMemoryDatabase {
+        InMemoryDatabase::new(&MemoryConfig { ttl: ttl.map(Duration::from_secs), sweep_interval: None })
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_get() {
+        let db = memory_db(None);
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+
+        assert_eq!(db.get_key_url("12345678").await.unwrap().url, "http://example.com");
+        assert!(matches!(db.get_key_url("87654321").await, Err(DatabaseError::NotExist(key)) if key == "87654321"));
+
+        db.insert_key("temporary".to_string(), RedirectTarget::temporary("http://example.com")).await.unwrap();
+        assert_eq!(db.get_key_url("temporary").await.unwrap(), RedirectTarget::temporary("http://example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_insert_key_if_absent() {
+        let db = memory_db(None);
+        db.insert_key_if_absent("alias".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+
+        let err = db.insert_key_if_absent("alias".to_string(), RedirectTarget::permanent("http://other.com")).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::AlreadyExists(_)));
+        assert_eq!(db.get_key_url("alias").await.unwrap().url, "http://example.com");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl_and_limited_inserts_if_absent() {
+        let db = memory_db(None);
+        db.insert_key_with_ttl_if_absent("short".to_string(), RedirectTarget::permanent("http://example.com"), Some(Duration::from_secs(10))).await.unwrap();
+        db.insert_limited_key_if_absent("limited".to_string(), RedirectTarget::permanent("http://example.com"), 1).await.unwrap();
+
+        for key in ["short", "limited"] {
+            let err = db.insert_key_with_ttl_if_absent(key.to_string(), RedirectTarget::permanent("http://other.com"), None).await.unwrap_err();
+            assert!(matches!(err, DatabaseError::AlreadyExists(_)));
+            let err = db.insert_limited_key_if_absent(key.to_string(), RedirectTarget::permanent("http://other.com"), 5).await.unwrap_err();
+            assert!(matches!(err, DatabaseError::AlreadyExists(_)));
+            assert_eq!(db.get_key_url(key).await.unwrap().url, "http://example.com");
+        }
+        db.consume_visit("limited").await.unwrap();
+        assert!(matches!(db.consume_visit("limited").await, Err(DatabaseError::Exhausted(_))));
+
+        // The expired key can be taken again.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(db.insert_key_with_ttl_if_absent("short".to_string(), RedirectTarget::permanent("http://other.com"), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_consume_visit() {
+        let db = memory_db(None);
+        db.insert_key("unlimited".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+        db.insert_limited_key("limited".to_string(), RedirectTarget::permanent("http://example.com"), 2).await.unwrap();
+        assert!(!db.get_key_url("unlimited").await.unwrap().limited);
+        assert_eq!(db.get_key_url("limited").await.unwrap(), RedirectTarget::permanent("http://example.com").with_limit());
+
+        for _ in 0..3 {
+            db.consume_visit("unlimited").await.unwrap();
+        }
+        db.consume_visit("limited").await.unwrap();
+        db.consume_visit("limited").await.unwrap();
+        assert!(matches!(db.consume_visit("limited").await, Err(DatabaseError::Exhausted(_))));
+    }
+
+    #[tokio::test]
+    async fn test_visit_count() {
+        let db = memory_db(None);
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+        assert_eq!(db.get_visit_count("12345678").await.unwrap(), 0);
+
+        db.record_visit("12345678").await.unwrap();
+        db.record_visit("12345678").await.unwrap();
+        db.record_visit("87654321").await.unwrap();
+        assert_eq!(db.get_visit_count("12345678").await.unwrap(), 2);
+        assert!(matches!(db.get_visit_count("87654321").await, Err(DatabaseError::NotExist(_))));
+
+        // A key written again starts counting from scratch.
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://other.com")).await.unwrap();
+        assert_eq!(db.get_visit_count("12345678").await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl_expiry() {
+        let db = memory_db(Some(60));
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert_eq!(db.get_key_url("12345678").await.unwrap().expires_in, Some(Duration::from_secs(1)));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(matches!(db.get_key_url("12345678").await, Err(DatabaseError::NotExist(_))));
+
+        // An expired key can be taken again.
+        assert!(db.insert_key_if_absent("12345678".to_string(), RedirectTarget::permanent("http://other.com")).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_insert_key_with_ttl() {
+        let db = memory_db(Some(60));
+        db.insert_key_with_ttl("short".to_string(), RedirectTarget::permanent("http://example.com"), Some(Duration::from_secs(10))).await.unwrap();
+        db.insert_key("default".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(matches!(db.get_key_url("short").await, Err(DatabaseError::NotExist(_))));
+        assert!(db.get_key_url("default").await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl_sweep() {
+        let db = memory_db(Some(60));
+        db.insert_key("12345678".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        db.insert_key("87654321".to_string(), RedirectTarget::permanent("http://example.com")).await.unwrap();
+
+        // The sweep runs every TTL, i.e. at 60s, when only the first key has expired.
+        tokio::time::sleep(Duration::from_secs(31)).await;
+        let keys: Vec<String> = db.store.read().await.keys().cloned().collect();
+        assert_eq!(keys, vec!["87654321".to_string()]);
+    }
+}
@@ -1,8 +1,14 @@
 //! This module provides the database layer for the application.
 use std::fmt::Debug;
+use std::time::Duration;
 use async_trait::async_trait;
 pub(crate) use crate::database::error::DatabaseError;
 
+mod cached;
+mod dual_write;
+mod encrypted;
+mod memory;
+mod redis;
 mod scylladb;
 pub(crate) mod error;
 pub(crate) mod layer;
@@ -10,6 +16,47 @@ pub(crate) mod layer;
 #[cfg(test)]
 use mockall::automock;
 
+
+/// The URL a key redirects to, and how it redirects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectTarget {
+    /// The URL the key redirects to.
+    pub url: String,
+    /// Whether the redirect is permanent (`308`), or temporary (`307`) so clients do not cache it.
+    pub permanent: bool,
+    /// Whether the key may have a limited number of visits, so visits must be consumed.
+    /// Only set by `get_key_url`, inserts ignore it.
+    pub limited: bool,
+    /// How long the key has left before it expires, if it expires.
+    /// Only set by `get_key_url`, inserts take their TTL as an argument instead.
+    pub expires_in: Option<Duration>,
+}
+
+
+#[cfg(test)]
+impl RedirectTarget {
+    /// Creates a new `RedirectTarget` redirecting permanently to `url`.
+    pub fn permanent(url: impl Into<String>) -> Self {
+        Self { url: url.into(), permanent: true, limited: false, expires_in: None }
+    }
+
+    /// Creates a new `RedirectTarget` redirecting temporarily to `url`.
+    pub fn temporary(url: impl Into<String>) -> Self {
+        Self { url: url.into(), permanent: false, limited: false, expires_in: None }
+    }
+
+    /// Marks the target as read from a key with a limited number of visits.
+    pub fn with_limit(self) -> Self {
+        Self { limited: true, ..self }
+    }
+
+    /// Marks the target as read from a key expiring after `expires_in`.
+    pub fn expiring_in(self, expires_in: Duration) -> Self {
+        Self { expires_in: Some(expires_in), ..self }
+    }
+}
+
+
 /// A trait that defines the operations for a database.
 #[cfg_attr(test, automock)]
 #[async_trait]
@@ -22,17 +69,125 @@ pub trait Database: Debug + Send + Sync {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the URL or a `DatabaseError`.
-    async fn get_key_url(&self, key_id: &String) -> Result<String, DatabaseError>;
+    /// A `Result` containing the URL, how it redirects and whether its visits are limited, or a
+    /// `DatabaseError`.
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError>;
     /// Inserts a new key-URL pair into the database.
     ///
     /// # Arguments
     ///
     /// * `key_id` - The key to insert.
-    /// * `url` - The URL to associate with the key.
+    /// * `target` - The URL to associate with the key, and how it redirects.
     ///
     /// # Returns
     ///
     /// A `Result` indicating whether the insertion was successful.
-    async fn insert_key(&self, key_id: String, url: String) -> Result<(), DatabaseError>;
+    async fn insert_key(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        self.insert_key_with_ttl(key_id, target, None).await
+    }
+    /// Inserts a new key-URL pair into the database that expires after `ttl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The key to insert.
+    /// * `target` - The URL to associate with the key, and how it redirects.
+    /// * `ttl` - How long the key is kept. If `None`, the default expiration of the database applies.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the insertion was successful.
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError>;
+    /// Inserts a new key-URL pair unless the key already exists, e.g. for a requested alias.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The key to insert.
+    /// * `target` - The URL to associate with the key, and how it redirects.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `DatabaseError::AlreadyExists` if the key is already taken.
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError>;
+    /// Inserts a new key-URL pair that expires after `ttl`, unless the key already exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The key to insert.
+    /// * `target` - The URL to associate with the key, and how it redirects.
+    /// * `ttl` - How long the key is kept. If `None`, the default expiration of the database applies.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `DatabaseError::AlreadyExists` if the key is already taken.
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError>;
+    /// Inserts a new key-URL pair that can only be visited a limited number of times.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The key to insert.
+    /// * `target` - The URL to associate with the key, and how it redirects.
+    /// * `max_uses` - The number of visits allowed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the insertion was successful.
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError>;
+    /// Inserts a new key-URL pair that can only be visited a limited number of times, unless the
+    /// key already exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The key to insert.
+    /// * `target` - The URL to associate with the key, and how it redirects.
+    /// * `max_uses` - The number of visits allowed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `DatabaseError::AlreadyExists` if the key is already taken.
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError>;
+    /// Atomically consumes one visit of a key, so concurrent visits can never exceed its limit.
+    /// Keys inserted without a limit can be visited any number of times.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The visited key.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `DatabaseError::Exhausted` once a limited key has used up its visits.
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError>;
+    /// Increments the visit count of a key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The visited key.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the visit was counted. Databases without visit counts
+    /// ignore visits.
+    async fn record_visit(&self, _key_id: &str) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+    /// Retrieves the number of visits recorded for a key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The key to retrieve the visit count for.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the visit count, `0` for a key never visited, or
+    /// `DatabaseError::Unimplemented` if the database does not count visits.
+    async fn get_visit_count(&self, _key_id: &str) -> Result<u64, DatabaseError> {
+        Err(DatabaseError::Unimplemented)
+    }
+    /// Checks that the database is reachable, with a lightweight query.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is an error if the database is unreachable.
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
 }
@@ -0,0 +1,332 @@
+//! This module provides a connection to a Redis database.
+use std::time::Duration;
+use async_trait::async_trait;
+use deadpool_redis::{Config, Connection, Pool, PoolError, Runtime};
+use deadpool_redis::redis::{self, RedisError};
+use tracing::instrument;
+use crate::config::RedisConfig;
+use crate::database::{Database, RedirectTarget};
+use crate::database::error::DatabaseError;
+
+
+/// Consumes one visit of a limited key atomically.
+/// Returns `-1` if the key has no limit, `0` if it has no visits left and `1` otherwise.
+const CONSUME_VISIT_SCRIPT: &str = r#"
+local remaining = redis.call('GET', KEYS[1])
+if not remaining then
+    return -1
+end
+if tonumber(remaining) <= 0 then
+    return 0
+end
+redis.call('DECR', KEYS[1])
+return 1
+"#;
+
+
+/// Writes the target of a key, its redirect kind and its remaining visits at once, so they are
+/// never visible apart. Returns `0` if only absent keys are written and the key exists, `1` otherwise.
+///
+/// `KEYS` are the target, the temporary marker and the remaining visits of the key, and `ARGV`
+/// the URL, `1` for a permanent redirect, the TTL in seconds (`0` for no expiry), `1` to only
+/// write an absent key, and the visits allowed (empty for no limit).
+const WRITE_TARGET_SCRIPT: &str = r#"
+local ttl = tonumber(ARGV[3])
+local function set(key, value)
+    if ttl > 0 then
+        return redis.call('SET', key, value, 'EX', ttl)
+    end
+    return redis.call('SET', key, value)
+end
+if ARGV[4] == '1' and redis.call('EXISTS', KEYS[1]) == 1 then
+    return 0
+end
+if ARGV[2] == '1' then
+    redis.call('DEL', KEYS[2])
+else
+    set(KEYS[2], 1)
+end
+if ARGV[5] == '' then
+    redis.call('DEL', KEYS[3])
+else
+    set(KEYS[3], ARGV[5])
+end
+set(KEYS[1], ARGV[1])
+return 1
+"#;
+
+
+/// A struct that represents a connection pool to a Redis database.
+#[derive(Clone)]
+pub struct RedisDB {
+    pool: Pool,
+    ttl_seconds: u64,
+}
+
+
+impl std::fmt::Debug for RedisDB {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisDB").field("ttl_seconds", &self.ttl_seconds).finish_non_exhaustive()
+    }
+}
+
+
+/// Maps a Redis error into a `DatabaseError`.
+/// Connection failures and timeouts are transient unavailability, anything else is an unknown error.
+fn redis_error_to_database_error(err: RedisError) -> DatabaseError {
+    if err.is_io_error() || err.is_connection_refusal() || err.is_connection_dropped() || err.is_timeout() {
+        DatabaseError::UnavailableError(err.to_string())
+    } else {
+        DatabaseError::UnknownError(err.to_string())
+    }
+}
+
+
+/// Returns the Redis key the target of a short code is stored under.
+///
+/// The keys of a short code share the code as their hash tag, so Redis Cluster keeps them in the
+/// same slot and they can be read and written together.
+fn url_key(key_id: &str) -> String {
+    format!("url:{{{key_id}}}")
+}
+
+
+/// Returns the Redis key the remaining visits of a short code are stored under.
+fn uses_key(key_id: &str) -> String {
+    format!("url_uses:{{{key_id}}}")
+}
+
+
+/// Returns the Redis key marking a short code as a temporary redirect.
+/// Permanent redirects have no marker.
+fn temporary_key(key_id: &str) -> String {
+    format!("url_temporary:{{{key_id}}}")
+}
+
+
+/// Returns how long a key has left from the reply of `PTTL`, which is negative if the key does
+/// not expire or does not exist.
+fn expires_in(ttl_millis: i64) -> Option<Duration> {
+    u64::try_from(ttl_millis).ok().map(Duration::from_millis)
+}
+
+
+impl RedisDB {
+    /// Creates a new `RedisDB` instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration for the Redis connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a new `RedisDB` instance or a `DatabaseError`.
+    pub fn new(config: &RedisConfig) -> Result<Self, DatabaseError> {
+        let pool = Config::from_url(config.url.as_str())
+            .create_pool(Some(Runtime::Tokio1))
+            .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+        Ok(Self { pool, ttl_seconds: config.ttl_seconds })
+    }
+
+    async fn connection(&self) -> Result<Connection, DatabaseError> {
+        self.pool.get().await.map_err(|err| match err {
+            PoolError::Backend(err) => redis_error_to_database_error(err),
+            err => DatabaseError::UnavailableError(err.to_string()),
+        })
+    }
+
+    /// Returns the TTL of a key in seconds, `ttl` or, if `None`, the configured TTL.
+    /// A TTL of `0` stores the key without expiry.
+    fn ttl_seconds(&self, ttl: Option<Duration>) -> u64 {
+        ttl.map_or(self.ttl_seconds, |ttl| ttl.as_secs())
+    }
+
+    /// Builds the command writing the target of a key with `WRITE_TARGET_SCRIPT`.
+    fn write_target_command(&self, key_id: &str, target: RedirectTarget, ttl: Option<Duration>, max_uses: Option<u32>, if_absent: bool) -> redis::Cmd {
+        let mut cmd = redis::cmd("EVAL");
+        cmd.arg(WRITE_TARGET_SCRIPT)
+            .arg(3)
+            .arg(url_key(key_id))
+            .arg(temporary_key(key_id))
+            .arg(uses_key(key_id))
+            .arg(target.url)
+            .arg(u8::from(target.permanent))
+            .arg(self.ttl_seconds(ttl))
+            .arg(u8::from(if_absent))
+            .arg(max_uses.map(|max_uses| max_uses.to_string()).unwrap_or_default());
+        cmd
+    }
+
+    /// Writes the target of a key, unless `if_absent` is set and the key exists.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `DatabaseError::AlreadyExists` if the key was not written.
+    async fn write_target(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>, max_uses: Option<u32>, if_absent: bool) -> Result<(), DatabaseError> {
+        let mut conn = self.connection().await?;
+        let written: i64 = self.write_target_command(&key_id, target, ttl, max_uses, if_absent)
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error_to_database_error)?;
+        match written {
+            0 => Err(DatabaseError::AlreadyExists(key_id)),
+            _ => Ok(()),
+        }
+    }
+}
+
+
+#[async_trait]
+impl Database for RedisDB {
+    /// Retrieves the URL associated with a given key from the database.
+    #[instrument(level = "info", target = "RedisDB::get_key_url")]
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError> {
+        let mut conn = self.connection().await?;
+        let ((url, temporary, remaining), ttl_millis): ((Option<String>, Option<String>, Option<String>), i64) = redis::pipe()
+            .cmd("MGET")
+            .arg(url_key(key_id))
+            .arg(temporary_key(key_id))
+            .arg(uses_key(key_id))
+            .cmd("PTTL")
+            .arg(url_key(key_id))
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error_to_database_error)?;
+        let url = url.ok_or_else(|| DatabaseError::NotExist(key_id.to_string()))?;
+        Ok(RedirectTarget { url, permanent: temporary.is_none(), limited: remaining.is_some(), expires_in: expires_in(ttl_millis) })
+    }
+
+    /// Inserts a new key-URL pair into the database, with its redirect kind in the same script.
+    #[instrument(level = "info", target = "RedisDB::insert_key_with_ttl")]
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.write_target(key_id, target, ttl, None, false).await
+    }
+
+    /// Inserts a new key-URL pair into the database, unless the key already exists.
+    /// The check and the write run in one script, so an existing key keeps its redirect kind.
+    #[instrument(level = "info", target = "RedisDB::insert_key_if_absent")]
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        self.write_target(key_id, target, None, None, true).await
+    }
+
+    /// Inserts a new key-URL pair that expires after `ttl` into the database, unless the key
+    /// already exists.
+    #[instrument(level = "info", target = "RedisDB::insert_key_with_ttl_if_absent")]
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.write_target(key_id, target, ttl, None, true).await
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into the database.
+    /// The visits are written in the same script, so the key is never visible without its limit.
+    #[instrument(level = "info", target = "RedisDB::insert_limited_key")]
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.write_target(key_id, target, None, Some(max_uses), false).await
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into the database, unless the
+    /// key already exists.
+    #[instrument(level = "info", target = "RedisDB::insert_limited_key_if_absent")]
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.write_target(key_id, target, None, Some(max_uses), true).await
+    }
+
+    /// Consumes one visit of a key with a Lua script, so concurrent visits are serialized by Redis.
+    #[instrument(level = "info", target = "RedisDB::consume_visit")]
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.connection().await?;
+        let consumed: i64 = redis::cmd("EVAL")
+            .arg(CONSUME_VISIT_SCRIPT)
+            .arg(1)
+            .arg(uses_key(key_id))
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error_to_database_error)?;
+        match consumed {
+            0 => Err(DatabaseError::Exhausted(key_id.to_string())),
+            // Keys without remaining visits stored have no limit.
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that Redis is reachable with a `PING`.
+    #[instrument(level = "debug", target = "RedisDB::health_check")]
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        let mut conn = self.connection().await?;
+        let _: String = redis::cmd("PING")
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error_to_database_error)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deadpool_redis::redis::ErrorKind;
+
+    #[test]
+    fn test_redis_error_to_database_error() {
+        let io_error = RedisError::from(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused"));
+        assert!(matches!(redis_error_to_database_error(io_error), DatabaseError::UnavailableError(_)));
+
+        let type_error = RedisError::from((ErrorKind::TypeError, "bad type"));
+        assert!(matches!(redis_error_to_database_error(type_error), DatabaseError::UnknownError(_)));
+    }
+
+    #[test]
+    fn test_keys() {
+        // The keys of a short code share its hash tag, so they map to the same cluster slot.
+        assert_eq!(url_key("12345678"), "url:{12345678}");
+        assert_eq!(uses_key("12345678"), "url_uses:{12345678}");
+        assert_eq!(temporary_key("12345678"), "url_temporary:{12345678}");
+    }
+
+    fn args(cmd: redis::Cmd) -> Vec<Vec<u8>> {
+        cmd.args_iter()
+            .map(|arg| match arg {
+                redis::Arg::Simple(arg) => arg.to_vec(),
+                redis::Arg::Cursor => Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_target_command() {
+        let db = RedisDB::new(&RedisConfig { url: "redis://localhost:6379".to_string(), ttl_seconds: 60 }).unwrap();
+        let cmd = db.write_target_command("k", RedirectTarget::temporary("http://example.com"), None, Some(2), true);
+        assert_eq!(args(cmd)[2..], [
+            b"3".to_vec(),
+            b"url:{k}".to_vec(),
+            b"url_temporary:{k}".to_vec(),
+            b"url_uses:{k}".to_vec(),
+            b"http://example.com".to_vec(),
+            b"0".to_vec(),
+            b"60".to_vec(),
+            b"1".to_vec(),
+            b"2".to_vec(),
+        ]);
+
+        let cmd = db.write_target_command("k", RedirectTarget::permanent("http://example.com"), Some(Duration::from_secs(3600)), None, false);
+        assert_eq!(args(cmd)[7..], [b"1".to_vec(), b"3600".to_vec(), b"0".to_vec(), b"".to_vec()]);
+    }
+
+    #[test]
+    fn test_expires_in() {
+        assert_eq!(expires_in(1500), Some(Duration::from_millis(1500)));
+        assert_eq!(expires_in(0), Some(Duration::ZERO));
+        assert_eq!(expires_in(-1), None);
+        assert_eq!(expires_in(-2), None);
+    }
+
+    #[test]
+    fn test_ttl_seconds() {
+        let db = RedisDB::new(&RedisConfig { url: "redis://localhost:6379".to_string(), ttl_seconds: 60 }).unwrap();
+        assert_eq!(db.ttl_seconds(None), 60);
+        assert_eq!(db.ttl_seconds(Some(Duration::from_secs(3600))), 3600);
+
+        let db = RedisDB::new(&RedisConfig { url: "redis://localhost:6379".to_string(), ttl_seconds: 0 }).unwrap();
+        assert_eq!(db.ttl_seconds(None), 0);
+    }
+}
@@ -1,13 +1,19 @@
 //! This module provides a connection to a ScyllaDB database.
+pub mod sessions;
 
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 use async_trait::async_trait;
 use scylla::client::session::Session;
 use scylla::client::session_builder::SessionBuilder;
-use futures::StreamExt as _;
+use scylla::response::query_result::QueryResult;
+use scylla::value::{Counter, CqlValue, Row};
+use futures::{Stream, StreamExt as _};
 use tracing::instrument;
-use crate::config::ScyllaDBConfig;
-use crate::database::Database;
+use tracing::log::{debug, warn};
+use crate::config::{ReplicationStrategy, ScyllaDBConfig, ScyllaWarmup};
+use crate::database::{Database, RedirectTarget};
 use crate::database::error::DatabaseError;
 
 /// A struct that represents a connection to a ScyllaDB database.
@@ -30,47 +36,379 @@ macro_rules! scylla_execution_to_database_error {
 }
 
 
+/// Maps an error raised while iterating a row stream into a `DatabaseError`.
+/// Failing to fetch the next page is treated as a transient unavailability, while
+/// anything else (e.g. a row that cannot be deserialized) is an unknown error.
+fn next_row_error_to_database_error(err: scylla::errors::NextRowError) -> DatabaseError {
+    match err {
+        scylla::errors::NextRowError::NextPageError(e) => DatabaseError::UnavailableError(e.to_string()),
+        e => DatabaseError::UnknownError(e.to_string()),
+    }
+}
+
+
+/// Returns the first row of a row stream.
+///
+/// A stream that ends without yielding anything is a genuinely empty result and maps to
+/// `DatabaseError::NotExist`, whereas a stream that yields an error is surfaced through
+/// `map_err`, so transient failures are never reported as a missing key.
+async fn first_row<S, T, E>(mut rows: S, key_id: &str, map_err: impl Fn(E) -> DatabaseError) -> Result<T, DatabaseError>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+{
+    match rows.next().await {
+        Some(Ok(row)) => Ok(row),
+        Some(Err(err)) => Err(map_err(err)),
+        None => Err(DatabaseError::NotExist(key_id.to_string())),
+    }
+}
+
+
+/// The lightweight query used to warm up the connections of a session.
+const WARMUP_QUERY: &str = "SELECT key FROM system.local";
+
+
+/// The number of times a visit is retried when concurrent visits update the same key.
+const MAX_CONSUME_ATTEMPTS: usize = 8;
+
+
+/// The number of times an overwriting write is retried when the key is concurrently inserted or
+/// removed between its insert and its update.
+const MAX_WRITE_ATTEMPTS: usize = 8;
+
+
+/// Returns the partition key stored for a short code when partition keys are hashed.
+///
+/// This is the 64-bit FNV-1a hash of the key, which, unlike `std`'s hashers, is guaranteed to be
+/// stable across builds, as stored rows depend on it.
+fn partition_hash(key_id: &str) -> i64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let hash = key_id.bytes().fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME));
+    hash as i64
+}
+
+
+/// Returns the name and columns of each table used with a configuration.
+fn tables(config: &ScyllaDBConfig) -> Vec<(&'static str, &'static str)> {
+    let mut tables = vec![
+        ("url_table", "url_key text, url_redirect text, permanent boolean, limited boolean, PRIMARY KEY (url_key)"),
+        // Keys with a limited number of visits keep their remaining visits in a separate table,
+        // updated with lightweight transactions.
+        ("url_uses", "url_key text, remaining int, PRIMARY KEY (url_key)"),
+    ];
+    // With hashed partition keys, rows are partitioned by the hash of the key, and the key itself
+    // is kept as a clustering column so it can still be read back.
+    if config.hash_partition_keys {
+        tables.push(("url_table_hashed", "key_hash bigint, url_key text, url_redirect text, permanent boolean, limited boolean, PRIMARY KEY ((key_hash), url_key)"));
+    }
+    tables
+}
+
+
+/// Returns the statements creating the tables used with a configuration, with the configured
+/// default TTL.
+fn create_table_statements(config: &ScyllaDBConfig) -> Vec<String> {
+    let keyspace = &config.keyspace;
+    let ttl = config.default_ttl_seconds;
+
+    tables(config)
+        .into_iter()
+        .map(|(table, columns)| format!("CREATE TABLE IF NOT EXISTS {keyspace}.{table} ({columns}) WITH default_time_to_live = {ttl}"))
+        .collect()
+}
+
+
+/// Returns the tables storing the targets with a configuration.
+fn target_tables(config: &ScyllaDBConfig) -> Vec<&'static str> {
+    tables(config).into_iter().map(|(table, _)| table).filter(|table| *table != "url_uses").collect()
+}
+
+
+/// The columns added to the target tables after they were first created. Rows written before a
+/// column existed have no value for it.
+const ADDED_COLUMNS: [(&str, &str); 2] = [("permanent", "boolean"), ("limited", "boolean")];
+
+
+/// Returns whether the error of adding a column means that the column already exists, e.g.
+/// because another instance starting at the same time added it first.
+fn is_existing_column_error(message: &str) -> bool {
+    message.contains("conflicts with an existing column")
+}
+
+
+/// Returns the statement adding a column to a target table created before it existed.
+fn add_column_statement(keyspace: &str, table: &str, (column, column_type): (&str, &str)) -> String {
+    format!("ALTER TABLE {keyspace}.{table} ADD {column} {column_type}")
+}
+
+
+/// Returns the lightweight transaction inserting a key-URL pair unless the key exists, with a
+/// `USING TTL` bind marker after the values if `with_ttl` is set. Without it, the row gets the
+/// default TTL of the table.
+///
+/// Every write to the target tables is a lightweight transaction, as mixing them with plain writes
+/// of the same rows breaks their guarantees.
+fn insert_url_statement(config: &ScyllaDBConfig, with_ttl: bool) -> String {
+    let keyspace = &config.keyspace;
+    let using_ttl = if with_ttl { " USING TTL ?" } else { "" };
+
+    if config.hash_partition_keys {
+        format!("INSERT INTO {keyspace}.url_table_hashed (key_hash, url_key, url_redirect, permanent, limited) VALUES (?, ?, ?, ?, ?) IF NOT EXISTS{using_ttl};")
+    } else {
+        format!("INSERT INTO {keyspace}.url_table (url_key, url_redirect, permanent, limited) VALUES (?, ?, ?, ?) IF NOT EXISTS{using_ttl};")
+    }
+}
+
+
+/// Returns the lightweight transaction overwriting the target of an existing key, with a
+/// `USING TTL` bind marker before the values if `with_ttl` is set.
+fn update_url_statement(config: &ScyllaDBConfig, with_ttl: bool) -> String {
+    let keyspace = &config.keyspace;
+    let using_ttl = if with_ttl { " USING TTL ?" } else { "" };
+
+    if config.hash_partition_keys {
+        format!("UPDATE {keyspace}.url_table_hashed{using_ttl} SET url_redirect = ?, permanent = ?, limited = ? WHERE key_hash = ? AND url_key = ? IF EXISTS;")
+    } else {
+        format!("UPDATE {keyspace}.url_table{using_ttl} SET url_redirect = ?, permanent = ?, limited = ? WHERE url_key = ? IF EXISTS;")
+    }
+}
+
+
+/// Returns the statement reading the target of a key, and the seconds it has left before it expires.
+fn select_url_statement(config: &ScyllaDBConfig) -> String {
+    let keyspace = &config.keyspace;
+
+    if config.hash_partition_keys {
+        format!("SELECT url_redirect, permanent, limited, TTL(url_redirect) FROM {keyspace}.url_table_hashed WHERE key_hash = ? AND url_key = ?")
+    } else {
+        format!("SELECT url_redirect, permanent, limited, TTL(url_redirect) FROM {keyspace}.url_table WHERE url_key = ?")
+    }
+}
+
+
+/// Returns whether a lightweight transaction was applied.
+/// The first column of its result tells it, and is followed by the columns of the existing row.
+fn lwt_applied(result: QueryResult) -> Result<bool, DatabaseError> {
+    let row = result
+        .into_rows_result()
+        .map_err(|err| DatabaseError::UnknownError(err.to_string()))?
+        .first_row::<Row>()
+        .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+
+    match row.columns.first() {
+        Some(Some(CqlValue::Boolean(applied))) => Ok(*applied),
+        _ => Err(DatabaseError::UnknownError("Missing [applied] column in lightweight transaction result".to_string())),
+    }
+}
+
+
+/// Returns the statement creating the table counting the visits of each key.
+///
+/// Counter tables cannot have a default TTL, so it is created apart from the other tables, and the
+/// counters of expired keys are kept until their key is inserted again, which resets them.
+fn create_visits_table_statement(keyspace: &str) -> String {
+    format!("CREATE TABLE IF NOT EXISTS {keyspace}.url_visits (url_key text, visits counter, PRIMARY KEY (url_key))")
+}
+
+
+/// Returns the statement creating the keyspace with the configured replication strategy.
+fn create_keyspace_statement(config: &ScyllaDBConfig) -> String {
+    let keyspace = &config.keyspace;
+    let replication = match &config.replication_strategy {
+        Some(ReplicationStrategy::SimpleStrategy { factor }) => format!("'class': 'SimpleStrategy', 'replication_factor': {factor}"),
+        Some(ReplicationStrategy::NetworkTopologyStrategy { per_dc }) => {
+            let factors: String = per_dc
+                .iter()
+                .map(|(dc, factor)| format!(", '{}': {factor}", dc.replace('\'', "''")))
+                .collect();
+            format!("'class': 'NetworkTopologyStrategy'{factors}")
+        },
+        None => format!("'class': 'NetworkTopologyStrategy', 'replication_factor': {}", config.replication_factor),
+    };
+    format!("CREATE KEYSPACE IF NOT EXISTS {keyspace} WITH REPLICATION = {{{replication}}}")
+}
+
+
+/// Returns the statements applying the configured default TTL to existing tables.
+/// Rows written before the change keep the TTL they were written with.
+fn alter_ttl_statements(config: &ScyllaDBConfig) -> Vec<String> {
+    let keyspace = &config.keyspace;
+    let ttl = config.default_ttl_seconds;
+
+    tables(config)
+        .into_iter()
+        .map(|(table, _)| format!("ALTER TABLE {keyspace}.{table} WITH default_time_to_live = {ttl}"))
+        .collect()
+}
+
+
+/// Runs the warmup query as many times as the cluster has nodes.
+///
+/// The queries are not sent to a given node: the driver's load balancing spreads them over the
+/// pool, so most connections, but not necessarily one to every node, are warm before the first
+/// request.
+async fn warm_up<F, Fut, T, E>(nodes: usize, mut query: F) -> Result<(), E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    for _ in 0..nodes.max(1) {
+        query().await?;
+    }
+    Ok(())
+}
+
 
 impl ScyllaDB {
-    /// Creates a new `ScyllaDB` instance.
+    /// Opens a new session to the ScyllaDB cluster at `url`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the ScyllaDB cluster.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a new session or a `DatabaseError`.
+    pub async fn connect(url: &str) -> Result<Session, DatabaseError> {
+        SessionBuilder::new()
+            .known_node(url)
+            .build()
+            .await.map_err(|err| DatabaseError::UnknownError(err.to_string()))
+    }
+
+    /// Creates a new `ScyllaDB` instance on an existing session, which may be shared with other stores.
     ///
     /// # Arguments
     ///
+    /// * `session` - The session to the cluster at `config.url`.
     /// * `config` - The configuration for the ScyllaDB connection.
     ///
     /// # Returns
     ///
     /// A `Result` containing a new `ScyllaDB` instance or a `DatabaseError`.
-    pub async fn new(config: &ScyllaDBConfig) -> Result<Self, DatabaseError> {
-        let uri = config.url.clone();
+    pub async fn with_session(session: Arc<Session>, config: &ScyllaDBConfig) -> Result<Self, DatabaseError> {
+        if config.warmup != ScyllaWarmup::Disabled {
+            let nodes = session.get_cluster_state().get_nodes_info().len();
+            debug!("Warming up ScyllaDB session on {} nodes", nodes);
+            let result = warm_up(nodes, || session.query_unpaged(WARMUP_QUERY, &[])).await;
+            match (scylla_execution_to_database_error!(result), config.warmup) {
+                (Err(err), ScyllaWarmup::Strict) => return Err(err),
+                (Err(err), _) => warn!("Error warming up ScyllaDB session: {}", err),
+                (Ok(()), _) => {},
+            }
+        }
+
         let keyspace = config.keyspace.clone();
-        let rep_factor = config.replication_factor;
 
-        let session: Session = SessionBuilder::new()
-            .known_node(uri.as_str())
-            .build()
-            .await.map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+        scylla_execution_to_database_error!(session.query_unpaged(create_keyspace_statement(config), ()).await)?;
 
-        // TODO: Check NetworkTopologyStrategy
-        let create_query = format!("CREATE KEYSPACE IF NOT EXISTS {keyspace} WITH REPLICATION = {{'class': 'NetworkTopologyStrategy', 'replication_factor': {rep_factor}}}");
-        scylla_execution_to_database_error!(
-            session.query_unpaged(create_query.as_str(), ()
-        ).await)?;
+        for statement in create_table_statements(config) {
+            scylla_execution_to_database_error!(session.query_unpaged(statement, &[]).await)?;
+        }
+        scylla_execution_to_database_error!(session.query_unpaged(create_visits_table_statement(&keyspace), &[]).await)?;
 
+        // `CREATE TABLE IF NOT EXISTS` leaves existing tables untouched, so tables created before
+        // a column existed get it added.
+        for table in target_tables(config) {
+            for column in ADDED_COLUMNS {
+                let query = "SELECT column_name FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ? AND column_name = ?";
+                let mut columns = session
+                    .query_iter(query, (keyspace.as_str(), table, column.0))
+                    .await
+                    .map_err(|err| DatabaseError::UnknownError(err.to_string()))?
+                    .rows_stream::<(String,)>()
+                    .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+                if columns.next().await.is_none() {
+                    debug!("Adding the {} column to {}.{}", column.0, keyspace, table);
+                    match session.query_unpaged(add_column_statement(&keyspace, table, column), &[]).await {
+                        Err(err) if is_existing_column_error(&err.to_string()) => debug!("The {} column was already added to {}.{}", column.0, keyspace, table),
+                        result => {
+                            scylla_execution_to_database_error!(result)?;
+                        },
+                    }
+                }
+            }
+        }
 
-        // Create a table if it doesn't exist. The table must contain two columns, one called url key, that is a string, and another one called url_redirect, that is a string. The table must have a default TTL of 30 days.
-        scylla_execution_to_database_error!(
-            session.query_unpaged(
-                format!(
-                    "CREATE TABLE IF NOT EXISTS {keyspace}.url_table ( \
-                        url_key text, \
-                        url_redirect text, \
-                        PRIMARY KEY (url_key)) \
-                        WITH default_time_to_live = 2592000"), // 2,592,000 seconds = 30 days
-                &[]
-        ).await)?;
+        // `CREATE TABLE IF NOT EXISTS` leaves the TTL of existing tables untouched.
+        if config.alter_ttl {
+            for statement in alter_ttl_statements(config) {
+                scylla_execution_to_database_error!(session.query_unpaged(statement, &[]).await)?;
+            }
+        }
+
+        Ok(Self {session, scylla_config: config.clone()})
+    }
+
+    /// Inserts a key-URL pair, marked as limited if its visits are kept in `url_uses`, with
+    /// `USING TTL` if a TTL is given.
+    ///
+    /// The key is inserted with a lightweight transaction. If it already exists, it is either left
+    /// untouched if `if_absent` is set, or overwritten with another one.
+    async fn insert_url(&self, key_id: String, target: RedirectTarget, limited: bool, ttl: Option<Duration>, if_absent: bool) -> Result<(), DatabaseError> {
+        let ttl = ttl
+            .map(|ttl| i32::try_from(ttl.as_secs()))
+            .transpose()
+            .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+        let insert = insert_url_statement(&self.scylla_config, ttl.is_some());
+        let update = update_url_statement(&self.scylla_config, ttl.is_some());
+        let (hash, key, url, permanent) = (partition_hash(&key_id), key_id.as_str(), target.url.as_str(), target.permanent);
+
+        for _ in 0..MAX_WRITE_ATTEMPTS {
+            let result = match (self.scylla_config.hash_partition_keys, ttl) {
+                (true, Some(ttl)) => self.session.query_unpaged(insert.as_str(), (hash, key, url, permanent, limited, ttl)).await,
+                (true, None) => self.session.query_unpaged(insert.as_str(), (hash, key, url, permanent, limited)).await,
+                (false, Some(ttl)) => self.session.query_unpaged(insert.as_str(), (key, url, permanent, limited, ttl)).await,
+                (false, None) => self.session.query_unpaged(insert.as_str(), (key, url, permanent, limited)).await,
+            };
+            if lwt_applied(scylla_execution_to_database_error!(result)?)? {
+                return self.reset_visits(key).await;
+            }
+            if if_absent {
+                return Err(DatabaseError::AlreadyExists(key_id));
+            }
+
+            let result = match (self.scylla_config.hash_partition_keys, ttl) {
+                (true, Some(ttl)) => self.session.query_unpaged(update.as_str(), (ttl, url, permanent, limited, hash, key)).await,
+                (true, None) => self.session.query_unpaged(update.as_str(), (url, permanent, limited, hash, key)).await,
+                (false, Some(ttl)) => self.session.query_unpaged(update.as_str(), (ttl, url, permanent, limited, key)).await,
+                (false, None) => self.session.query_unpaged(update.as_str(), (url, permanent, limited, key)).await,
+            };
+            // The key may have expired or been removed since the insert, which is then retried.
+            if lwt_applied(scylla_execution_to_database_error!(result)?)? {
+                return self.reset_visits(key).await;
+            }
+        }
 
-        Ok(Self {session: Arc::new(session), scylla_config: config.clone()})
+        Err(DatabaseError::UnavailableError(format!("Too many concurrent writes of key {key_id}")))
+    }
+
+    /// Resets the visit counter of a key once it is inserted, so it does not inherit the visits of
+    /// an expired key it reuses. Counters cannot be set, and deleted ones cannot be reliably
+    /// reused, so the counted visits are subtracted instead, before any visit of the new key.
+    async fn reset_visits(&self, key_id: &str) -> Result<(), DatabaseError> {
+        let visits = self.get_visit_count(key_id).await?;
+        if visits == 0 {
+            return Ok(());
+        }
+        let visits = i64::try_from(visits).map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+        let query = format!("UPDATE {}.url_visits SET visits = visits - ? WHERE url_key = ?", self.scylla_config.keyspace);
+        scylla_execution_to_database_error!(self.session.query_unpaged(query, (Counter(visits), key_id)).await)?;
+        Ok(())
+    }
+
+    /// Stores the remaining visits of a limited key.
+    async fn insert_uses(&self, key_id: &str, max_uses: u32) -> Result<(), DatabaseError> {
+        let remaining = i32::try_from(max_uses).map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+        let query = format!("INSERT INTO {}.url_uses (url_key, remaining) VALUES (?, ?);", self.scylla_config.keyspace);
+        scylla_execution_to_database_error!(
+            self.session
+                .query_unpaged(query, (key_id, remaining))
+                .await
+            )?;
+        Ok(())
     }
 }
 
@@ -79,32 +417,377 @@ impl ScyllaDB {
 impl Database for ScyllaDB {
     /// Retrieves the URL associated with a given key from the database.
     #[instrument(level = "info", target = "ScyllaDB::get_key_url")]
-    async fn get_key_url(&self, key_id: &String) -> Result<String, DatabaseError> {
-        let query = format!("SELECT url_redirect FROM {}.url_table WHERE url_key = ?", self.scylla_config.keyspace);
-        let mut rs = self.session
+    async fn get_key_url(&self, key_id: &str) -> Result<RedirectTarget, DatabaseError> {
+        let query = select_url_statement(&self.scylla_config);
+        let pager = if self.scylla_config.hash_partition_keys {
+            self.session.query_iter(query, (partition_hash(key_id), key_id)).await
+        } else {
+            self.session.query_iter(query, (key_id,)).await
+        };
+        let rs = pager
+            .map_err(|err| DatabaseError::UnknownError(err.to_string()))?
+            .rows_stream::<(String, Option<bool>, Option<bool>, Option<i32>)>()
+            .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+
+        let (url, permanent, limited, ttl) = first_row(rs, key_id, next_row_error_to_database_error).await?;
+        // Rows written before the `permanent` column existed have no value, and are permanent.
+        // Rows written before the `limited` column existed may have a limit in `url_uses`.
+        // Rows without a TTL have no value for it.
+        Ok(RedirectTarget {
+            url,
+            permanent: permanent.unwrap_or(true),
+            limited: limited.unwrap_or(true),
+            expires_in: ttl.and_then(|ttl| u64::try_from(ttl).ok()).map(Duration::from_secs),
+        })
+    }
+
+    /// Inserts a new key-URL pair into the database, with `USING TTL` if a TTL is given, or
+    /// overwrites the target of an existing key.
+    #[instrument(level = "info", target = "ScyllaDB::insert_key_with_ttl")]
+    async fn insert_key_with_ttl(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.insert_url(key_id, target, false, ttl, false).await
+    }
+
+    /// Inserts a new key-URL pair into the database with a lightweight transaction, unless the
+    /// key already exists.
+    #[instrument(level = "info", target = "ScyllaDB::insert_key_if_absent")]
+    async fn insert_key_if_absent(&self, key_id: String, target: RedirectTarget) -> Result<(), DatabaseError> {
+        self.insert_url(key_id, target, false, None, true).await
+    }
+
+    /// Inserts a new key-URL pair into the database with a lightweight transaction, with
+    /// `USING TTL` if a TTL is given, unless the key already exists.
+    #[instrument(level = "info", target = "ScyllaDB::insert_key_with_ttl_if_absent")]
+    async fn insert_key_with_ttl_if_absent(&self, key_id: String, target: RedirectTarget, ttl: Option<Duration>) -> Result<(), DatabaseError> {
+        self.insert_url(key_id, target, false, ttl, true).await
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into the database.
+    /// The visits are written first, so the key is never visible without its limit.
+    #[instrument(level = "info", target = "ScyllaDB::insert_limited_key")]
+    async fn insert_limited_key(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.insert_uses(&key_id, max_uses).await?;
+        self.insert_url(key_id, target, true, None, false).await
+    }
+
+    /// Inserts a new key-URL pair with a limited number of visits into the database with a
+    /// lightweight transaction, unless the key already exists.
+    ///
+    /// The key is taken before its visits are written, so the visits of an existing key are never
+    /// overwritten. Keys are only handed out once inserted, so the key is not visited in between.
+    #[instrument(level = "info", target = "ScyllaDB::insert_limited_key_if_absent")]
+    async fn insert_limited_key_if_absent(&self, key_id: String, target: RedirectTarget, max_uses: u32) -> Result<(), DatabaseError> {
+        self.insert_url(key_id.clone(), target, true, None, true).await?;
+        self.insert_uses(&key_id, max_uses).await
+    }
+
+    /// Consumes one visit of a key with a compare-and-set on its remaining visits, retrying
+    /// while concurrent visits win the race. Callers skip it for keys read as unlimited.
+    #[instrument(level = "info", target = "ScyllaDB::consume_visit")]
+    async fn consume_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        let keyspace = &self.scylla_config.keyspace;
+        let query = format!("SELECT remaining FROM {keyspace}.url_uses WHERE url_key = ?");
+        let rs = self.session
             .query_iter(query, (key_id,))
             .await
             .map_err(|err| DatabaseError::UnknownError(err.to_string()))?
-            .rows_stream::<(String,)>()
+            .rows_stream::<(Option<i32>,)>()
             .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
 
-        if let Some(row) = rs.next().await {
-            let row = row.map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
-            Ok(row.0)
-        } else { 
-            Err(DatabaseError::NotExist (key_id.clone()))
+        let mut remaining = match first_row(rs, key_id, next_row_error_to_database_error).await {
+            Ok((remaining,)) => remaining.unwrap_or(0),
+            // Keys without a row in `url_uses` have no limit.
+            Err(DatabaseError::NotExist(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let update = format!("UPDATE {keyspace}.url_uses SET remaining = ? WHERE url_key = ? IF remaining = ?");
+        for _ in 0..MAX_CONSUME_ATTEMPTS {
+            if remaining <= 0 {
+                return Err(DatabaseError::Exhausted(key_id.to_string()));
+            }
+
+            let result = scylla_execution_to_database_error!(
+                self.session
+                    .query_unpaged(update.as_str(), (remaining - 1, key_id, remaining))
+                    .await
+                )?;
+            let (applied, current) = result
+                .into_rows_result()
+                .map_err(|err| DatabaseError::UnknownError(err.to_string()))?
+                .first_row::<(bool, Option<i32>)>()
+                .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+
+            if applied {
+                return Ok(());
+            }
+            remaining = current.unwrap_or(0);
         }
+
+        Err(DatabaseError::UnavailableError(format!("Too many concurrent visits of key {key_id}")))
     }
 
-    /// Inserts a new key-URL pair into the database.
-    #[instrument(level = "info", target = "ScyllaDB::insert_key")]
-    async fn insert_key(&self, key_id: String, url: String) -> Result<(), DatabaseError> {
-        let query = format!("INSERT INTO {}.url_table (url_key, url_redirect) VALUES (?, ?);", self.scylla_config.keyspace);
-        scylla_execution_to_database_error!(
-            self.session
-                .query_unpaged(query, (key_id, url))
-                .await
-            )?;
+    /// Increments the visit counter of a key.
+    #[instrument(level = "info", target = "ScyllaDB::record_visit")]
+    async fn record_visit(&self, key_id: &str) -> Result<(), DatabaseError> {
+        let query = format!("UPDATE {}.url_visits SET visits = visits + 1 WHERE url_key = ?", self.scylla_config.keyspace);
+        scylla_execution_to_database_error!(self.session.query_unpaged(query, (key_id,)).await)?;
         Ok(())
     }
+
+    /// Retrieves the visit counter of a key. Keys never visited have no counter row.
+    #[instrument(level = "info", target = "ScyllaDB::get_visit_count")]
+    async fn get_visit_count(&self, key_id: &str) -> Result<u64, DatabaseError> {
+        let query = format!("SELECT visits FROM {}.url_visits WHERE url_key = ?", self.scylla_config.keyspace);
+        let rs = self.session
+            .query_iter(query, (key_id,))
+            .await
+            .map_err(|err| DatabaseError::UnknownError(err.to_string()))?
+            .rows_stream::<(Counter,)>()
+            .map_err(|err| DatabaseError::UnknownError(err.to_string()))?;
+
+        match first_row(rs, key_id, next_row_error_to_database_error).await {
+            Ok((Counter(visits),)) => Ok(visits.max(0) as u64),
+            Err(DatabaseError::NotExist(_)) => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Checks that the cluster is reachable with the warmup query.
+    #[instrument(level = "debug", target = "ScyllaDB::health_check")]
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        scylla_execution_to_database_error!(self.session.query_unpaged(WARMUP_QUERY, &[]).await)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+    use futures::stream;
+
+    fn unavailable(err: &str) -> DatabaseError {
+        DatabaseError::UnavailableError(err.to_string())
+    }
+
+    fn config(default_ttl: u32, hash_partition_keys: bool) -> ScyllaDBConfig {
+        ScyllaDBConfig {
+            url: "localhost:9042".to_string(),
+            keyspace: "ks".to_string(),
+            replication_factor: 1,
+            replication_strategy: None,
+            hash_partition_keys,
+            warmup: ScyllaWarmup::Disabled,
+            default_ttl_seconds: default_ttl,
+            alter_ttl: false,
+        }
+    }
+
+    #[test]
+    fn test_create_table_statements_ttl() {
+        let statements = create_table_statements(&config(86400, false));
+        assert_eq!(statements, vec![
+            "CREATE TABLE IF NOT EXISTS ks.url_table (url_key text, url_redirect text, permanent boolean, limited boolean, PRIMARY KEY (url_key)) WITH default_time_to_live = 86400",
+            "CREATE TABLE IF NOT EXISTS ks.url_uses (url_key text, remaining int, PRIMARY KEY (url_key)) WITH default_time_to_live = 86400",
+        ]);
+
+        let statements = create_table_statements(&config(0, true));
+        assert_eq!(statements.len(), 3);
+        assert!(statements[2].starts_with("CREATE TABLE IF NOT EXISTS ks.url_table_hashed ("));
+        assert!(statements.iter().all(|statement| statement.ends_with("WITH default_time_to_live = 0")));
+    }
+
+    #[test]
+    fn test_create_keyspace_statement() {
+        let mut config = config(0, false);
+        assert_eq!(
+            create_keyspace_statement(&config),
+            "CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class': 'NetworkTopologyStrategy', 'replication_factor': 1}",
+        );
+
+        config.replication_strategy = Some(ReplicationStrategy::SimpleStrategy { factor: 2 });
+        assert_eq!(
+            create_keyspace_statement(&config),
+            "CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 2}",
+        );
+
+        config.replication_strategy = Some(ReplicationStrategy::NetworkTopologyStrategy {
+            per_dc: BTreeMap::from([("eu-west".to_string(), 3), ("us-east".to_string(), 2)]),
+        });
+        assert_eq!(
+            create_keyspace_statement(&config),
+            "CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class': 'NetworkTopologyStrategy', 'eu-west': 3, 'us-east': 2}",
+        );
+    }
+
+    #[test]
+    fn test_insert_url_statement() {
+        assert_eq!(
+            insert_url_statement(&config(0, false), false),
+            "INSERT INTO ks.url_table (url_key, url_redirect, permanent, limited) VALUES (?, ?, ?, ?) IF NOT EXISTS;",
+        );
+        // `IF NOT EXISTS` goes before `USING TTL`.
+        assert_eq!(
+            insert_url_statement(&config(0, false), true),
+            "INSERT INTO ks.url_table (url_key, url_redirect, permanent, limited) VALUES (?, ?, ?, ?) IF NOT EXISTS USING TTL ?;",
+        );
+        assert_eq!(
+            insert_url_statement(&config(0, true), true),
+            "INSERT INTO ks.url_table_hashed (key_hash, url_key, url_redirect, permanent, limited) VALUES (?, ?, ?, ?, ?) IF NOT EXISTS USING TTL ?;",
+        );
+    }
+
+    #[test]
+    fn test_update_url_statement() {
+        assert_eq!(
+            update_url_statement(&config(0, false), false),
+            "UPDATE ks.url_table SET url_redirect = ?, permanent = ?, limited = ? WHERE url_key = ? IF EXISTS;",
+        );
+        assert_eq!(
+            update_url_statement(&config(0, true), true),
+            "UPDATE ks.url_table_hashed USING TTL ? SET url_redirect = ?, permanent = ?, limited = ? WHERE key_hash = ? AND url_key = ? IF EXISTS;",
+        );
+    }
+
+    #[test]
+    fn test_select_url_statement() {
+        assert_eq!(
+            select_url_statement(&config(0, false)),
+            "SELECT url_redirect, permanent, limited, TTL(url_redirect) FROM ks.url_table WHERE url_key = ?",
+        );
+        assert_eq!(
+            select_url_statement(&config(0, true)),
+            "SELECT url_redirect, permanent, limited, TTL(url_redirect) FROM ks.url_table_hashed WHERE key_hash = ? AND url_key = ?",
+        );
+    }
+
+    #[test]
+    fn test_hashed_statements_bind_key_columns() {
+        let markers = |statement: String| statement.matches('?').count();
+        let config = config(0, true);
+        // (key_hash, url_key, url_redirect, permanent, limited), then the TTL.
+        assert_eq!(markers(insert_url_statement(&config, false)), 5);
+        assert_eq!(markers(insert_url_statement(&config, true)), 6);
+        // The TTL, then (url_redirect, permanent, limited, key_hash, url_key).
+        assert_eq!(markers(update_url_statement(&config, false)), 5);
+        assert_eq!(markers(update_url_statement(&config, true)), 6);
+        // (key_hash, url_key).
+        assert_eq!(markers(select_url_statement(&config)), 2);
+    }
+
+    #[test]
+    fn test_add_column_statement() {
+        assert_eq!(target_tables(&config(0, false)), vec!["url_table"]);
+        assert_eq!(target_tables(&config(0, true)), vec!["url_table", "url_table_hashed"]);
+        let statements: Vec<String> = ADDED_COLUMNS.into_iter().map(|column| add_column_statement("ks", "url_table", column)).collect();
+        assert_eq!(statements, vec!["ALTER TABLE ks.url_table ADD permanent boolean", "ALTER TABLE ks.url_table ADD limited boolean"]);
+    }
+
+    #[test]
+    fn test_is_existing_column_error() {
+        assert!(is_existing_column_error("Database returned an error: The query is syntactically correct but invalid, Error message: Invalid column name permanent because it conflicts with an existing column"));
+        assert!(!is_existing_column_error("Database returned an error: Unauthorized"));
+    }
+
+    #[test]
+    fn test_create_visits_table_statement() {
+        // Counter tables cannot have a default TTL.
+        assert_eq!(
+            create_visits_table_statement("ks"),
+            "CREATE TABLE IF NOT EXISTS ks.url_visits (url_key text, visits counter, PRIMARY KEY (url_key))",
+        );
+    }
+
+    #[test]
+    fn test_alter_ttl_statements() {
+        assert_eq!(alter_ttl_statements(&config(3600, true)), vec![
+            "ALTER TABLE ks.url_table WITH default_time_to_live = 3600",
+            "ALTER TABLE ks.url_uses WITH default_time_to_live = 3600",
+            "ALTER TABLE ks.url_table_hashed WITH default_time_to_live = 3600",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_queries_per_node() {
+        let mut queries = 0;
+        warm_up(3, || {
+            queries += 1;
+            async { Ok::<_, ()>(()) }
+        }).await.unwrap();
+        assert_eq!(queries, 3);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_failure() {
+        let mut queries = 0;
+        let result = warm_up(3, || {
+            queries += 1;
+            async { Err::<(), _>(unavailable("node down")) }
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(queries, 1);
+    }
+
+    #[test]
+    fn test_partition_hash() {
+        // FNV-1a reference values, so stored rows stay readable across builds.
+        assert_eq!(partition_hash(""), 0xcbf29ce484222325_u64 as i64);
+        assert_eq!(partition_hash("a"), 0xaf63dc4c8601ec8c_u64 as i64);
+        assert_eq!(partition_hash("12345678"), partition_hash("12345678"));
+        assert_ne!(partition_hash("12345678"), partition_hash("12345679"));
+    }
+
+    #[test]
+    fn test_partition_hash_keys() {
+        // Reference values of real keys, so rows written by earlier builds are still found.
+        assert_eq!(partition_hash("12345678"), 0x173932c41a90a42d_u64 as i64);
+        assert_eq!(partition_hash("abcdefgh"), 0x25da8c1836a8d66d_u64 as i64);
+        assert_eq!(partition_hash("my-link_1"), 0xce586ae96f328b52_u64 as i64);
+
+        // Keys differing in a single character land in different partitions.
+        let keys = ["12345678", "12345679", "22345678", "abcdefgh", "abcdefgH", "my-link_1", "my-link_2"];
+        let hashes: BTreeSet<i64> = keys.iter().map(|key| partition_hash(key)).collect();
+        assert_eq!(hashes.len(), keys.len());
+    }
+
+    #[test]
+    fn test_hashed_statements() {
+        // Every read and write goes to the hashed table, by the hash and the key.
+        let config = config(0, true);
+        assert_eq!(
+            insert_url_statement(&config, false),
+            "INSERT INTO ks.url_table_hashed (key_hash, url_key, url_redirect, permanent, limited) VALUES (?, ?, ?, ?, ?) IF NOT EXISTS;",
+        );
+        assert_eq!(
+            update_url_statement(&config, false),
+            "UPDATE ks.url_table_hashed SET url_redirect = ?, permanent = ?, limited = ? WHERE key_hash = ? AND url_key = ? IF EXISTS;",
+        );
+        assert_eq!(
+            select_url_statement(&config),
+            "SELECT url_redirect, permanent, limited, TTL(url_redirect) FROM ks.url_table_hashed WHERE key_hash = ? AND url_key = ?",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_first_row_returns_row() {
+        let rows = stream::iter(vec![Ok::<_, &str>(("http://example.com".to_string(),))]);
+        let row = first_row(rows, "12345678", unavailable).await.unwrap();
+        assert_eq!(row.0, "http://example.com");
+    }
+
+    #[tokio::test]
+    async fn test_first_row_empty_stream() {
+        let rows = stream::iter(Vec::<Result<(String,), &str>>::new());
+        let err = first_row(rows, "12345678", unavailable).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::NotExist(key) if key == "12345678"));
+    }
+
+    #[tokio::test]
+    async fn test_first_row_stream_error() {
+        let rows = stream::iter(vec![Err::<(String,), _>("next page failed")]);
+        let err = first_row(rows, "12345678", unavailable).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::UnavailableError(msg) if msg == "next page failed"));
+    }
 }
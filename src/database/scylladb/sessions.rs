@@ -0,0 +1,80 @@
+//! This module provides a registry that shares one ScyllaDB session per cluster.
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use scylla::client::session::Session;
+
+
+/// A registry of open sessions keyed by the cluster URL.
+///
+/// Every logical store built on the same cluster reuses its session, so they share its
+/// connection pool instead of each opening their own connections.
+#[derive(Debug)]
+pub struct SessionRegistry<S = Session> {
+    sessions: HashMap<String, Arc<S>>,
+}
+
+
+impl<S> Default for SessionRegistry<S> {
+    fn default() -> Self {
+        Self { sessions: HashMap::new() }
+    }
+}
+
+
+impl<S> SessionRegistry<S> {
+    /// Returns the session of the cluster at `url`, calling `connect` only if none is open yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the cluster.
+    /// * `connect` - A function opening a new session to the cluster.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the shared session or the error returned by `connect`.
+    pub async fn get_or_connect<F, Fut, E>(&mut self, url: &str, connect: F) -> Result<Arc<S>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<S, E>>,
+    {
+        if let Some(session) = self.sessions.get(url) {
+            return Ok(session.clone());
+        }
+
+        let session = Arc::new(connect().await?);
+        self.sessions.insert(url.to_string(), session.clone());
+        Ok(session)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_session_reused() {
+        let connections = AtomicUsize::new(0);
+        let connect = || async {
+            Ok::<_, ()>(connections.fetch_add(1, Ordering::Relaxed))
+        };
+
+        let mut registry = SessionRegistry::default();
+        let first = registry.get_or_connect("localhost:9042", connect).await.unwrap();
+        let second = registry.get_or_connect("localhost:9042", connect).await.unwrap();
+        let other = registry.get_or_connect("other:9042", connect).await.unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_connection_not_cached() {
+        let mut registry = SessionRegistry::<()>::default();
+        assert!(registry.get_or_connect("localhost:9042", || async { Err("unreachable") }).await.is_err());
+        assert!(registry.get_or_connect("localhost:9042", || async { Ok::<_, &str>(()) }).await.is_ok());
+    }
+}
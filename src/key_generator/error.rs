@@ -18,6 +18,9 @@ pub enum GeneratorError {
     /// The request has an invalid parameter to generate the key.
     #[error("Bad Request generating key")]
     BadRequest,
+    /// Every key the generator tried was already taken, so no key is left to generate.
+    #[error("Keyspace exhausted, no free key could be generated")]
+    KeyspaceExhausted,
     /// An unknown or unexpected error occurred.
     #[error("Generator unknown error: {0}")]
     UnknownError(String),
@@ -33,6 +36,7 @@ impl From<GeneratorError> for (StatusCode, String) {
             GeneratorError::GeneratorNotFound => (StatusCode::NOT_FOUND, err.to_string()),
             GeneratorError::NotPermission => (StatusCode::FORBIDDEN, err.to_string()),
             GeneratorError::BadRequest => (StatusCode::BAD_REQUEST, err.to_string()),
+            GeneratorError::KeyspaceExhausted => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
             GeneratorError::UnknownError(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
         }
     }
@@ -65,6 +69,11 @@ mod tests {
         assert_eq!(status.0, StatusCode::BAD_REQUEST);
         assert_eq!(status.1, "Bad Request generating key");
 
+        let keyspace_exhausted_error = GeneratorError::KeyspaceExhausted;
+        let status: (StatusCode, String) = keyspace_exhausted_error.into();
+        assert_eq!(status.0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status.1, "Keyspace exhausted, no free key could be generated");
+
         let unknown_error = GeneratorError::UnknownError("Some error".to_string());
         let status: (StatusCode, String) = unknown_error.into();
         assert_eq!(status.0, StatusCode::INTERNAL_SERVER_ERROR);
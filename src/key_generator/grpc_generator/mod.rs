@@ -1,16 +1,82 @@
 //! This module contains the gRPC implementation of the `KeyGenerationService` trait.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use async_trait::async_trait;
 use rust_proto_pkg::generated::key_generator_service_client::KeyGeneratorServiceClient;
-use tonic::Code;
+use rust_proto_pkg::generated::{GenerateKeyRequest, GenerateKeyResponse};
+use tonic::{Code, Request, Response, Status};
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::{Body, Bytes, StdError};
+use tonic::metadata::{Ascii, MetadataKey, MetadataValue};
+use tonic::service::{Interceptor, InterceptorLayer};
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Channel;
+use tonic_health::pb::{HealthCheckRequest, health_check_response::ServingStatus, health_client::HealthClient};
 use tonic_tracing_opentelemetry::middleware::client::OtelGrpcLayer;
 use tower::ServiceBuilder;
+use tracing::log::warn;
 use crate::config::GRPCKeyGeneratorConfig;
 use crate::key_generator::error::GeneratorError;
-use crate::key_generator::KeyGenerationService;
+use crate::key_generator::{KeyGenerationService, MAX_KEY_LENGTH};
 
 
-type KeyGenClient = KeyGeneratorServiceClient<tonic_tracing_opentelemetry::middleware::client::OtelGrpcService<Channel>>;
+type KeyGenClient = KeyGeneratorServiceClient<
+    tonic_tracing_opentelemetry::middleware::client::OtelGrpcService<InterceptedService<Channel, MetadataInterceptor>>
+>;
+
+
+/// The metadata key carrying the key generator API key.
+pub const API_KEY_METADATA: &str = "x-api-key";
+
+
+/// The metadata key listing the encodings a gRPC server accepts.
+const ACCEPT_ENCODING_METADATA: &str = "grpc-accept-encoding";
+
+
+/// A tonic interceptor that attaches the configured metadata to every key generator request.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataInterceptor {
+    metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+}
+
+
+impl MetadataInterceptor {
+    /// Creates a new `MetadataInterceptor` from the configured API key and metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `conf` - The configuration for the gRPC generator.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is either a new `MetadataInterceptor` or a `GeneratorError` if a metadata
+    /// key or value is not valid ASCII metadata.
+    pub fn new(conf: &GRPCKeyGeneratorConfig) -> Result<Self, GeneratorError> {
+        let api_key = conf.api_key.as_ref().map(|api_key| (API_KEY_METADATA, api_key));
+        let entries = conf.metadata.iter().map(|(key, value)| (key.as_str(), value)).chain(api_key);
+
+        let metadata = entries
+            .map(|(key, value)| {
+                let key = MetadataKey::from_bytes(key.as_bytes())
+                    .map_err(|err| GeneratorError::UnknownError(format!("Invalid metadata key {key}: {err}")))?;
+                let value = MetadataValue::try_from(value.as_str())
+                    .map_err(|err| GeneratorError::UnknownError(format!("Invalid metadata value for {key}: {err}")))?;
+                Ok((key, value))
+            })
+            .collect::<Result<_, GeneratorError>>()?;
+        Ok(Self { metadata })
+    }
+}
+
+
+impl Interceptor for MetadataInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        for (key, value) in &self.metadata {
+            request.metadata_mut().insert(key.clone(), value.clone());
+        }
+        Ok(request)
+    }
+}
 
 /// This struct is a gRPC client for the key generator service.
 #[derive(Clone, Debug)]
@@ -20,6 +86,11 @@ pub struct GRPCGenerator {
     /// Cloning the client is a cheap operation that just creates a new handle to the same
     /// underlying connection pool.
     client: KeyGenClient,
+    /// The channel to the key generator, used for health checks.
+    channel: Channel,
+    /// Whether the requests are sent compressed with gzip. It is cleared if the key generator
+    /// rejects them, so the following requests are sent uncompressed.
+    compression: Arc<AtomicBool>,
 }
 
 
@@ -44,17 +115,52 @@ impl GRPCGenerator {
         // 2. Apply middleware layers to the channel.
         let layered_channel = ServiceBuilder::new()
             .layer(OtelGrpcLayer)
-            .service(channel);
+            .layer(InterceptorLayer::new(MetadataInterceptor::new(conf)?))
+            .service(channel.clone());
 
         // 3. Create the client with the layered channel.
         let client = rust_proto_pkg::generated::key_generator_service_client::KeyGeneratorServiceClient::new(layered_channel);
 
         // 4. Return a new instance of our struct containing the client.
-        Ok(GRPCGenerator { client })
+        Ok(GRPCGenerator { client, channel, compression: Arc::new(AtomicBool::new(conf.compression)) })
+    }
+
+    /// Requests a new key, compressed with gzip if `compressed` is set.
+    async fn request_key(&self, compressed: bool) -> Result<Response<GenerateKeyResponse>, Status> {
+        // Clone the client. This is a cheap operation that just
+        // creates a new handle to the same underlying connection pool.
+        let client = self.client.clone();
+        let mut client = if compressed { gzip(client) } else { client };
+        client.generate_key(GenerateKeyRequest {}).await
     }
 }
 
 
+/// Sends the requests of a client compressed with gzip, and accepts gzip responses.
+fn gzip<T>(client: KeyGeneratorServiceClient<T>) -> KeyGeneratorServiceClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::Body>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    client
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip)
+}
+
+
+/// Returns whether a request failed because the server does not accept gzip requests.
+/// Such servers answer `Unimplemented`, listing the encodings they accept (gRPC compression spec).
+fn gzip_rejected(status: &Status) -> bool {
+    status.code() == Code::Unimplemented
+        && status.metadata()
+            .get(ACCEPT_ENCODING_METADATA)
+            .and_then(|encodings| encodings.to_str().ok())
+            .is_some_and(|encodings| !encodings.split(',').any(|encoding| encoding.trim() == "gzip"))
+}
+
+
 #[async_trait]
 impl KeyGenerationService for GRPCGenerator {
     /// Asynchronously generates a new key.
@@ -63,27 +169,174 @@ impl KeyGenerationService for GRPCGenerator {
     ///
     /// A `Result` which is either a `String` representing the generated key,
     /// or a `GeneratorError` if key generation fails.
+    /// A request the key generator rejects as compressed is retried uncompressed, and a key
+    /// longer than `MAX_KEY_LENGTH` is an error.
     async fn generate_key(&self) -> Result<String, GeneratorError> {
-        // Clone the client. This is a cheap operation that just
-        // creates a new handle to the same underlying connection pool.
-        let mut client = self.client.clone();
+        let compressed = self.compression.load(Ordering::Relaxed);
+        let res = match self.request_key(compressed).await {
+            Err(status) if compressed && gzip_rejected(&status) => {
+                warn!("The key generator does not accept gzip requests, sending them uncompressed");
+                self.compression.store(false, Ordering::Relaxed);
+                self.request_key(false).await
+            },
+            res => res,
+        };
 
-        let res = client.generate_key(rust_proto_pkg::generated::GenerateKeyRequest {}).await.map_err(
-            |err| match err.code() {
-                Code::InvalidArgument => GeneratorError::BadRequest,
-                Code::PermissionDenied => GeneratorError::NotPermission,
-                Code::Unavailable => GeneratorError::ConnectionError,
-                _ => GeneratorError::UnknownError(err.to_string()),
-            }
-        )?;
+        checked_key(res.map_err(|err| status_to_generator_error(&err))?.into_inner().key)
+    }
 
-        Ok(res.into_inner().key)
+    /// Checks that the key generator is reachable with the standard gRPC health check.
+    /// Servers that do not implement the health service are reachable, so they are healthy.
+    async fn health_check(&self) -> Result<(), GeneratorError> {
+        let mut client = HealthClient::new(self.channel.clone());
+        health_check_result(client.check(HealthCheckRequest { service: String::new() }).await.map(|res| res.into_inner().status()))
+    }
+}
+
+
+/// Returns the key of the key generator, unless it is longer than `MAX_KEY_LENGTH`, which
+/// requested keys are checked against before any lookup.
+fn checked_key(key: String) -> Result<String, GeneratorError> {
+    if key.chars().count() > MAX_KEY_LENGTH {
+        return Err(GeneratorError::UnknownError(format!("The key generator returned a key longer than {MAX_KEY_LENGTH} characters")));
+    }
+    Ok(key)
+}
+
+
+/// Maps the status of a failed key generation request into a `GeneratorError`.
+/// The key generator reports with `ResourceExhausted` that every key it tried collided.
+fn status_to_generator_error(status: &Status) -> GeneratorError {
+    match status.code() {
+        Code::InvalidArgument => GeneratorError::BadRequest,
+        Code::PermissionDenied => GeneratorError::NotPermission,
+        Code::Unavailable => GeneratorError::ConnectionError,
+        Code::ResourceExhausted => GeneratorError::KeyspaceExhausted,
+        _ => GeneratorError::UnknownError(status.to_string()),
+    }
+}
+
+
+/// Maps the result of a gRPC health check into the health of the key generator.
+/// Any status other than `Unavailable` proves the server is reachable.
+fn health_check_result(result: Result<ServingStatus, Status>) -> Result<(), GeneratorError> {
+    match result {
+        Ok(ServingStatus::Serving) => Ok(()),
+        Ok(status) => Err(GeneratorError::UnknownError(format!("Key generator is {}", status.as_str_name()))),
+        Err(status) if status.code() == Code::Unavailable => Err(GeneratorError::ConnectionError),
+        Err(_) => Ok(()),
     }
 }
 
 
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use tonic::codegen::http;
+
+    #[test]
+    fn test_metadata_attached() {
+        let conf = GRPCKeyGeneratorConfig {
+            url: "http://localhost:8080".to_string(),
+            api_key: Some("secret".to_string()),
+            metadata: BTreeMap::from([("x-tenant".to_string(), "tinyurl".to_string())]),
+            compression: false,
+        };
+        let mut interceptor = MetadataInterceptor::new(&conf).unwrap();
+
+        let request = interceptor.call(Request::new(())).unwrap();
+        assert_eq!(request.metadata().get(API_KEY_METADATA).unwrap(), "secret");
+        assert_eq!(request.metadata().get("x-tenant").unwrap(), "tinyurl");
+    }
+
+    #[test]
+    fn test_invalid_metadata_rejected() {
+        let conf = GRPCKeyGeneratorConfig {
+            url: "http://localhost:8080".to_string(),
+            api_key: None,
+            metadata: BTreeMap::from([("invalid key".to_string(), "value".to_string())]),
+            compression: false,
+        };
+        assert!(MetadataInterceptor::new(&conf).is_err());
+    }
+
+    #[test]
+    fn test_checked_key() {
+        assert_eq!(checked_key("12345678".to_string()), Ok("12345678".to_string()));
+        assert_eq!(checked_key("a".repeat(MAX_KEY_LENGTH)), Ok("a".repeat(MAX_KEY_LENGTH)));
+        assert!(matches!(checked_key("a".repeat(MAX_KEY_LENGTH + 1)), Err(GeneratorError::UnknownError(_))));
+    }
+
+    #[test]
+    fn test_status_to_generator_error() {
+        assert_eq!(status_to_generator_error(&Status::invalid_argument("bad")), GeneratorError::BadRequest);
+        assert_eq!(status_to_generator_error(&Status::permission_denied("denied")), GeneratorError::NotPermission);
+        assert_eq!(status_to_generator_error(&Status::unavailable("down")), GeneratorError::ConnectionError);
+        assert_eq!(status_to_generator_error(&Status::resource_exhausted("all keys collide")), GeneratorError::KeyspaceExhausted);
+        assert!(matches!(status_to_generator_error(&Status::internal("boom")), GeneratorError::UnknownError(_)));
+    }
+
+    /// Sends a key generation request and returns its headers. The server answers `Unimplemented`.
+    async fn request_headers(compressed: bool) -> http::HeaderMap {
+        let headers = Arc::new(Mutex::new(http::HeaderMap::new()));
+        let captured = headers.clone();
+        let service = tower::service_fn(move |req: http::Request<tonic::body::Body>| {
+            *captured.lock().unwrap() = req.headers().clone();
+            async {
+                let res = http::Response::builder()
+                    .header("content-type", "application/grpc")
+                    .header("grpc-status", "12")
+                    .body(tonic::body::Body::empty())
+                    .unwrap();
+                Ok::<_, Infallible>(res)
+            }
+        });
+        let client = KeyGeneratorServiceClient::with_origin(service, http::Uri::from_static("http://localhost:8080"));
+        let mut client = if compressed { gzip(client) } else { client };
+
+        assert_eq!(client.generate_key(GenerateKeyRequest {}).await.unwrap_err().code(), Code::Unimplemented);
+        headers.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_gzip_client() {
+        let headers = request_headers(true).await;
+        assert_eq!(headers["grpc-encoding"], "gzip");
+        assert!(headers[ACCEPT_ENCODING_METADATA].to_str().unwrap().contains("gzip"));
+
+        let headers = request_headers(false).await;
+        assert!(!headers.contains_key("grpc-encoding"));
+        assert!(!headers.contains_key(ACCEPT_ENCODING_METADATA));
+    }
+
+    #[test]
+    fn test_gzip_rejected() {
+        let mut rejected = Status::unimplemented("Content is compressed with `gzip` which isn't supported");
+        rejected.metadata_mut().insert(ACCEPT_ENCODING_METADATA, "identity".parse().unwrap());
+        assert!(gzip_rejected(&rejected));
+
+        let mut accepted = Status::unimplemented("unknown method");
+        accepted.metadata_mut().insert(ACCEPT_ENCODING_METADATA, "deflate, gzip".parse().unwrap());
+        assert!(!gzip_rejected(&accepted));
+        assert!(!gzip_rejected(&Status::unimplemented("unknown method")));
+        assert!(!gzip_rejected(&Status::unavailable("down")));
+    }
+
+    #[test]
+    fn test_health_check_result() {
+        assert_eq!(health_check_result(Ok(ServingStatus::Serving)), Ok(()));
+        assert!(health_check_result(Ok(ServingStatus::NotServing)).is_err());
+        assert_eq!(health_check_result(Err(Status::unavailable("down"))), Err(GeneratorError::ConnectionError));
+        assert_eq!(health_check_result(Err(Status::unimplemented("no health service"))), Ok(()));
+    }
+}
+
+
 /*
 #[derive(Clone, Debug)]
 pub struct GRPCGenerator {
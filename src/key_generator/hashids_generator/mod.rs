@@ -0,0 +1,317 @@
+//! This module contains the hashids implementation of the `KeyGenerationService` trait.
+use std::fmt::Debug;
+use std::sync::Arc;
+use async_trait::async_trait;
+use deadpool_redis::{Config, Pool, PoolError, Runtime};
+use deadpool_redis::redis::{self, RedisError};
+use crate::config::HashidsKeyGeneratorConfig;
+use crate::key_generator::error::GeneratorError;
+use crate::key_generator::KeyGenerationService;
+
+#[cfg(test)]
+use mockall::automock;
+
+/// The characters the keys are encoded with, as in the reference hashids implementation.
+const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890";
+/// The characters reserved as separators, as in the reference hashids implementation.
+const SEPARATORS: &str = "cfhistuCFHISTU";
+const SEPARATOR_DIV: f64 = 3.5;
+const GUARD_DIV: f64 = 12.0;
+
+
+/// A trait for the source of the sequential counter encoded into keys.
+#[cfg_attr(test, automock)]
+#[async_trait]
+trait Counter: Debug + Send + Sync {
+    /// Increments the counter.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new value of the counter, never returned before.
+    async fn next_value(&self) -> Result<u64, GeneratorError>;
+}
+
+
+/// A counter stored in Redis, incremented with `INCR` so every instance draws distinct values.
+#[derive(Clone)]
+struct RedisCounter {
+    pool: Pool,
+    key: String,
+}
+
+
+impl Debug for RedisCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisCounter").field("key", &self.key).finish_non_exhaustive()
+    }
+}
+
+
+/// Maps a Redis error into a `GeneratorError`.
+fn redis_error_to_generator_error(err: RedisError) -> GeneratorError {
+    if err.is_io_error() || err.is_connection_refusal() || err.is_connection_dropped() || err.is_timeout() {
+        GeneratorError::ConnectionError
+    } else {
+        GeneratorError::UnknownError(err.to_string())
+    }
+}
+
+
+#[async_trait]
+impl Counter for RedisCounter {
+    async fn next_value(&self) -> Result<u64, GeneratorError> {
+        let mut conn = self.pool.get().await.map_err(|err| match err {
+            PoolError::Backend(err) => redis_error_to_generator_error(err),
+            _ => GeneratorError::ConnectionError,
+        })?;
+        redis::cmd("INCR")
+            .arg(&self.key)
+            .query_async(&mut conn)
+            .await
+            .map_err(redis_error_to_generator_error)
+    }
+}
+
+
+/// Shuffles `alphabet` deterministically with `salt`, as in the reference hashids implementation.
+fn consistent_shuffle(alphabet: &mut [char], salt: &[char]) {
+    if salt.is_empty() {
+        return;
+    }
+    let (mut v, mut p) = (0, 0);
+    for i in (1..alphabet.len()).rev() {
+        v %= salt.len();
+        let n = salt[v] as usize;
+        p += n;
+        alphabet.swap(i, (n + v + p) % i);
+        v += 1;
+    }
+}
+
+
+/// Encodes a number in base `alphabet.len()`, with `alphabet` as the digits.
+fn hash(mut number: u64, alphabet: &[char]) -> Vec<char> {
+    let base = alphabet.len() as u64;
+    let mut hashed = Vec::new();
+    loop {
+        hashed.insert(0, alphabet[(number % base) as usize]);
+        number /= base;
+        if number == 0 {
+            return hashed;
+        }
+    }
+}
+
+
+/// A hashids encoder of a single number, compatible with the reference implementation so keys
+/// can be decoded by any hashids library given the same salt and minimum length.
+#[derive(Debug, Clone)]
+struct Hashids {
+    salt: Vec<char>,
+    min_length: usize,
+    alphabet: Vec<char>,
+    guards: Vec<char>,
+}
+
+
+impl Hashids {
+    fn new(salt: &str, min_length: usize) -> Self {
+        let salt: Vec<char> = salt.chars().collect();
+        let mut separators: Vec<char> = SEPARATORS.chars().collect();
+        let mut alphabet: Vec<char> = ALPHABET.chars().filter(|c| !separators.contains(c)).collect();
+
+        consistent_shuffle(&mut separators, &salt);
+        let separator_count = match (alphabet.len() as f64 / SEPARATOR_DIV).ceil() as usize {
+            1 => 2,
+            count => count,
+        };
+        if separator_count > separators.len() {
+            let missing = separator_count - separators.len();
+            separators.extend(alphabet.drain(..missing));
+        } else {
+            separators.truncate(separator_count);
+        }
+
+        consistent_shuffle(&mut alphabet, &salt);
+        let guard_count = (alphabet.len() as f64 / GUARD_DIV).ceil() as usize;
+        let guards = alphabet.drain(..guard_count).collect();
+
+        // Separators only join several numbers, but are still kept out of the alphabet.
+        Self { salt, min_length, alphabet, guards }
+    }
+
+    /// Returns the alphabet a number is encoded with, shuffled by the lottery character.
+    fn lottery_alphabet(&self, lottery: char) -> Vec<char> {
+        let mut alphabet = self.alphabet.clone();
+        let buffer: Vec<char> = std::iter::once(lottery)
+            .chain(self.salt.iter().copied())
+            .chain(self.alphabet.iter().copied())
+            .take(alphabet.len())
+            .collect();
+        consistent_shuffle(&mut alphabet, &buffer);
+        alphabet
+    }
+
+    fn encode(&self, number: u64) -> String {
+        let number_hash = (number % 100) as usize;
+        let lottery = self.alphabet[number_hash % self.alphabet.len()];
+        let mut alphabet = self.lottery_alphabet(lottery);
+
+        let mut encoded = vec![lottery];
+        encoded.extend(hash(number, &alphabet));
+
+        if encoded.len() < self.min_length {
+            let guard = self.guards[(number_hash + encoded[0] as usize) % self.guards.len()];
+            encoded.insert(0, guard);
+            if encoded.len() < self.min_length {
+                let guard = self.guards[(number_hash + encoded[2] as usize) % self.guards.len()];
+                encoded.push(guard);
+            }
+        }
+
+        let half = alphabet.len() / 2;
+        while encoded.len() < self.min_length {
+            let salt = alphabet.clone();
+            consistent_shuffle(&mut alphabet, &salt);
+            encoded = alphabet[half..].iter().chain(&encoded).chain(&alphabet[..half]).copied().collect();
+            let excess = encoded.len().saturating_sub(self.min_length);
+            if excess > 0 {
+                encoded = encoded[excess / 2..excess / 2 + self.min_length].to_vec();
+            }
+        }
+        encoded.into_iter().collect()
+    }
+}
+
+
+/// A key generator encoding a sequential counter with hashids, so the counter of a key can be
+/// decoded back, e.g. for analytics, with the salt.
+#[derive(Debug, Clone)]
+pub struct HashidsGenerator {
+    counter: Arc<dyn Counter>,
+    hashids: Hashids,
+}
+
+
+impl HashidsGenerator {
+    /// Creates a new `HashidsGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration for the hashids generator.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is either a new `HashidsGenerator` or a `GeneratorError` if the counter
+    /// URL is invalid.
+    pub fn new(config: &HashidsKeyGeneratorConfig) -> Result<Self, GeneratorError> {
+        let pool = Config::from_url(config.counter_url.as_str())
+            .create_pool(Some(Runtime::Tokio1))
+            .map_err(|err| GeneratorError::UnknownError(err.to_string()))?;
+        let counter = RedisCounter { pool, key: config.counter_key.clone() };
+        Ok(Self { counter: Arc::new(counter), hashids: Hashids::new(&config.salt, config.min_length) })
+    }
+}
+
+
+#[async_trait]
+impl KeyGenerationService for HashidsGenerator {
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        let value = self.counter.next_value().await?;
+        Ok(self.hashids.encode(value))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use crate::key_generator::MAX_KEY_LENGTH;
+
+    /// Decodes a key back into its number, or `None` if it is not a key of `hashids`, as any
+    /// hashids library would with the same salt and minimum length.
+    fn decode(hashids: &Hashids, key: &str) -> Option<u64> {
+        let chars: Vec<char> = key.chars().collect();
+        let parts: Vec<&[char]> = chars.split(|c| hashids.guards.contains(c)).collect();
+        let body = match parts.len() {
+            2 | 3 => parts[1],
+            _ => parts[0],
+        };
+        let (&lottery, digits) = body.split_first()?;
+        if digits.is_empty() {
+            return None;
+        }
+
+        let alphabet = hashids.lottery_alphabet(lottery);
+        let number = digits.iter().try_fold(0u64, |number, c| {
+            let digit = alphabet.iter().position(|a| a == c)? as u64;
+            number.checked_mul(alphabet.len() as u64)?.checked_add(digit)
+        })?;
+        (hashids.encode(number) == key).then_some(number)
+    }
+
+    #[test]
+    fn test_encode_reference_vectors() {
+        let hashids = Hashids::new("this is my salt", 0);
+        assert_eq!(hashids.encode(12345), "NkK9");
+
+        let hashids = Hashids::new("this is my salt", 8);
+        assert_eq!(hashids.encode(1), "gB0NV05e");
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        for (salt, min_length) in [("", 0), ("my salt", 0), ("my salt", 6), ("my salt", 12)] {
+            let hashids = Hashids::new(salt, min_length);
+            for number in [0, 1, 2, 99, 100, 12345, 1 << 40, u64::MAX] {
+                let key = hashids.encode(number);
+                assert!(key.chars().count() >= min_length, "{key}");
+                assert_eq!(decode(&hashids, &key), Some(number), "{key}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_within_max_key_length() {
+        // HASHIDS_MIN_LENGTH is at most MAX_KEY_LENGTH, and unpadded keys are shorter.
+        for min_length in 0..=MAX_KEY_LENGTH {
+            let key = Hashids::new("my salt", min_length).encode(u64::MAX);
+            assert!(key.chars().count() <= MAX_KEY_LENGTH, "{key}");
+        }
+    }
+
+    #[test]
+    fn test_decode_other_salt() {
+        let key = Hashids::new("my salt", 6).encode(12345);
+        assert_eq!(decode(&Hashids::new("other salt", 6), &key), None);
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_unique() {
+        let mut counter = MockCounter::new();
+        let value = Arc::new(AtomicU64::new(0));
+        counter.expect_next_value().returning(move || Ok(value.fetch_add(1, Ordering::SeqCst) + 1));
+
+        let hashids = Hashids::new("my salt", 6);
+        let generator = HashidsGenerator { counter: Arc::new(counter), hashids: hashids.clone() };
+
+        let mut keys = BTreeSet::new();
+        for expected in 1..=1000 {
+            let key = generator.generate_key().await.unwrap();
+            assert_eq!(decode(&hashids, &key), Some(expected));
+            keys.insert(key);
+        }
+        assert_eq!(keys.len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_counter_error() {
+        let mut counter = MockCounter::new();
+        counter.expect_next_value().returning(|| Err(GeneratorError::ConnectionError));
+
+        let generator = HashidsGenerator { counter: Arc::new(counter), hashids: Hashids::new("my salt", 6) };
+        assert_eq!(generator.generate_key().await, Err(GeneratorError::ConnectionError));
+    }
+}
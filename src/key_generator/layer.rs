@@ -4,6 +4,8 @@ use anyhow::Result;
 use crate::config::KeyGeneratorConfig;
 use crate::key_generator::KeyGenerationService;
 use crate::key_generator::grpc_generator::GRPCGenerator;
+use crate::key_generator::hashids_generator::HashidsGenerator;
+use crate::key_generator::local_generator::LocalGenerator;
 
 
 /// This function creates a new key generation service layer based on the provided configuration.
@@ -21,6 +23,8 @@ pub async fn new_key_generation_service(config: &KeyGeneratorConfig) -> Result<A
             let key_gen_service = GRPCGenerator::new(conf).await?;
             Ok(Arc::new(key_gen_service))
         },
+        KeyGeneratorConfig::Local(conf) => Ok(Arc::new(LocalGenerator::new(conf))),
+        KeyGeneratorConfig::Hashids(conf) => Ok(Arc::new(HashidsGenerator::new(conf)?)),
         // Add other key generation configurations here
     }
 }
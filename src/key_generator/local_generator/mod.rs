@@ -0,0 +1,82 @@
+//! This module contains the in-process implementation of the `KeyGenerationService` trait.
+use async_trait::async_trait;
+use rand::Rng;
+use crate::config::LocalKeyGeneratorConfig;
+use crate::key_generator::error::GeneratorError;
+use crate::key_generator::KeyGenerationService;
+
+
+/// A key generator drawing random keys in process, so no key generation service is needed.
+/// A key colliding with a stored one costs a retry with a new key, so the length only bounds how
+/// often retries happen, e.g. rarely with the 62^8 possible keys of the default base62 alphabet
+/// and length of 8.
+#[derive(Debug, Clone)]
+pub struct LocalGenerator {
+    alphabet: Vec<char>,
+    length: usize,
+}
+
+
+impl LocalGenerator {
+    /// Creates a new `LocalGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration for the local generator.
+    ///
+    /// # Returns
+    ///
+    /// A new `LocalGenerator`.
+    pub fn new(config: &LocalKeyGeneratorConfig) -> Self {
+        Self { alphabet: config.alphabet.chars().collect(), length: config.length }
+    }
+}
+
+
+#[async_trait]
+impl KeyGenerationService for LocalGenerator {
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        if self.alphabet.is_empty() {
+            return Err(GeneratorError::UnknownError("The key alphabet is empty".to_string()));
+        }
+        let mut rng = rand::rng();
+        Ok((0..self.length).map(|_| self.alphabet[rng.random_range(0..self.alphabet.len())]).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[tokio::test]
+    async fn test_generate_key_length_and_charset() {
+        let generator = LocalGenerator::new(&LocalKeyGeneratorConfig { length: 12, alphabet: "abc123".to_string() });
+
+        let mut keys = BTreeSet::new();
+        for _ in 0..100 {
+            let key = generator.generate_key().await.unwrap();
+            assert_eq!(key.chars().count(), 12);
+            assert!(key.chars().all(|c| "abc123".contains(c)), "{key}");
+            keys.insert(key);
+        }
+        // With 6^12 possible keys, 100 draws all differ.
+        assert_eq!(keys.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_symbol_alphabet() {
+        let generator = LocalGenerator::new(&LocalKeyGeneratorConfig { length: 4, alphabet: "-_".to_string() });
+
+        let key = generator.generate_key().await.unwrap();
+        assert_eq!(key.len(), 4);
+        assert!(key.chars().all(|c| c == '-' || c == '_'), "{key}");
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_empty_alphabet() {
+        let generator = LocalGenerator::new(&LocalKeyGeneratorConfig { length: 4, alphabet: String::new() });
+        assert!(matches!(generator.generate_key().await, Err(GeneratorError::UnknownError(_))));
+    }
+}
@@ -1,12 +1,18 @@
 //! This module provides the `KeyGenerationService` trait and its implementations.
 pub(crate) mod error;
 mod grpc_generator;
+mod hashids_generator;
+mod local_generator;
 pub(crate) mod layer;
 
 use std::fmt::Debug;
 use async_trait::async_trait;
 use error::GeneratorError;
 
+/// The maximum length of a key produced by any `KeyGenerationService`, which the local and hashids
+/// generators are configured within, and the gRPC generator checks its keys against.
+/// Requested keys longer than this can be rejected without a database lookup.
+pub const MAX_KEY_LENGTH: usize = 32;
 
 #[cfg(test)]
 use mockall::automock;
@@ -22,4 +28,12 @@ pub trait KeyGenerationService: Debug + Send + Sync {
     /// A `Result` which is either a `String` representing the generated key,
     /// or a `GeneratorError` if key generation fails.
     async fn generate_key(&self) -> Result<String, GeneratorError>;
+    /// Checks that the key generator is reachable.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is a `GeneratorError` if the key generator is unreachable.
+    async fn health_check(&self) -> Result<(), GeneratorError> {
+        Ok(())
+    }
 }
@@ -1,32 +1,45 @@
 //! This is the main entry point for the redirection service.
 //! It sets up the database, task sender, key generator, and the Axum server.
-use axum::Router;
-use axum::routing::{post, get};
+use axum::middleware::from_fn_with_state;
 
 use anyhow::Result;
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
 
 use rust_otel_setup::otel::OpenTelemetryObject;
 use rust_otel_setup::config as otel_config;
-use tracing::log::{debug, info};
+use tracing::log::{debug, info, warn};
 
 mod database;
 mod app;
 mod task_sender;
 mod config;
 mod key_generator;
+mod shutdown;
+#[cfg(unix)]
+mod uds;
 
 use app::AppState;
-use app::handlers::create_url;
-use crate::app::handlers::{get_healthy, get_url, HEALTHY_URL, ROUTE_CREATE_URL, ROUTE_GET_URL};
+use app::access_log::{access_log, AccessLog};
+use app::error_pages::{error_pages, ErrorPages};
+use app::key_audit::KeyAudit;
+use app::load_shed::{shed_load, InFlightLimit};
+use app::prometheus::install_recorder;
+use app::routes::{admin_router, public_router};
 use crate::config::RedirectionServiceConfig;
 
 
 /// The main entry point for the application.
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = RedirectionServiceConfig::from_env()?;
+    let config = match std::env::var("CONFIG_FILE") {
+        Ok(path) if !path.is_empty() => RedirectionServiceConfig::from_file(Path::new(&path))?,
+        _ => RedirectionServiceConfig::from_env()?,
+    };
     let otel_object = OpenTelemetryObject::new(&otel_config::LogConfig::from_env()?, &otel_config::TraceConfig::from_env()?, "redirection-service".into()).await?;
     debug!("OpenTelemetry started");
+    let metrics_handle = install_recorder()?;
+    debug!("Prometheus metrics recorder installed");
     info!("Starting redirection service");
     debug!("Connecting to database");
     let db_layer = database::layer::new_db_layer(&config).await?;
@@ -38,22 +51,122 @@ async fn main() -> Result<()> {
     let key_generator = key_generator::layer::new_key_generation_service(&config.key_generator).await?;
     debug!("Key generator started");
     
-    let app_state = AppState::new(db_layer, task_sender, key_generator).await?;
-    let app = Router::new()
-        .route(ROUTE_CREATE_URL, post(create_url))
-        .route(ROUTE_GET_URL, get(get_url))
-        .route(HEALTHY_URL, get(get_healthy))
-        .with_state(app_state);
-
-    let listener = tokio::net::TcpListener::bind(format!("[::]:{}", config.port))
-        .await?;
-
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async move { 
-            tokio::signal::ctrl_c().await.expect("failed to install CTRL+C signal handler");
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            otel_object.stop().unwrap();
-        })
-        .await?;
+    let mut app_state = AppState::new(db_layer, task_sender, key_generator).await?
+        .with_config(config.handler.clone())
+        .with_limits(config.limits.clone());
+    // The guard flushes the pending key audit entries when `main` returns.
+    let _key_audit_guard = match KeyAudit::from_config(&config.key_audit)? {
+        Some((key_audit, guard)) => {
+            app_state = app_state.with_key_audit(key_audit);
+            Some(guard)
+        },
+        None => None,
+    };
+    // With an admin server, the metrics and admin routes are only served on its port.
+    let public_metrics = config.admin_port.is_none().then(|| metrics_handle.clone());
+    let mut app = public_router(app_state.clone(), &config.auth, public_metrics);
+
+    if let Some(max_in_flight) = config.max_in_flight {
+        app = app.layer(from_fn_with_state(InFlightLimit::new(max_in_flight), shed_load));
+    }
+
+    if let Some(dir) = &config.error_pages_dir {
+        app = app.layer(from_fn_with_state(ErrorPages::load(dir)?, error_pages));
+    }
+
+    // The guard flushes the pending access log lines when `main` returns.
+    let _access_log_guard = match AccessLog::from_config(&config.access_log)? {
+        Some((log, guard)) => {
+            app = app.layer(from_fn_with_state(log, access_log));
+            Some(guard)
+        },
+        None => None,
+    };
+
+    // The admin server keeps serving while the public one drains, and stops once it has shut down.
+    let admin_shutdown = CancellationToken::new();
+    let mut admin_server = match config.admin_port {
+        Some(port) => {
+            let listener = tokio::net::TcpListener::bind(format!("[::]:{}", port)).await?;
+            info!("Admin server listening on port {}", port);
+            let admin = admin_router(app_state.clone(), metrics_handle);
+            let stopped = admin_shutdown.clone().cancelled_owned();
+            Some(tokio::spawn(async move {
+                axum::serve(listener, admin).with_graceful_shutdown(stopped).await
+            }))
+        },
+        None => None,
+    };
+
+    let shutdown = shutdown::shutdown_signal()?;
+    let shutdown_drain = config.shutdown_drain;
+    // Cancelled once the server stops accepting connections, and starts draining the in-flight requests.
+    let draining = CancellationToken::new();
+    let graceful_shutdown = {
+        let (app_state, draining) = (app_state.clone(), draining.clone());
+        async move {
+            shutdown.await;
+            info!("Shutting down, draining in-flight requests");
+            app_state.begin_shutdown();
+            // Load balancers see the service is not ready, and stop sending requests, before it stops accepting them.
+            tokio::time::sleep(shutdown_drain).await;
+            draining.cancel();
+        }
+    };
+
+    let served = async {
+        match &config.listen_uds {
+            #[cfg(unix)]
+            Some(uds_config) => {
+                // The socket file is removed once the server has shut down.
+                let (listener, _socket) = uds::bind_uds(uds_config)?;
+                info!("Listening on {}", uds_config.path.display());
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(graceful_shutdown)
+                    .await?;
+            },
+            #[cfg(not(unix))]
+            Some(_) => return Err(anyhow::anyhow!("LISTEN_UDS_PATH is only supported on Unix")),
+            None => {
+                let listener = tokio::net::TcpListener::bind(format!("[::]:{}", config.port))
+                    .await?;
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(graceful_shutdown)
+                    .await?;
+            },
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    // The in-flight requests, then the visits recorded in the background, are finished before
+    // the telemetry stops, each for at most the shutdown timeout.
+    let shutdown_timeout = config.shutdown_timeout;
+    // The admin server only stops once cancelled, so stopping earlier, e.g. on an accept error,
+    // stops the service instead of leaving it running without its probes and metrics.
+    let admin_stopped = async {
+        match admin_server.as_mut() {
+            Some(admin_server) => admin_server.await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        served = served => served?,
+        _ = async { draining.cancelled().await; tokio::time::sleep(shutdown_timeout).await } => {
+            warn!("In-flight requests did not finish within {:?} of the shutdown", shutdown_timeout);
+        },
+        stopped = admin_stopped => {
+            stopped??;
+            return Err(anyhow::anyhow!("The admin server stopped unexpectedly"));
+        },
+    }
+    if !app_state.drain_background(shutdown_timeout).await {
+        warn!("Background work did not finish within {:?} of the shutdown", shutdown_timeout);
+    }
+
+    admin_shutdown.cancel();
+    if let Some(admin_server) = admin_server {
+        admin_server.await??;
+    }
+    otel_object.stop().unwrap();
     Ok(())
 }
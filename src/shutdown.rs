@@ -0,0 +1,68 @@
+//! This module provides the signal handling that triggers a graceful shutdown.
+use std::future::Future;
+use tracing::log::info;
+
+
+/// Installs the termination signal handlers and returns a future that resolves once one is received.
+///
+/// On Unix both `SIGINT` and `SIGTERM` trigger the shutdown, so container orchestrators
+/// such as Kubernetes can drain the service on pod termination. Elsewhere, only Ctrl+C does.
+/// The handlers are installed before this function returns, so no signal is missed while
+/// the returned future has not been polled yet.
+#[cfg(unix)]
+pub fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    Ok(async move { first_signal(interrupt.recv(), terminate.recv()).await })
+}
+
+
+/// Resolves once either the `SIGINT` or the `SIGTERM` future resolves, logging which one.
+#[cfg(unix)]
+async fn first_signal(interrupt: impl Future<Output = Option<()>>, terminate: impl Future<Output = Option<()>>) {
+    tokio::select! {
+        _ = interrupt => info!("Received SIGINT, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
+
+
+/// Installs the termination signal handler and returns a future that resolves once it is received.
+#[cfg(not(unix))]
+pub fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    Ok(async {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => info!("Received Ctrl+C, shutting down"),
+            Err(err) => tracing::log::error!("Error listening for Ctrl+C: {}", err),
+        }
+    })
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::future::{pending, ready};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_sigterm_triggers_shutdown() {
+        let shutdown = first_signal(pending(), ready(Some(())));
+        assert!(tokio::time::timeout(Duration::from_secs(5), shutdown).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sigint_triggers_shutdown() {
+        let shutdown = first_signal(ready(Some(())), pending());
+        assert!(tokio::time::timeout(Duration::from_secs(5), shutdown).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_signal_keeps_running() {
+        let shutdown = first_signal(pending(), pending());
+        assert!(tokio::time::timeout(Duration::from_secs(60), shutdown).await.is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! This module contains the Kafka implementation of the `TaskSenderBytes` trait.
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use anyhow::{anyhow, Result};
+use prost::Message;
+use rdkafka::config::ClientConfig;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rust_proto_pkg::generated::{task, Task};
+use crate::config::KafkaConfig;
+use crate::task_sender::{TaskSenderBytes, TASK_SCHEMA_VERSION, TASK_SCHEMA_VERSION_HEADER};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// A trait for producing records to Kafka and waiting for their delivery.
+#[cfg_attr(test, automock)]
+#[async_trait]
+trait RecordProducer: Debug + Send + Sync {
+    /// Produces a record and waits for its delivery.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic to produce to.
+    /// * `key` - The key of the record, which picks its partition. If `None`, the partition is picked by the producer.
+    /// * `payload` - The payload of the record.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the record was delivered.
+    async fn produce(&self, topic: String, key: Option<String>, payload: Vec<u8>) -> Result<()>;
+    /// Checks that the brokers are reachable and know the topic.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic to look up.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is an error if the brokers are unreachable or the topic does not exist.
+    async fn check_topic(&self, topic: String) -> Result<()>;
+}
+
+
+/// A Kafka producer, with the timeout applied to both enqueuing and delivering a record.
+struct TimedProducer {
+    producer: FutureProducer,
+    timeout: Duration,
+}
+
+
+impl Debug for TimedProducer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimedProducer").field("timeout", &self.timeout).finish_non_exhaustive()
+    }
+}
+
+
+#[async_trait]
+impl RecordProducer for TimedProducer {
+    async fn produce(&self, topic: String, key: Option<String>, payload: Vec<u8>) -> Result<()> {
+        let headers = OwnedHeaders::new().insert(Header { key: TASK_SCHEMA_VERSION_HEADER, value: Some(TASK_SCHEMA_VERSION) });
+        let mut record = FutureRecord::<str, [u8]>::to(&topic).payload(&payload).headers(headers);
+        if let Some(key) = &key {
+            record = record.key(key);
+        }
+        self.producer.send(record, self.timeout).await
+            .map(|_| ())
+            .map_err(|(err, _)| anyhow!("Error producing task to Kafka topic {topic}: {err}"))
+    }
+
+    async fn check_topic(&self, topic: String) -> Result<()> {
+        let producer = self.producer.clone();
+        let timeout = self.timeout;
+        // Fetching metadata blocks, so it runs off the async workers.
+        tokio::task::spawn_blocking(move || {
+            let metadata = producer.client()
+                .fetch_metadata(Some(&topic), timeout)
+                .map_err(|err| anyhow!("Error fetching Kafka metadata: {err}"))?;
+            match metadata.topics().iter().find(|t| t.name() == topic) {
+                Some(t) if t.error().is_none() => Ok(()),
+                _ => Err(anyhow!("Kafka task topic {topic} does not exist")),
+            }
+        }).await?
+    }
+}
+
+
+/// Returns the URL key of an encoded task, so every visit of a key lands on the same partition.
+fn partition_key(task: &[u8]) -> Option<String> {
+    match Task::decode(task).ok()?.task? {
+        task::Task::T1(record) => Some(record.tag),
+    }
+}
+
+
+/// This struct is a Kafka client for sending tasks.
+#[derive(Clone, Debug)]
+pub struct KafkaTaskSender {
+    producer: Arc<dyn RecordProducer>,
+    topic: String,
+}
+
+
+impl KafkaTaskSender {
+    /// Creates a new `KafkaTaskSender`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration for the Kafka task sender.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is either a new `KafkaTaskSender` or an error.
+    pub fn new(config: &KafkaConfig) -> Result<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("message.timeout.ms", config.timeout.as_millis().to_string())
+            .create()?;
+        Ok(KafkaTaskSender {
+            producer: Arc::new(TimedProducer { producer, timeout: config.timeout }),
+            topic: config.topic.clone(),
+        })
+    }
+}
+
+
+#[async_trait]
+impl TaskSenderBytes for KafkaTaskSender {
+    /// Sends a task to Kafka, keyed by its URL key, with its schema version in the
+    /// `Task-Schema-Version` header.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The task to send as a byte vector.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the task was sent successfully.
+    async fn send_task(&self, task: Vec<u8>) -> Result<()> {
+        let key = partition_key(&task);
+        self.producer.produce(self.topic.clone(), key, task).await
+    }
+
+    /// Checks that the brokers are reachable and the topic exists.
+    async fn health_check(&self) -> Result<()> {
+        self.producer.check_topic(self.topic.clone()).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_proto_pkg::generated::InsertRecord;
+
+    fn sender(producer: MockRecordProducer) -> KafkaTaskSender {
+        KafkaTaskSender { producer: Arc::new(producer), topic: "tasks.visit".to_string() }
+    }
+
+    fn visit_task(key: &str) -> Vec<u8> {
+        Task {
+            task: Some(task::Task::T1(InsertRecord { tag: key.to_string(), time: None })),
+        }.encode_to_vec()
+    }
+
+    #[test]
+    fn test_partition_key() {
+        assert_eq!(partition_key(&visit_task("12345678")), Some("12345678".to_string()));
+        assert_eq!(partition_key(&Task { task: None }.encode_to_vec()), None);
+        assert_eq!(partition_key(b"\xff\xff"), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_task_keyed_by_url_key() {
+        let mut producer = MockRecordProducer::new();
+        producer.expect_produce()
+            .withf(|topic, key, payload| topic == "tasks.visit" && key.as_deref() == Some("12345678") && *payload == visit_task("12345678"))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        assert!(sender(producer).send_task(visit_task("12345678")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_task_error() {
+        let mut producer = MockRecordProducer::new();
+        producer.expect_produce().times(1).returning(|_, _, _| Err(anyhow!("Message timed out")));
+
+        let err = sender(producer).send_task(visit_task("12345678")).await.unwrap_err();
+        assert_eq!(err.to_string(), "Message timed out");
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let mut producer = MockRecordProducer::new();
+        producer.expect_check_topic()
+            .withf(|topic| topic == "tasks.visit")
+            .returning(|topic| Err(anyhow!("Kafka task topic {topic} does not exist")));
+
+        assert!(TaskSenderBytes::health_check(&sender(producer)).await.is_err());
+    }
+}
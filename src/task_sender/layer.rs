@@ -19,5 +19,9 @@ pub async fn new_task_sender(config: &RedirectionServiceConfig) -> Result<Arc<dy
             let nats_sender = crate::task_sender::nats::NatsTaskSender::new(nats_sender_config).await?;
             Ok(Arc::new(nats_sender))
         }
+        TaskConfigSender::Kafka(ref kafka_sender_config) => {
+            let kafka_sender = crate::task_sender::kafka::KafkaTaskSender::new(kafka_sender_config)?;
+            Ok(Arc::new(kafka_sender))
+        }
     }
 }
\ No newline at end of file
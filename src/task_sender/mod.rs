@@ -1,16 +1,48 @@
 //! This module provides the `TaskSender` trait and its implementations.
+mod kafka;
 mod nats;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 pub mod layer;
 
 use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
 use async_trait::async_trait;
 use prost::Message;
-use rust_proto_pkg;
+use rust_proto_pkg::generated::{task, InsertRecord, Task};
 
 #[cfg(test)]
 use mockall::automock;
 
+/// The schema version of the encoded `Task` payloads, sent alongside every task so consumers
+/// can tell which producer version emitted it. Bump it whenever the `Task` proto changes.
+pub const TASK_SCHEMA_VERSION: &str = "1";
+
+/// The Kafka and NATS header carrying the task schema version.
+pub const TASK_SCHEMA_VERSION_HEADER: &str = "Task-Schema-Version";
+
+/// Builds the task recording a visit of a key.
+///
+/// # Arguments
+///
+/// * `url_key` - The visited key.
+/// * `time` - When the key was visited. Times before the Unix epoch are sent as the epoch.
+///
+/// # Returns
+///
+/// The task recording the visit.
+pub fn visit_task(url_key: &str, time: SystemTime) -> Task {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX);
+
+    Task {
+        task: Some(task::Task::T1(InsertRecord {
+            tag: url_key.to_string(),
+            time: Some(prost_types::Timestamp { seconds, nanos: since_epoch.subsec_nanos() as i32 }),
+        })),
+    }
+}
+
+
 /// A trait for sending tasks.
 #[cfg_attr(test, automock)]
 #[async_trait]
@@ -25,6 +57,14 @@ pub trait TaskSender: Debug + Send + Sync {
     ///
     /// A `Result` indicating whether the task was sent successfully.
     async fn send_task(&self, task: rust_proto_pkg::generated::Task) -> Result<()>;
+    /// Checks that the task queue is reachable.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is an error if the task queue is unreachable.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 
@@ -42,15 +82,65 @@ pub trait TaskSenderBytes: Send + Sync + Debug {
     ///
     /// A `Result` indicating whether the task was sent successfully.
     async fn send_task(&self, task: Vec<u8>) -> Result<()>;
+    /// Checks that the task queue is reachable.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is an error if the task queue is unreachable.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 
 /// A default implementation of `TaskSender` that uses `TaskSenderBytes`.
 /// This implementation encodes the `Task` into bytes and sends it using the `TaskSender` trait.
+/// A task that cannot be encoded is returned as an error, and never sent.
 #[async_trait]
 impl <T: TaskSenderBytes> TaskSender for T {
     async fn send_task(&self, task: rust_proto_pkg::generated::Task) -> Result<()> {
-        let bts = task.encode_to_vec();
+        let mut bts = Vec::with_capacity(task.encoded_len());
+        task.encode(&mut bts).map_err(|err| anyhow!("Error encoding task: {err}"))?;
         self.send_task(bts).await
     }
+
+    async fn health_check(&self) -> Result<()> {
+        TaskSenderBytes::health_check(self).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_visit_task() {
+        let task = visit_task("12345678", UNIX_EPOCH + Duration::new(1_700_000_000, 5));
+        let Some(task::Task::T1(record)) = task.task else {
+            panic!("Unexpected task {task:?}");
+        };
+        assert_eq!(record.tag, "12345678");
+        assert_eq!(record.time, Some(prost_types::Timestamp { seconds: 1_700_000_000, nanos: 5 }));
+    }
+
+    #[test]
+    fn test_visit_task_before_epoch() {
+        let task = visit_task("12345678", UNIX_EPOCH - Duration::from_secs(1));
+        let Some(task::Task::T1(record)) = task.task else {
+            panic!("Unexpected task {task:?}");
+        };
+        assert_eq!(record.time, Some(prost_types::Timestamp { seconds: 0, nanos: 0 }));
+    }
+
+    #[tokio::test]
+    async fn test_send_task_encodes() {
+        let task = visit_task("12345678", UNIX_EPOCH);
+        let encoded = task.encode_to_vec();
+
+        let mut sender = MockTaskSenderBytes::new();
+        sender.expect_send_task().withf(move |bts| *bts == encoded).returning(|_| Ok(()));
+        TaskSender::send_task(&sender, task).await.unwrap();
+    }
 }
@@ -1,21 +1,116 @@
 //! This module contains the NATS implementation of the `TaskSenderBytes` trait.
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
 use async_trait::async_trait;
-use async_nats::jetstream::{self, context::Context};
+use async_nats::HeaderMap;
+use async_nats::jetstream::{self, context::{Context, GetStreamByNameError, GetStreamByNameErrorKind, PublishError, PublishErrorKind}};
 use bytes::Bytes;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+use tracing::log::warn;
 use crate::config::NatsConfig;
-use crate::task_sender::TaskSenderBytes;
+use crate::task_sender::{TaskSenderBytes, TASK_SCHEMA_VERSION, TASK_SCHEMA_VERSION_HEADER};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// `PublishFailure` tells a publish whose ack timed out apart from any other publish error.
+#[derive(Debug, Error)]
+enum PublishFailure {
+    /// The message was sent, but its ack did not arrive within the ack timeout.
+    #[error("Timed out waiting for the publish ack")]
+    AckTimeout,
+    /// The message could not be published.
+    #[error(transparent)]
+    Publish(#[from] PublishError),
+}
+
+
+/// A trait for publishing a message to JetStream and waiting for its ack.
+#[cfg_attr(test, automock)]
+#[async_trait]
+trait AckPublisher: Debug + Send + Sync {
+    /// Publishes a message and waits for its ack.
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The subject to publish to.
+    /// * `headers` - The headers of the message.
+    /// * `payload` - The payload of the message.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the message was acked.
+    async fn publish_acked(&self, subject: String, headers: HeaderMap, payload: Bytes) -> Result<(), PublishFailure>;
+}
+
+
+#[async_trait]
+impl AckPublisher for Context {
+    async fn publish_acked(&self, subject: String, headers: HeaderMap, payload: Bytes) -> Result<(), PublishFailure> {
+        let ack = self.publish_with_headers(subject, headers, payload).await?;
+        match ack.await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == PublishErrorKind::TimedOut => Err(PublishFailure::AckTimeout),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+
+/// A trait for looking up the JetStream stream bound to a subject.
+#[cfg_attr(test, automock)]
+#[async_trait]
+trait StreamLookup: Debug + Send + Sync {
+    /// Returns the name of the stream bound to a subject.
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The subject to look up.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the name of the stream, or a `NotFound` error if there is none.
+    async fn stream_by_subject(&self, subject: String) -> Result<String, GetStreamByNameError>;
+}
+
+
+#[async_trait]
+impl StreamLookup for Context {
+    async fn stream_by_subject(&self, subject: String) -> Result<String, GetStreamByNameError> {
+        Context::stream_by_subject(self, subject).await
+    }
+}
+
+
+/// Checks that a JetStream stream is bound to the subject, so a misconfigured subject
+/// fails at startup instead of on every publish.
+async fn verify_stream(lookup: &dyn StreamLookup, subject: &str) -> Result<()> {
+    match lookup.stream_by_subject(subject.to_string()).await {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == GetStreamByNameErrorKind::NotFound =>
+            Err(anyhow!("No JetStream stream is bound to the NATS task subject {subject}")),
+        Err(err) => Err(anyhow!("Error looking up the JetStream stream for the NATS task subject {subject}: {err}")),
+    }
+}
+
 
 /// This struct is a NATS client for sending tasks.
 #[derive(Clone, Debug)]
 pub struct NatsTaskSender {
-    ctx: Context,
+    publisher: Arc<dyn AckPublisher>,
+    streams: Arc<dyn StreamLookup>,
     subject: String,
+    ack_timeout: Duration,
+    max_attempts: u32,
+    base_delay: Duration,
 }
 
 
 impl NatsTaskSender {
     /// Creates a new `NatsTaskSender`.
+    /// Unless `skip_stream_check` is set, it fails if no JetStream stream is bound to the subject.
     ///
     /// # Arguments
     ///
@@ -26,15 +121,50 @@ impl NatsTaskSender {
     /// A `Result` which is either a new `NatsTaskSender` or an error.
     pub async fn new(config: &NatsConfig) -> Result<Self> {
         let client = async_nats::connect(&config.url).await?;
-        let ctx = jetstream::new(client);
-        Ok(NatsTaskSender { ctx, subject: config.subject.clone() })
+        let mut ctx = jetstream::new(client);
+        ctx.set_timeout(config.ack_timeout);
+        if !config.skip_stream_check {
+            verify_stream(&ctx, &config.subject).await?;
+        }
+        let ctx = Arc::new(ctx);
+        Ok(NatsTaskSender {
+            publisher: ctx.clone(),
+            streams: ctx,
+            subject: config.subject.clone(),
+            ack_timeout: config.ack_timeout,
+            max_attempts: config.max_attempts,
+            base_delay: config.base_delay,
+        })
+    }
+}
+
+
+impl NatsTaskSender {
+    /// Publishes a task and waits for its ack, within the ack timeout.
+    /// The JetStream context already times out the ack itself, but publishing can also stall,
+    /// e.g. while the client reconnects, so the whole publish is bounded.
+    async fn publish_within_timeout(&self, payload: Bytes) -> Result<(), PublishFailure> {
+        let publish = self.publisher.publish_acked(self.subject.clone(), task_headers(), payload);
+        tokio::time::timeout(self.ack_timeout, publish)
+            .await
+            .unwrap_or(Err(PublishFailure::AckTimeout))
     }
 }
 
 
+/// Returns the headers published with every task.
+fn task_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TASK_SCHEMA_VERSION_HEADER, TASK_SCHEMA_VERSION);
+    headers
+}
+
+
 #[async_trait]
 impl TaskSenderBytes for NatsTaskSender {
-    /// Sends a task to NATS.
+    /// Sends a task to NATS, with its schema version in the `Task-Schema-Version` header.
+    /// A failed publish, or one whose ack times out, is retried with exponential backoff up to
+    /// the configured number of attempts, so consumers may see a task twice.
     ///
     /// # Arguments
     ///
@@ -42,9 +172,152 @@ impl TaskSenderBytes for NatsTaskSender {
     ///
     /// # Returns
     ///
-    /// A `Result` indicating whether the task was sent successfully.
+    /// A `Result` indicating whether the task was sent successfully, with the error of the
+    /// last attempt once all attempts failed.
     async fn send_task(&self, task: Vec<u8>) -> Result<()> {
-        self.ctx.publish(self.subject.clone(), Bytes::from(task)).await?.await?;
-        Ok(())
+        let payload = Bytes::from(task);
+        let mut attempt = 1;
+        loop {
+            match self.publish_within_timeout(payload.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.max_attempts => {
+                    let delay = self.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+                    warn!("Error publishing task on attempt {}/{}: {}, retrying in {:?}", attempt, self.max_attempts, err, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Checks that JetStream is reachable and a stream is still bound to the subject.
+    async fn health_check(&self) -> Result<()> {
+        verify_stream(self.streams.as_ref(), &self.subject).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender(publisher: MockAckPublisher) -> NatsTaskSender {
+        NatsTaskSender {
+            publisher: Arc::new(publisher),
+            streams: Arc::new(MockStreamLookup::new()),
+            subject: "tasks.visit".to_string(),
+            ack_timeout: Duration::from_secs(5),
+            max_attempts: 2,
+            base_delay: Duration::from_millis(100),
+        }
+    }
+
+    /// A publisher that fails `failures` times, then succeeds.
+    fn flaky_publisher(failures: usize) -> MockAckPublisher {
+        let mut publisher = MockAckPublisher::new();
+        let mut seq = mockall::Sequence::new();
+        publisher.expect_publish_acked().times(failures).in_sequence(&mut seq)
+            .returning(|_, _, _| Err(PublishError::from(PublishErrorKind::BrokenPipe).into()));
+        publisher.expect_publish_acked().times(1).in_sequence(&mut seq)
+            .withf(|subject, _, payload| subject == "tasks.visit" && payload.as_ref() == b"task")
+            .returning(|_, _, _| Ok(()));
+        publisher
+    }
+
+    #[test]
+    fn test_task_headers_schema_version() {
+        let headers = task_headers();
+        assert_eq!(headers.get(TASK_SCHEMA_VERSION_HEADER).map(|value| value.as_str()), Some(TASK_SCHEMA_VERSION));
+    }
+
+    #[tokio::test]
+    async fn test_verify_stream() {
+        let mut lookup = MockStreamLookup::new();
+        lookup.expect_stream_by_subject()
+            .withf(|subject| subject == "tasks.visit")
+            .returning(|_| Ok("TASKS".to_string()));
+
+        assert!(verify_stream(&lookup, "tasks.visit").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_verify_stream_missing() {
+        let mut lookup = MockStreamLookup::new();
+        lookup.expect_stream_by_subject()
+            .returning(|_| Err(GetStreamByNameError::from(GetStreamByNameErrorKind::NotFound)));
+
+        let err = verify_stream(&lookup, "tasks.visit").await.unwrap_err();
+        assert_eq!(err.to_string(), "No JetStream stream is bound to the NATS task subject tasks.visit");
+    }
+
+    #[tokio::test]
+    async fn test_send_task_retries_ack_timeout() {
+        let mut publisher = MockAckPublisher::new();
+        let mut seq = mockall::Sequence::new();
+
+        publisher.expect_publish_acked().times(1).in_sequence(&mut seq)
+            .returning(|_, _, _| Err(PublishFailure::AckTimeout));
+        publisher.expect_publish_acked().times(1).in_sequence(&mut seq)
+            .withf(|subject, _, payload| subject == "tasks.visit" && payload.as_ref() == b"task")
+            .returning(|_, _, _| Ok(()));
+
+        assert!(sender(publisher).send_task(b"task".to_vec()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_task_gives_up_after_max_attempts() {
+        let mut publisher = MockAckPublisher::new();
+        publisher.expect_publish_acked().times(2).returning(|_, _, _| Err(PublishFailure::AckTimeout));
+
+        let err = sender(publisher).send_task(b"task".to_vec()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Timed out waiting for the publish ack");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_task_retries_with_backoff() {
+        let sender = NatsTaskSender { max_attempts: 4, ..sender(flaky_publisher(3)) };
+
+        let start = tokio::time::Instant::now();
+        assert!(sender.send_task(b"task".to_vec()).await.is_ok());
+        // The retries wait 100ms, 200ms and 400ms.
+        assert_eq!(start.elapsed(), Duration::from_millis(700));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_task_publish_error_retried() {
+        assert!(sender(flaky_publisher(1)).send_task(b"task".to_vec()).await.is_ok());
+    }
+
+    /// A publisher whose publishes are never acked.
+    #[derive(Debug)]
+    struct NeverAcked;
+
+    #[async_trait]
+    impl AckPublisher for NeverAcked {
+        async fn publish_acked(&self, _subject: String, _headers: HeaderMap, _payload: Bytes) -> Result<(), PublishFailure> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_task_ack_timeout() {
+        let sender = NatsTaskSender { publisher: Arc::new(NeverAcked), ..sender(MockAckPublisher::new()) };
+
+        let start = tokio::time::Instant::now();
+        let err = sender.send_task(b"task".to_vec()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Timed out waiting for the publish ack");
+        // Both attempts time out, with a retry delay in between.
+        assert_eq!(start.elapsed(), Duration::from_millis(10_100));
+    }
+
+    #[tokio::test]
+    async fn test_send_task_single_attempt() {
+        let mut publisher = MockAckPublisher::new();
+        publisher.expect_publish_acked().times(1)
+            .returning(|_, _, _| Err(PublishError::from(PublishErrorKind::BrokenPipe).into()));
+        let sender = NatsTaskSender { max_attempts: 1, ..sender(publisher) };
+
+        assert!(sender.send_task(b"task".to_vec()).await.is_err());
     }
 }
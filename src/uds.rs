@@ -0,0 +1,134 @@
+//! This module provides the Unix domain socket listener, for sidecar deployments.
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::UnixListener;
+use tracing::log::warn;
+use crate::config::UdsConfig;
+
+
+/// The socket file of a listener, removed when dropped so no stale socket is left on shutdown.
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Error removing the socket file {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+
+/// Removes the socket file left behind by a previous run, which would make binding fail.
+/// Any other kind of file at the path is left untouched.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+
+/// Binds a Unix domain socket listener, with the configured permissions on its socket file.
+///
+/// The socket is bound inside a private directory next to the configured path, and only moved
+/// into place once its permissions are set, so it is never reachable with looser permissions.
+///
+/// # Arguments
+///
+/// * `config` - The Unix domain socket configuration.
+///
+/// # Returns
+///
+/// A `Result` containing the listener together with its socket file, which is removed when dropped.
+pub fn bind_uds(config: &UdsConfig) -> io::Result<(UnixListener, SocketFile)> {
+    remove_stale_socket(&config.path)?;
+    let private_dir = private_dir_path(&config.path)?;
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+
+    let result = bind_in(&private_dir, config);
+    if let Err(err) = fs::remove_dir_all(&private_dir) {
+        warn!("Error removing the directory {}: {}", private_dir.display(), err);
+    }
+    result
+}
+
+
+/// Returns the path of the private directory the socket at `path` is bound in.
+fn private_dir_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid socket path: {}", path.display())))?;
+    let mut dir_name = std::ffi::OsString::from(".");
+    dir_name.push(file_name);
+    dir_name.push(format!(".{}", std::process::id()));
+    Ok(path.with_file_name(dir_name))
+}
+
+
+/// Binds the socket in `private_dir`, sets its permissions, then moves it to the configured path.
+fn bind_in(private_dir: &Path, config: &UdsConfig) -> io::Result<(UnixListener, SocketFile)> {
+    let private_path = private_dir.join("socket");
+    let listener = UnixListener::bind(&private_path)?;
+    fs::set_permissions(&private_path, fs::Permissions::from_mode(config.mode))?;
+    fs::rename(&private_path, &config.path)?;
+    Ok((listener, SocketFile { path: config.path.clone() }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    fn config(name: &str) -> UdsConfig {
+        let path = std::env::temp_dir().join(format!("uds-test-{}-{}.sock", name, std::process::id()));
+        UdsConfig { path, mode: 0o660 }
+    }
+
+    #[tokio::test]
+    async fn test_serve_over_uds() {
+        let config = config("serve");
+        // A socket left behind by a previous run does not prevent binding.
+        drop(std::os::unix::net::UnixListener::bind(&config.path).unwrap());
+
+        let (listener, socket) = bind_uds(&config).unwrap();
+        assert_eq!(fs::metadata(&config.path).unwrap().permissions().mode() & 0o777, 0o660);
+        assert!(!private_dir_path(&config.path).unwrap().exists());
+
+        let app = Router::new().route("/{url_key}", get(|| async { (StatusCode::PERMANENT_REDIRECT, "redirect") }));
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut stream = UnixStream::connect(&config.path).await.unwrap();
+        stream.write_all(b"GET /12345678 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 308"), "{response}");
+        assert!(response.ends_with("redirect"), "{response}");
+
+        server.abort();
+        drop(socket);
+        assert!(!config.path.exists());
+    }
+
+    #[test]
+    fn test_bind_uds_keeps_regular_file() {
+        let config = config("regular");
+        fs::write(&config.path, "data").unwrap();
+
+        let err = bind_uds(&config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&config.path).unwrap(), "data");
+        fs::remove_file(&config.path).unwrap();
+    }
+}